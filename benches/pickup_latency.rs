@@ -0,0 +1,36 @@
+//! Measures how long a worker takes to pick up tasks submitted one at a time, with
+//! and without spinning before parking. Run with `cargo bench --bench pickup_latency`.
+
+use std::time::{Duration, Instant};
+
+use planetary::handle::Planetary;
+
+const SAMPLES: u32 = 500;
+
+fn pickup_latency(spin: Duration) -> Duration {
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .launch_on_build(true)
+        .spin_before_park(spin)
+        .build()
+        .unwrap();
+
+    let mut total = Duration::ZERO;
+
+    for _ in 0..SAMPLES {
+        let submitted = Instant::now();
+        total += pool.spawn(move || submitted.elapsed()).join().unwrap();
+
+        // leave a gap between tasks, as a trickle of latency sensitive requests would
+        std::thread::sleep(Duration::from_micros(200));
+    }
+
+    pool.shutdown();
+    total / SAMPLES
+}
+
+fn main() {
+    for spin in [Duration::ZERO, Duration::from_micros(500), Duration::from_millis(5)] {
+        println!("spin {spin:?}: mean pickup latency {:?}", pickup_latency(spin));
+    }
+}
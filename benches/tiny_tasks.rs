@@ -0,0 +1,41 @@
+//! Measures the overhead of the worker loop when running a large number of tiny tasks
+//! queued on a single worker. Run with `cargo bench --bench tiny_tasks`.
+
+use std::{sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::{Duration, Instant}};
+
+use planetary::handle::Planetary;
+
+const TASKS: usize = 1_000_000;
+
+fn run_tiny_tasks() -> Duration {
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .launch_on_build(true)
+        .build()
+        .unwrap();
+
+    let counter = Arc::new(AtomicUsize::new(0));
+    let started = Instant::now();
+
+    // spawned from the worker, so every task lands on its local queue
+    let spawner = Arc::clone(&counter);
+    pool.spawn(move || {
+        for _ in 0..TASKS {
+            let counter = Arc::clone(&spawner);
+            planetary::spawn(move || counter.fetch_add(1, Ordering::Relaxed)).detach();
+        }
+    }).join().unwrap();
+
+    while counter.load(Ordering::Relaxed) < TASKS {
+        std::thread::yield_now();
+    }
+
+    let elapsed = started.elapsed();
+    pool.shutdown();
+    elapsed
+}
+
+fn main() {
+    let elapsed = run_tiny_tasks();
+    println!("{TASKS} tiny tasks: {elapsed:?}, {:?} per task", elapsed / TASKS as u32);
+}
@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use crate::{core::Core, macros::tracing_feat};
+
+/// Configuration for the autoscaling controller, which periodically samples the
+/// threadpool and spawns or reaps workers depending on the load.
+#[derive(Debug, Clone)]
+pub struct AutoscaleConfig {
+    /// Minimum number of threads kept alive.
+    pub min_threads: usize,
+    /// Maximum number of threads the controller scales up to, capped by the
+    /// maximum number of threads of the pool.
+    pub max_threads: usize,
+    /// How often the threadpool is sampled.
+    pub interval: Duration,
+    /// Ratio of busy threads above which the pool is considered under pressure
+    /// if there are tasks waiting in the global queue, and below which the
+    /// pool is considered over provisioned if there are not.
+    pub target_utilization: f32,
+    /// Number of consecutive samples a condition must hold before scaling.
+    pub hysteresis: usize,
+}
+
+impl Default for AutoscaleConfig {
+    fn default() -> Self {
+        Self {
+            min_threads: 1,
+            max_threads: num_cpus::get(),
+            interval: Duration::from_millis(100),
+            target_utilization: 0.75,
+            hysteresis: 3,
+        }
+    }
+}
+
+/// Spawns the controller thread, which runs until the threadpool stops.
+pub(crate) fn spawn_controller(core: Core, config: AutoscaleConfig) {
+    std::thread::Builder::new()
+        .name("planetary-autoscaler".to_string())
+        .spawn(move || run_controller(core, config))
+        .unwrap_or_else(|_| panic!("Failed to spawn autoscaler thread"));
+}
+
+fn run_controller(core: Core, config: AutoscaleConfig) {
+    let mut up_streak = 0;
+    let mut down_streak = 0;
+
+    while !core.should_stop() {
+        // the limit of the threadpool can be lowered at runtime
+        let max_threads = config.max_threads.min(core.thread_limit());
+        let min_threads = config.min_threads.min(max_threads);
+        let live = core.live_threads();
+
+        if live < min_threads {
+            core.spawn_thread_with(None);
+            continue;
+        }
+
+        let backlog = core.queued_tasks();
+        let busy = live - core.idle_threads().min(live);
+        let utilization = if live == 0 { 1.0 } else { busy as f32 / live as f32 };
+
+        if backlog > 0 && utilization >= config.target_utilization {
+            up_streak += 1;
+            down_streak = 0;
+        } else if backlog == 0 && utilization < config.target_utilization {
+            down_streak += 1;
+            up_streak = 0;
+        } else {
+            up_streak = 0;
+            down_streak = 0;
+        }
+
+        if up_streak >= config.hysteresis && live < max_threads {
+            tracing_feat!(debug!("Autoscaler spawning a worker, {backlog} tasks waiting"));
+            core.spawn_thread_with(None);
+            up_streak = 0;
+        } else if down_streak >= config.hysteresis && live > min_threads {
+            // workers asked to exit before may still be alive, they count towards the minimum
+            if core.request_reap(min_threads) {
+                tracing_feat!(debug!("Autoscaler reaping a worker, utilization {utilization}"));
+            }
+            down_streak = 0;
+        }
+
+        std::thread::sleep(config.interval);
+    }
+}
@@ -0,0 +1,47 @@
+use std::time::{Duration, Instant};
+
+use crate::core::Core;
+
+/// Barrier that waits for a number of tasks to complete in a threadpool, counting
+/// from the moment it was created. See [`Planetary::barrier`].
+///
+/// [`Planetary::barrier`]: crate::handle::Planetary::barrier
+pub struct CompletionBarrier {
+    core: Core,
+    /// Completed tasks when the barrier was created
+    start: u64,
+    /// Number of completions to wait for
+    count: u64,
+}
+
+impl CompletionBarrier {
+    pub(crate) fn new(core: Core, count: usize) -> Self {
+        Self {
+            start: core.completed_tasks(),
+            core,
+            count: count as u64,
+        }
+    }
+
+    /// Number of tasks completed since the barrier was created.
+    pub fn completed(&self) -> usize {
+        self.core.completed_tasks().wrapping_sub(self.start) as usize
+    }
+
+    /// Whether the expected number of tasks already completed.
+    pub fn is_done(&self) -> bool {
+        self.core.completed_tasks().wrapping_sub(self.start) >= self.count
+    }
+
+    /// Blocks until the expected number of tasks complete. If fewer tasks ever run,
+    /// this blocks forever, see [`CompletionBarrier::wait_timeout`] for a bounded wait.
+    pub fn wait(&self) {
+        self.core.wait_completions(None, || self.is_done());
+    }
+
+    /// Blocks until the expected number of tasks complete or the timeout elapses,
+    /// returning whether the tasks completed.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        self.core.wait_completions(Some(Instant::now() + timeout), || self.is_done())
+    }
+}
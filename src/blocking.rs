@@ -0,0 +1,205 @@
+//! Secondary pool running the tasks spawned with [`Planetary::spawn_blocking`] on their
+//! own threads, so they don't take workers away from the rest of the tasks.
+//!
+//! [`Planetary::spawn_blocking`]: crate::handle::Planetary::spawn_blocking
+
+use std::{cell::Cell, collections::VecDeque, sync::{Condvar, Mutex, MutexGuard}, thread::JoinHandle, time::Duration};
+
+use crate::{builder::BlockingQueuePolicy, core::Core, macros::tracing_feat, task::TypeErasedTask};
+
+thread_local! {
+    /// Blocking pool the current thread belongs to, null outside blocking threads.
+    static CURRENT_POOL: Cell<*const BlockingPool> = const { Cell::new(std::ptr::null()) };
+}
+
+struct BlockingState {
+    /// Tasks waiting for a blocking thread
+    queue: VecDeque<TypeErasedTask>,
+    /// Number of blocking threads alive
+    threads: usize,
+    /// Handles of the blocking threads spawned, joined on shutdown
+    handles: Vec<JoinHandle<()>>,
+    /// Number of blocking threads waiting for a task
+    idle: usize,
+    /// Whether the threadpool is shutting down
+    stopped: bool,
+}
+
+pub(crate) struct BlockingPool {
+    state: Mutex<BlockingState>,
+    /// Notified when a task is queued or the threadpool shuts down
+    task_cv: Condvar,
+    /// Notified when a queued task is taken, making room in the queue
+    room_cv: Condvar,
+    /// Maximum number of blocking threads
+    max_threads: usize,
+    /// Behavior when every blocking thread is busy
+    policy: BlockingQueuePolicy,
+    /// How long blocking threads wait for a task before exiting
+    timeout: Duration,
+}
+
+impl BlockingPool {
+    pub fn new(max_threads: usize, policy: BlockingQueuePolicy, timeout: Duration) -> Self {
+        Self {
+            state: Mutex::new(BlockingState {
+                queue: VecDeque::new(),
+                threads: 0,
+                handles: Vec::new(),
+                idle: 0,
+                stopped: false,
+            }),
+            task_cv: Condvar::new(),
+            room_cv: Condvar::new(),
+            max_threads,
+            policy,
+            timeout,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BlockingState> {
+        self.state.lock().unwrap_or_else(|s| s.into_inner())
+    }
+
+    /// Number of blocking threads alive.
+    pub fn threads(&self) -> usize {
+        self.lock().threads
+    }
+
+    /// Hands the task to an idle blocking thread or a new one, otherwise queues it or
+    /// runs it on the caller, depending on the policy.
+    pub fn spawn(&self, core: &Core, task: TypeErasedTask) {
+        core.accept(&task);
+        let mut state = self.lock();
+
+        loop {
+            if state.stopped {
+                drop(state);
+                task.cancel();
+                return;
+            }
+
+            // an idle thread will take it
+            if state.idle > state.queue.len() {
+                state.queue.push_back(task);
+                self.task_cv.notify_one();
+                return;
+            }
+
+            if state.threads < self.max_threads {
+                // spawned under the lock, so a shutdown can't miss its handle
+                self.spawn_thread(&mut state, core, task);
+                return;
+            }
+
+            match self.policy {
+                BlockingQueuePolicy::CallerRuns => {
+                    drop(state);
+                    tracing_feat!(trace!("Blocking pool saturated, running task on the caller"));
+                    core.execute_task(task);
+                    return;
+                }
+                BlockingQueuePolicy::Queue(capacity) if state.queue.len() < capacity => {
+                    state.queue.push_back(task);
+                    return;
+                }
+                // waiting for room from a blocking thread could wait for itself forever
+                BlockingQueuePolicy::Queue(_) if self.is_current() => {
+                    drop(state);
+                    tracing_feat!(trace!("Blocking queue full, running task on the blocking thread spawning it"));
+                    core.execute_task(task);
+                    return;
+                }
+                BlockingQueuePolicy::Queue(_) => {
+                    state = self.room_cv.wait(state).unwrap_or_else(|s| s.into_inner());
+                }
+            }
+        }
+    }
+
+    /// Whether the current thread is a blocking thread of this pool.
+    fn is_current(&self) -> bool {
+        CURRENT_POOL.with(|pool| std::ptr::eq(pool.get(), self))
+    }
+
+    fn spawn_thread(&self, state: &mut BlockingState, core: &Core, task: TypeErasedTask) {
+        let thread_core = core.clone();
+
+        let handle = std::thread::Builder::new()
+            .name("planetary-blocking".to_string())
+            .spawn(move || {
+                crate::handle::sealed::set_handle(thread_core.clone());
+                CURRENT_POOL.with(|pool| pool.set(&thread_core.blocking));
+                thread_core.blocking.run_thread(&thread_core, task);
+            })
+            .unwrap_or_else(|_| panic!("Failed to spawn blocking thread"));
+
+        state.threads += 1;
+        state.handles.retain(|handle| !handle.is_finished());
+        state.handles.push(handle);
+    }
+
+    /// Runs the given task and then the queued ones, until no task comes for the
+    /// configured timeout or the threadpool shuts down.
+    fn run_thread(&self, core: &Core, task: TypeErasedTask) {
+        let mut next = Some(task);
+
+        while let Some(task) = next.take() {
+            core.execute_task(task);
+
+            let mut state = self.lock();
+
+            loop {
+                if let Some(task) = state.queue.pop_front() {
+                    self.room_cv.notify_one();
+                    next = Some(task);
+                    break;
+                }
+
+                if state.stopped {
+                    break;
+                }
+
+                state.idle += 1;
+                let (guard, result) = self.task_cv.wait_timeout(state, self.timeout)
+                    .unwrap_or_else(|s| s.into_inner());
+                state = guard;
+                state.idle -= 1;
+
+                if result.timed_out() && state.queue.is_empty() {
+                    break;
+                }
+            }
+
+            if next.is_none() {
+                state.threads -= 1;
+            }
+        }
+    }
+
+    /// Cancels the queued tasks and makes the blocking threads exit once they finish
+    /// the task they are running.
+    pub fn shutdown(&self) {
+        let queued = {
+            let mut state = self.lock();
+            state.stopped = true;
+            std::mem::take(&mut state.queue)
+        };
+
+        self.task_cv.notify_all();
+        self.room_cv.notify_all();
+        queued.into_iter().for_each(TypeErasedTask::cancel);
+    }
+
+    /// Waits for the blocking threads to exit, except the calling one, after a shutdown.
+    pub fn join_threads(&self) {
+        let handles = std::mem::take(&mut self.lock().handles);
+        let current = std::thread::current().id();
+
+        for handle in handles {
+            if handle.thread().id() != current {
+                let _ = handle.join();
+            }
+        }
+    }
+}
@@ -1,6 +1,101 @@
-use std::{io, time::Duration};
+use std::{any::Any, io, sync::Arc, time::Duration};
 
-use crate::{core::Core, handle::Planetary, hooks::Hooks};
+use crate::{autoscale::AutoscaleConfig, core::Core, handle::Planetary, hooks::Hooks, metrics::{CumulativeStats, FinalMetrics, MetricsSink, SinkConfig}, steal::{Random, StealStrategy}, task::{IntoRunnable, ResultSink, Task, TypeErasedTask}, testing::TestPool, JoinResult};
+
+/// Where spawned tasks are placed when spawning from a worker thread.
+/// Tasks spawned outside the threadpool always go to the global injector.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnPolicy {
+    /// Push the task into the local queue of the current worker, other workers
+    /// can still steal it.
+    #[default]
+    LocalFirst,
+    /// Push the task into the global injector, so any worker can pick it up
+    /// without stealing.
+    GlobalFanout,
+}
+
+/// Order in which workers run the tasks of their local queue.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum QueueOrder {
+    /// Run the oldest task first, which is fair to tasks spawned earlier.
+    #[default]
+    Fifo,
+    /// Run the most recently spawned task first, which keeps the data of recursive
+    /// workloads hot in the cache. Other workers still steal the oldest tasks, so a
+    /// task spawned early may wait for a long time if nobody steals it.
+    Lifo,
+}
+
+/// Where a worker places the tasks it spawns itself in its local queue, see
+/// [`PlanetaryBuilder::child_spawn_order`].
+///
+/// The local queue only holds the tasks its worker spawned, so this is the same setting as
+/// [`QueueOrder`] described by its effect on recursive workloads: other workers always steal
+/// the oldest tasks, whichever end the worker runs first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChildSpawnOrder {
+    /// Place children at the back, so the worker runs the whole level of a recursive
+    /// fan-out before going deeper, which leaves the most tasks around for stealing.
+    /// Same as [`QueueOrder::Fifo`].
+    Breadth,
+    /// Place children at the front, so the worker runs the latest one next and goes deep
+    /// first, like Cilk. The local queue of a recursive fan-out only grows with its depth,
+    /// while thieves take the oldest tasks, which have the most work under them.
+    /// Same as [`QueueOrder::Lifo`].
+    Depth,
+}
+
+/// What to do with tasks that would be queued in the global injector while every worker
+/// is busy and no more threads can be spawned.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SaturationPolicy {
+    /// Queue the task until a worker is free.
+    #[default]
+    Queue,
+    /// Run the task on the spawning thread before the spawn returns.
+    CallerRuns,
+    /// Cancel the task, see [`Planetary::try_spawn`] to get the runnable back instead.
+    Reject,
+}
+
+/// What to do with tasks spawned with [`Planetary::spawn_blocking`] while every blocking
+/// thread is busy and no more can be spawned.
+///
+/// [`Planetary::spawn_blocking`]: crate::handle::Planetary::spawn_blocking
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockingQueuePolicy {
+    /// Queue the task until a blocking thread is free, making the spawning thread wait
+    /// while the given number of tasks are already queued.
+    Queue(usize),
+    /// Run the task on the spawning thread before the spawn returns.
+    CallerRuns,
+}
+
+impl Default for BlockingQueuePolicy {
+    fn default() -> Self {
+        Self::Queue(usize::MAX)
+    }
+}
+
+/// Configuration a `Planetary` instance was built with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// Maximum number of threads that can be spawned.
+    pub max_threads: usize,
+    /// Stack size for the threads.
+    pub stack_size: Option<usize>,
+    /// Timeout for the worker threads while not doing any work.
+    pub timeout: Duration,
+    /// Whether all the threads were launched when the threadpool was built.
+    pub launch_on_build: bool,
+    /// Maximum number of tasks that can be running at the same time.
+    pub max_concurrent_tasks: Option<usize>,
+    /// Default placement of tasks spawned from worker threads.
+    pub spawn_policy: SpawnPolicy,
+    /// Behavior when spawning tasks while the threadpool is saturated.
+    pub saturation_policy: SaturationPolicy,
+}
 
 /// Builder for a `Planetary` instance.
 pub struct PlanetaryBuilder {
@@ -14,6 +109,61 @@ pub struct PlanetaryBuilder {
     pub(crate) timeout: Duration,
     /// Whether to launch all the threads when the threadpool is built
     pub(crate) launch_on_build: bool,
+    /// Maximum number of tasks that can be running at the same time.
+    pub(crate) max_concurrent_tasks: Option<usize>,
+    /// Configuration of the autoscaling controller, if enabled.
+    pub(crate) autoscale: Option<AutoscaleConfig>,
+    /// Default placement of tasks spawned from worker threads.
+    pub(crate) spawn_policy: SpawnPolicy,
+    /// Behavior when spawning tasks while the threadpool is saturated.
+    pub(crate) saturation_policy: SaturationPolicy,
+    /// Number of tasks a worker runs between checks of the global injector.
+    pub(crate) injector_interval: u32,
+    /// How long idle workers keep looking for tasks before parking.
+    pub(crate) spin_before_park: Duration,
+    /// Whether idle workers steal tasks from the local queues of other workers.
+    pub(crate) work_stealing: bool,
+    /// Order in which workers run the tasks of their local queue.
+    pub(crate) local_queue_order: QueueOrder,
+    /// Whether tasks that would start a new thread run on the spawning thread instead.
+    pub(crate) eager_caller_run: bool,
+    /// Number of workers that only steal tasks from the rest.
+    pub(crate) dedicated_stealers: usize,
+    /// Whether workers blocked joining tasks nothing can run panic instead of hanging.
+    pub(crate) detect_deadlocks: bool,
+    /// Maximum number of threads running blocking tasks.
+    pub(crate) max_blocking_threads: usize,
+    /// Behavior when spawning blocking tasks while every blocking thread is busy.
+    pub(crate) blocking_queue_policy: BlockingQueuePolicy,
+    /// Timeout for the blocking threads while not doing any work.
+    pub(crate) blocking_timeout: Duration,
+    /// Minimum interval between the creation of threads spawned on demand, if any.
+    pub(crate) thread_spawn_rate: Option<Duration>,
+    /// How long low priority tasks wait before being treated as high priority ones.
+    pub(crate) starvation_threshold: Duration,
+    /// Sink receiving periodic metrics snapshots, if any.
+    pub(crate) metrics_sink: Option<SinkConfig>,
+    /// Receives the final statistics once the threadpool shut down, if any.
+    pub(crate) final_metrics: Option<FinalMetrics>,
+    /// Whether to record the queue latency of the tasks.
+    pub(crate) track_latency: bool,
+    /// Receives the outputs of detached tasks, if any.
+    pub(crate) result_sink: Option<ResultSink>,
+    /// Whether the threadpool is driven manually instead of by worker threads.
+    pub(crate) test_mode: bool,
+    /// Tasks queued into the threadpool when it's built.
+    pub(crate) initial_tasks: Vec<TypeErasedTask>,
+    /// Chooses the workers to steal tasks from.
+    pub(crate) steal_strategy: Box<dyn StealStrategy + Send + Sync>,
+    /// Minimum stack a worker must have left to start a task, if any.
+    #[cfg(feature = "stack-guard")]
+    pub(crate) task_stack_guard: Option<usize>,
+}
+
+impl Default for PlanetaryBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PlanetaryBuilder {
@@ -23,7 +173,32 @@ impl PlanetaryBuilder {
             max_threads: num_cpus::get(),
             stack_size: None,
             timeout: Duration::from_secs(15),
-            launch_on_build: false
+            launch_on_build: false,
+            max_concurrent_tasks: None,
+            autoscale: None,
+            spawn_policy: SpawnPolicy::default(),
+            saturation_policy: SaturationPolicy::default(),
+            injector_interval: 61,
+            spin_before_park: Duration::ZERO,
+            work_stealing: true,
+            local_queue_order: QueueOrder::default(),
+            eager_caller_run: false,
+            dedicated_stealers: 0,
+            detect_deadlocks: false,
+            max_blocking_threads: 512,
+            blocking_queue_policy: BlockingQueuePolicy::default(),
+            blocking_timeout: Duration::from_secs(10),
+            thread_spawn_rate: None,
+            starvation_threshold: Duration::from_millis(100),
+            metrics_sink: None,
+            final_metrics: None,
+            track_latency: false,
+            result_sink: None,
+            test_mode: false,
+            initial_tasks: Vec::new(),
+            steal_strategy: Box::new(Random),
+            #[cfg(feature = "stack-guard")]
+            task_stack_guard: None
         }
     }
 
@@ -51,6 +226,260 @@ impl PlanetaryBuilder {
         self
     }
 
+    /// Sets the maximum number of tasks that can be running at the same time across the
+    /// whole threadpool, independently of the number of threads. Workers wait for a running
+    /// task to finish before starting a new one once the limit is reached.
+    ///
+    /// Tasks blocked joining other tasks give their permit back until they resume, and tasks
+    /// run by a thread while it runs another one, like inline or helped tasks, share its permit.
+    pub fn max_concurrent_tasks(&mut self, tasks: usize) -> &mut Self {
+        self.max_concurrent_tasks = Some(tasks);
+        self
+    }
+
+    /// Enables the autoscaling controller, a background thread that spawns and reaps
+    /// workers depending on the queued tasks and the ratio of idle workers. While enabled,
+    /// spawning a task only creates new threads to keep the configured minimum alive.
+    pub fn autoscale(&mut self, config: AutoscaleConfig) -> &mut Self {
+        self.autoscale = Some(config);
+        self
+    }
+
+    /// Sets where tasks spawned from worker threads are placed by default, it can be
+    /// overridden for a single task with [`Planetary::spawn_with_policy`].
+    pub fn spawn_policy(&mut self, policy: SpawnPolicy) -> &mut Self {
+        self.spawn_policy = policy;
+        self
+    }
+
+    /// Sets what to do with tasks that would be queued while every worker is busy and
+    /// no more threads can be spawned, defaults to [`SaturationPolicy::Queue`]. Tasks
+    /// pushed into the local queue of the spawning worker are not affected.
+    pub fn saturation_policy(&mut self, policy: SaturationPolicy) -> &mut Self {
+        self.saturation_policy = policy;
+        self
+    }
+
+    /// Sets how many tasks a worker executes before taking a task from the global injector
+    /// ahead of its own queue, so tasks spawned from outside the threadpool aren't starved
+    /// by workers that keep spawning tasks into their local queues. Defaults to 61, zero
+    /// makes workers only check the injector once their own queue is empty.
+    pub fn injector_interval(&mut self, tasks: u32) -> &mut Self {
+        self.injector_interval = tasks;
+        self
+    }
+
+    /// Sets how long a worker that ran out of tasks keeps busy looking for new ones before
+    /// parking. Spinning reduces the latency of picking up tasks spawned shortly after at
+    /// the cost of CPU time. Defaults to zero, which parks right away.
+    ///
+    /// While spinning the worker keeps trying to steal from the rest, but a spin that ends
+    /// up parking counts as a single failed steal, both in
+    /// [`CumulativeStats::steals_failed`](crate::metrics::CumulativeStats::steals_failed)
+    /// and for the `on_steal_fail` hook.
+    pub fn spin_before_park(&mut self, duration: Duration) -> &mut Self {
+        self.spin_before_park = duration;
+        self
+    }
+
+    /// Spawns a sampler thread that passes a fresh [`Metrics`] snapshot to `sink`
+    /// every `interval`, until the threadpool is shut down.
+    ///
+    /// [`Metrics`]: crate::metrics::Metrics
+    pub fn metrics_sink(&mut self, sink: impl MetricsSink + Send + Sync + 'static, interval: Duration) -> &mut Self {
+        self.metrics_sink = Some(SinkConfig {
+            sink: Box::new(sink),
+            interval
+        });
+        self
+    }
+
+    /// Sets a callback receiving the [`CumulativeStats`] of the whole life of the threadpool
+    /// once it shut down and every worker exited, so batch jobs can log a summary. It runs
+    /// once, on the last worker to exit, or on the thread shutting down the threadpool if
+    /// there are no workers left.
+    ///
+    /// The stats are the ones since the last [`Planetary::reset_stats`], if it was called.
+    pub fn final_metrics(&mut self, callback: impl FnOnce(CumulativeStats) + Send + 'static) -> &mut Self {
+        self.final_metrics = Some(Box::new(callback));
+        self
+    }
+
+    /// Sets whether to record how long each task waits between being spawned and starting
+    /// to run, see [`Planetary::latency_percentiles`]. Disabled by default.
+    pub fn track_latency(&mut self, track: bool) -> &mut Self {
+        self.track_latency = track;
+        self
+    }
+
+    /// Sets a function that receives the result of every task whose [`JoinHandle`] was
+    /// dropped or detached before taking its output, including panics, which would
+    /// otherwise be silently dropped. Successful outputs are boxed as [`Any`].
+    ///
+    /// The function runs on the thread that frees the task, usually a worker.
+    ///
+    /// [`JoinHandle`]: crate::join::JoinHandle
+    pub fn result_sink(&mut self, sink: impl Fn(JoinResult<Box<dyn Any + Send>>) + Send + Sync + 'static) -> &mut Self {
+        self.result_sink = Some(Arc::new(sink));
+        self
+    }
+
+    /// Sets whether idle workers steal tasks from the local queues of other workers, enabled
+    /// by default. When disabled, tasks pushed into the local queue of a worker are only run
+    /// by that worker, and the rest only share the tasks in the global injector.
+    pub fn work_stealing(&mut self, enabled: bool) -> &mut Self {
+        self.work_stealing = enabled;
+        self
+    }
+
+    /// Sets how many workers are dedicated stealers, none by default. Dedicated stealers
+    /// have no local queue of their own: tasks spawned from them go to the global injector,
+    /// they can't be targeted by [`Planetary::spawn_on`], and they only take tasks from the
+    /// global injector and the local queues of the rest, draining the backlog of workers
+    /// that accumulate deep queues. Workers become dedicated stealers as they are spawned
+    /// without a task while there are less of them than configured.
+    ///
+    /// [`Planetary::spawn_on`]: crate::handle::Planetary::spawn_on
+    pub fn dedicated_stealers(&mut self, stealers: usize) -> &mut Self {
+        self.dedicated_stealers = stealers;
+        self
+    }
+
+    /// Sets whether to detect workers deadlocking in [`JoinHandle::join`], disabled by default.
+    ///
+    /// When enabled, a worker about to block joining a task that didn't start panics with
+    /// a descriptive message if every worker is blocked joining and no more can be spawned,
+    /// like a task joining another one on a single threaded pool, instead of hanging.
+    ///
+    /// Meant for debugging, as the check only knows about the workers of this threadpool
+    /// and may report false positives: joining a task that something else will start,
+    /// like a blocking task, a future waiting to be woken or a task of another threadpool,
+    /// panics as well once every worker is blocked, even if it would finish eventually.
+    ///
+    /// [`JoinHandle::join`]: crate::join::JoinHandle::join
+    pub fn detect_deadlocks(&mut self, detect: bool) -> &mut Self {
+        self.detect_deadlocks = detect;
+        self
+    }
+
+    /// Sets the maximum number of threads running tasks spawned with
+    /// [`Planetary::spawn_blocking`], 512 by default.
+    ///
+    /// Panics if `threads` is zero, as blocking tasks would never run.
+    ///
+    /// [`Planetary::spawn_blocking`]: crate::handle::Planetary::spawn_blocking
+    pub fn max_blocking_threads(&mut self, threads: usize) -> &mut Self {
+        assert!(threads > 0, "max_blocking_threads must be at least 1");
+        self.max_blocking_threads = threads;
+        self
+    }
+
+    /// Sets what to do with blocking tasks spawned while every blocking thread is busy,
+    /// they are queued without bound by default.
+    pub fn blocking_queue_policy(&mut self, policy: BlockingQueuePolicy) -> &mut Self {
+        self.blocking_queue_policy = policy;
+        self
+    }
+
+    /// Sets the timeout for blocking threads without work, after which they exit.
+    pub fn blocking_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.blocking_timeout = timeout;
+        self
+    }
+
+    /// Sets the minimum interval between the creation of threads spawned on demand, so a
+    /// burst of spawns doesn't create every thread at once. Tasks spawned while throttled
+    /// are queued for the existing threads instead. Disabled by default.
+    pub fn thread_spawn_rate(&mut self, interval: Option<Duration>) -> &mut Self {
+        self.thread_spawn_rate = interval;
+        self
+    }
+
+    /// Sets whether a task spawned from outside the threadpool that would be handed to a
+    /// newly spawned thread runs on the spawning thread instead, disabled by default. The
+    /// thread is still spawned, but without a task, so the first task doesn't wait for
+    /// the thread to start while the thread is ready for the next ones. The spawn blocks
+    /// until the task finishes.
+    pub fn eager_caller_run(&mut self, enabled: bool) -> &mut Self {
+        self.eager_caller_run = enabled;
+        self
+    }
+
+    /// Sets the order in which workers run the tasks of their local queue, defaults to
+    /// [`QueueOrder::Fifo`]. See [`QueueOrder`] for the tradeoffs.
+    pub fn local_queue_order(&mut self, order: QueueOrder) -> &mut Self {
+        self.local_queue_order = order;
+        self
+    }
+
+    /// Sets where workers place the tasks they spawn themselves, defaults to
+    /// [`ChildSpawnOrder::Breadth`]. See [`ChildSpawnOrder`] for the tradeoffs.
+    ///
+    /// This sets the [`PlanetaryBuilder::local_queue_order`] matching the order, so
+    /// whichever of both is called last wins.
+    pub fn child_spawn_order(&mut self, order: ChildSpawnOrder) -> &mut Self {
+        self.local_queue_order = match order {
+            ChildSpawnOrder::Breadth => QueueOrder::Fifo,
+            ChildSpawnOrder::Depth => QueueOrder::Lifo,
+        };
+        self
+    }
+
+    /// Sets how long a task spawned with [`Priority::Low`] can wait before workers take it
+    /// ahead of high priority tasks, so a steady stream of those can't starve it. Defaults
+    /// to 100 milliseconds.
+    ///
+    /// [`Priority::Low`]: crate::handle::Priority::Low
+    pub fn starvation_threshold(&mut self, threshold: Duration) -> &mut Self {
+        self.starvation_threshold = threshold;
+        self
+    }
+
+    /// Sets how idle workers choose which worker to steal tasks from, defaults to
+    /// [`Random`].
+    pub fn steal_strategy(&mut self, strategy: impl StealStrategy + Send + Sync + 'static) -> &mut Self {
+        self.steal_strategy = Box::new(strategy);
+        self
+    }
+
+    /// Sets the minimum number of bytes of stack a worker must have left to start a task.
+    /// Tasks that would start below it, e.g. when executed from nested calls, are not run
+    /// and finish with [`JoinError::StackExhausted`] instead of overflowing the stack.
+    ///
+    /// The remaining stack is estimated from the stack size of the workers, see
+    /// [`PlanetaryBuilder::stack_size`], and is only probed on platforms whose stack grows
+    /// downwards, on the rest tasks always run.
+    ///
+    /// The stack is only checked when a task starts, so a running task recursing deeply
+    /// without going through the threadpool can still overflow it.
+    ///
+    /// [`JoinError::StackExhausted`]: crate::join::JoinError::StackExhausted
+    #[cfg(feature = "stack-guard")]
+    pub fn task_stack_guard(&mut self, bytes: usize) -> &mut Self {
+        self.task_stack_guard = Some(bytes);
+        self
+    }
+
+    /// Names the worker threads as `{prefix}-{id}`, so a worker keeps the same name
+    /// when its id is reused by a respawned thread.
+    pub fn name_prefix(&mut self, prefix: impl Into<String>) -> &mut Self {
+        let prefix = prefix.into();
+        self.hooks.set_name_with_id_fn(move |id| format!("{prefix}-{id}"));
+        self
+    }
+
+    /// Queues the given tasks into the threadpool when it's built, before the handle is
+    /// returned, so it starts with work to do. Their outputs go to the
+    /// [result sink](PlanetaryBuilder::result_sink), if any.
+    pub fn with_initial_tasks<M, I>(&mut self, tasks: I) -> &mut Self
+    where
+        I: IntoIterator,
+        I::Item: IntoRunnable<M>
+    {
+        self.initial_tasks.extend(tasks.into_iter().map(|task| Task::new(task).erase()));
+        self
+    }
+
     /// Sets the hooks to be executed from the threadpool.
     pub fn with_hooks(&mut self, fun: impl FnOnce(&mut Hooks)) -> &mut Self {
         fun(&mut self.hooks);
@@ -58,10 +487,28 @@ impl PlanetaryBuilder {
         self
     }
 
+    /// Builds a [`TestPool`], which never spawns worker threads and runs its tasks on the
+    /// thread driving it instead. The autoscaler and metrics sink are not started.
+    pub fn test_mode(&mut self) -> TestPool {
+        self.test_mode = true;
+        self.autoscale = None;
+        self.metrics_sink = None;
+        let initial_tasks = std::mem::take(&mut self.initial_tasks);
+        let pool_core = Core::new(std::mem::take(self));
+
+        pool_core.inject_initial(initial_tasks);
+        crate::handle::sealed::set_handle(pool_core.clone());
+
+        TestPool::new(Planetary::new(pool_core))
+    }
+
     pub fn build(&mut self) -> io::Result<Planetary> {
         let launch = self.launch_on_build;
         let threads = self.max_threads;
-        let pool_core = Core::new(std::mem::replace(self, Self::new()));
+        let autoscale = self.autoscale.clone();
+        let metrics_sink = self.metrics_sink.take();
+        let initial_tasks = std::mem::take(&mut self.initial_tasks);
+        let pool_core = Core::new(std::mem::take(self));
 
         if launch {
             for _ in 0..threads {
@@ -69,13 +516,19 @@ impl PlanetaryBuilder {
             }
         }
 
-        let planetary = Planetary {
-            inner: pool_core
-        };
+        pool_core.inject_initial(initial_tasks);
+
+        if let Some(config) = autoscale {
+            crate::autoscale::spawn_controller(pool_core.clone(), config);
+        }
+
+        if let Some(config) = metrics_sink {
+            crate::metrics::spawn_sampler(pool_core.clone(), config);
+        }
 
-        crate::handle::sealed::set_handle(planetary.clone());
+        crate::handle::sealed::set_handle(pool_core.clone());
 
-        Ok(planetary)
+        Ok(Planetary::new(pool_core))
     }
 }
 
@@ -0,0 +1,100 @@
+use std::{future::Future, pin::Pin, sync::{Arc, Mutex}, task::{Context, Poll, Wake, Waker}};
+
+use crate::{core::Core, join::{AbortHandle, JoinError, JoinHandle}, task::{Task, TypeErasedTask}};
+
+/// Links a task to the one chained to it with [`JoinHandle::then`], spawning the
+/// continuation once the task finishes, or aborting it if the task failed.
+struct Link<T> {
+    core: Core,
+    /// The task and its continuation, taken by whoever advances the link
+    state: Mutex<Option<(JoinHandle<T>, TypeErasedTask)>>,
+    /// Where the output of the task is left for the continuation
+    output: Arc<Mutex<Option<T>>>,
+    upstream: AbortHandle,
+}
+
+impl<T: Send + 'static> Link<T> {
+    fn take_state(&self) -> Option<(JoinHandle<T>, TypeErasedTask)> {
+        self.state.lock().unwrap_or_else(|s| s.into_inner()).take()
+    }
+
+    /// Polls the task, handing its output to the continuation once it finishes.
+    fn advance(self: Arc<Self>) {
+        let waker = Waker::from(Arc::clone(&self));
+
+        // the task may finish between polling it and putting it back, in which case
+        // its wakeup finds nothing to advance, so check again after putting it back
+        loop {
+            let Some((mut upstream, next)) = self.take_state() else {
+                return;
+            };
+
+            let output = match Pin::new(&mut upstream).poll(&mut Context::from_waker(&waker)) {
+                Poll::Ready(output) => output,
+                Poll::Pending => {
+                    *self.state.lock().unwrap_or_else(|s| s.into_inner()) = Some((upstream, next));
+
+                    if self.upstream.is_finished() {
+                        continue;
+                    }
+
+                    return;
+                },
+            };
+
+            match output {
+                // the continuation was aborted meanwhile, and dropping it is enough
+                _ if next.header().state.get(crate::task::state::State::ABORTED) => drop(next),
+                Ok(output) => {
+                    *self.output.lock().unwrap_or_else(|s| s.into_inner()) = Some(output);
+                    self.core.spawn_task(next);
+                },
+                Err(_) => next.abort_with(JoinError::Aborted),
+            }
+
+            return;
+        }
+    }
+}
+
+impl<T: Send + 'static> Wake for Link<T> {
+    fn wake(self: Arc<Self>) {
+        self.advance();
+    }
+}
+
+pub(crate) fn then<T, U, F>(core: Core, upstream: JoinHandle<T>, f: F) -> JoinHandle<U>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+    F: FnOnce(T) -> U + Send + 'static
+{
+    let output = Arc::new(Mutex::new(None));
+
+    let next = Task::new({
+        let output = Arc::clone(&output);
+
+        move || {
+            let output = output.lock()
+                .unwrap_or_else(|s| s.into_inner())
+                .take()
+                .expect("Continuation ran before the task it's chained to finished");
+
+            f(output)
+        }
+    }).erase();
+
+    let _ = next.header().upstream.set(upstream.abort_handle());
+    core.attach_result_sink(next.header());
+    let handle = JoinHandle::new(next.header);
+
+    let link = Arc::new(Link {
+        core,
+        upstream: upstream.abort_handle(),
+        state: Mutex::new(Some((upstream, next))),
+        output,
+    });
+
+    link.advance();
+    handle
+}
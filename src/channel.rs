@@ -0,0 +1,96 @@
+//! Bounded channel receiving the outputs of tasks, see [`Planetary::spawn_to_result_channel`].
+//!
+//! [`Planetary::spawn_to_result_channel`]: crate::handle::Planetary::spawn_to_result_channel
+
+use std::time::Duration;
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+
+use crate::task::Runnable;
+
+/// Fixed-capacity channel the tasks spawned with [`Planetary::spawn_to_result_channel`]
+/// push their outputs into once they complete.
+///
+/// While the channel is full, the worker completing a task blocks until the consumer makes
+/// room, so a slow consumer throttles the threadpool instead of letting the results pile
+/// up. The consumer must not run on the workers of that threadpool, as they may all end up
+/// blocked. Once the channel is dropped, the outputs of the tasks still pending are dropped.
+///
+/// [`Planetary::spawn_to_result_channel`]: crate::handle::Planetary::spawn_to_result_channel
+pub struct ResultChannel<T> {
+    sender: Sender<T>,
+    receiver: Receiver<T>,
+}
+
+impl<T> ResultChannel<T> {
+    /// Creates a channel holding up to `capacity` outputs.
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "result channel capacity must be greater than zero");
+        let (sender, receiver) = crossbeam_channel::bounded(capacity);
+
+        Self { sender, receiver }
+    }
+
+    /// Blocks until an output is available and takes it. If no task ever pushes into
+    /// the channel, this blocks forever.
+    pub fn recv(&self) -> T {
+        self.receiver.recv().expect("the channel holds a sender")
+    }
+
+    /// Blocks until an output is available or the timeout elapses.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<T> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(output) => Some(output),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => unreachable!("the channel holds a sender"),
+        }
+    }
+
+    /// Takes an output if there's one available.
+    pub fn try_recv(&self) -> Option<T> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Number of outputs waiting in the channel.
+    pub fn len(&self) -> usize {
+        self.receiver.len()
+    }
+
+    /// Whether no output is waiting in the channel.
+    pub fn is_empty(&self) -> bool {
+        self.receiver.is_empty()
+    }
+
+    /// Maximum number of outputs the channel holds.
+    pub fn capacity(&self) -> usize {
+        self.receiver.capacity().expect("the channel is bounded")
+    }
+
+    pub(crate) fn sender(&self) -> Sender<T> {
+        self.sender.clone()
+    }
+}
+
+/// Runs the wrapped runnable and pushes its output into a [`ResultChannel`].
+pub(crate) struct ToChannel<T: Runnable> {
+    runnable: T,
+    sender: Sender<T::Output>,
+}
+
+impl<T: Runnable> ToChannel<T> {
+    pub(crate) fn new(runnable: T, sender: Sender<T::Output>) -> Self {
+        Self { runnable, sender }
+    }
+}
+
+impl<T: Runnable> Runnable for ToChannel<T> {
+    type Output = ();
+
+    fn run(self) -> Self::Output {
+        let output = self.runnable.run();
+        // fails only if the channel was dropped, nobody wants the output then
+        let _ = self.sender.send(output);
+    }
+}
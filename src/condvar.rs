@@ -15,27 +15,64 @@ impl Cv {
     }
 
     /// Wait on the condvar, returns if the condvar timed out
+    #[allow(unused)]
     pub fn wait_timeout(&self, timeout: Duration) -> bool {
         let _guard = self.mutex.lock().unwrap();
-        let t = match self.condvar.wait_timeout(_guard, timeout) {
+        match self.condvar.wait_timeout(_guard, timeout) {
             Err(e) => e.into_inner().1.timed_out(),
             Ok((_, res)) => res.timed_out()
-        };
-        t
+        }
     }
 
-    pub fn wait_no_timeout(&self) {
-        let _guard = self.mutex.lock().unwrap();
-        drop(self.condvar.wait(_guard));
+    /// Like [`Cv::wait_timeout`], but returns right away without timing out if `ready`
+    /// holds once the lock is taken. Paired with [`Cv::notify_all_locked`], this can't
+    /// miss a notification sent after the condition was made true.
+    pub fn wait_timeout_unless(&self, timeout: Duration, ready: impl FnOnce() -> bool) -> bool {
+        let guard = self.mutex.lock().unwrap();
+
+        if ready() {
+            return false;
+        }
+
+        match self.condvar.wait_timeout(guard, timeout) {
+            Err(e) => e.into_inner().1.timed_out(),
+            Ok((_, res)) => res.timed_out()
+        }
+    }
+
+    /// Waits on the condvar until `ready` holds, checking it under the lock. Paired with
+    /// [`Cv::notify_all_locked`], this can't miss a notification.
+    pub fn wait_until(&self, ready: impl Fn() -> bool) {
+        let mut guard = self.mutex.lock().unwrap_or_else(|e| e.into_inner());
+
+        while !ready() {
+            guard = self.condvar.wait(guard).unwrap_or_else(|e| e.into_inner());
+        }
     }
 
     /// Notify a single thread waiting on the condvar
+    #[allow(unused)]
     pub fn notify_one(&self) {
         self.condvar.notify_one();
     }
 
     /// Notify all threads waiting on the condvar
+    #[allow(unused)]
     pub fn notify_all(&self) {
         self.condvar.notify_all();
     }
+
+    /// Notify a single thread waiting on the condvar, taking the lock first so threads
+    /// checking their condition in [`Cv::wait_timeout_unless`] can't miss it.
+    pub fn notify_one_locked(&self) {
+        let _guard = self.mutex.lock().unwrap_or_else(|e| e.into_inner());
+        self.condvar.notify_one();
+    }
+
+    /// Notify all threads waiting on the condvar, taking the lock first so threads
+    /// checking their condition in [`Cv::wait_timeout_unless`] can't miss it.
+    pub fn notify_all_locked(&self) {
+        let _guard = self.mutex.lock().unwrap_or_else(|e| e.into_inner());
+        self.condvar.notify_all();
+    }
 }
@@ -1,8 +1,17 @@
-use std::{cell::UnsafeCell, collections::HashSet, ops::Deref, sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard}, thread::JoinHandle, time::Duration};
+use std::{cell::{RefCell, UnsafeCell}, collections::{HashSet, VecDeque}, ops::Deref, sync::{atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering}, Arc, Mutex, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak}, task::Waker, thread::JoinHandle, time::{Duration, Instant}};
 
-use crossbeam_deque::{Injector, Steal, Stealer};
+use crossbeam_deque::{Injector, Steal, Stealer, Worker};
 
-use crate::{builder::PlanetaryBuilder, condvar::Cv, hooks::Hooks, macros::tracing_feat, task::TypeErasedTask, worker::{self, WorkerCore}};
+use crate::{blocking::BlockingPool, keyed::KeyedTasks, shutdown::AwaitedTaskWaker, builder::{PlanetaryBuilder, PoolConfig, QueueOrder, SaturationPolicy, SpawnPolicy}, condvar::Cv, defer, handle::Placement, hooks::{Hooks, NameContext}, latency::{LatencyHistogram, LatencyStats}, macros::tracing_feat, metrics::{FinalMetrics, StatCounters}, partition::{PartitionConfig, PartitionInner, PartitionSlot}, join::{self, AbortHandle}, steal::{Peer, StealStrategy}, task::{state::{Snapshot, State}, timing::Timestamp, Header, IntoRunnable, ResultSink, RunnableOutput, Task, TypeErasedTask}, timer::Timer, worker::{self, WorkerCore}};
+
+/// Epoch to be assigned to the next threadpool built.
+static NEXT_POOL_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// Threadpools whose task permit the current thread holds, shared by the tasks it runs
+    /// while running another one, like inline or helped tasks
+    static HELD_PERMITS: RefCell<Vec<Core>> = const { RefCell::new(Vec::new()) };
+}
 
 #[derive(Clone)]
 pub struct Core(Arc<CoreInner>);
@@ -22,21 +31,138 @@ pub struct CoreInner {
     pub hooks: Hooks,
     /// Whether to stop the thread pool and all the workers
     stop: UnsafeCell<bool>,
-    /// Timeout for worker threads to be alive while not executing any task.
-    timeout: Duration,
+    /// Number of user facing `Planetary` handles alive
+    handles: AtomicUsize,
+    /// Timeout for worker threads to be alive while not executing any task,
+    /// stored as nanoseconds so it can be changed at runtime.
+    timeout: AtomicU64,
 
     // - Thread state counters -
     /// Number of threads that are currently idle, waiting for work while parked
     idle: AtomicUsize,
     /// Number of threads that are currently working
     working: AtomicUsize,
+    /// Number of threads alive, the length of `threads` readable without taking its lock
+    live: AtomicUsize,
 
     /// Optional stack size to use when spawning threads
     stack_size: Option<usize>,
-    /// Maximum number of threads that can be spawned
-    max_threads: usize,
+    /// Maximum number of threads the threadpool was built with, raised for the duration
+    /// of bursts
+    max_threads: AtomicUsize,
+    /// Number of distinct worker ids, grown by bursts and never shrunk so the ids of
+    /// workers outliving a burst stay valid
+    id_space: AtomicUsize,
+    /// Maximum number of threads currently allowed, lowered with `set_max_threads`
+    thread_limit: AtomicUsize,
+    /// Whether all the threads were launched when the threadpool was built
+    launch_on_build: bool,
+    /// Maximum number of threads spawned on demand when tasks are spawned,
+    /// lower than `max_threads` when the autoscaler manages the rest.
+    spawn_threads_cap: AtomicUsize,
+    /// Maximum number of threads the autoscaler scales up to, if there's one
+    autoscale_max_threads: Option<usize>,
+    /// Number of workers asked to exit by the autoscaler
+    reap_requests: AtomicUsize,
     /// Conditional variable used when shutting down the threadpool
     shutdown_cv: Cv,
+    /// Wakers of the futures waiting for all the workers to exit
+    stop_wakers: Mutex<Vec<Waker>>,
+    /// Conditional variable notified when a worker may have become available
+    slot_cv: Cv,
+
+    /// Default placement of tasks spawned from worker threads
+    spawn_policy: SpawnPolicy,
+    /// Behavior when spawning tasks while the threadpool is saturated
+    saturation_policy: SaturationPolicy,
+    /// Number of tasks a worker runs between checks of the global injector
+    injector_interval: u32,
+    /// How long idle workers keep looking for tasks before parking
+    spin_before_park: Duration,
+    /// Whether idle workers steal tasks from the local queues of other workers
+    work_stealing: bool,
+    /// Order in which workers run the tasks of their local queue
+    local_queue_order: QueueOrder,
+    /// Whether tasks that would start a new thread run on the spawning thread instead
+    eager_caller_run: bool,
+    /// Number of workers that only steal tasks from the rest
+    dedicated_stealers: usize,
+    /// Number of dedicated stealers parked, woken when a local queue gets tasks to steal
+    parked_stealers: AtomicUsize,
+    /// Whether workers blocked joining tasks nothing can run panic instead of hanging
+    detect_deadlocks: bool,
+    /// Minimum interval between the creation of threads spawned on demand
+    thread_spawn_rate: Option<Duration>,
+    /// When the last thread was spawned on demand
+    last_thread_spawn: Timestamp,
+    /// Tasks spawned with low priority, only run when there is nothing else to do
+    /// unless they waited for longer than the starvation threshold
+    low_priority: Mutex<VecDeque<TypeErasedTask>>,
+    /// Number of tasks in `low_priority`, so workers only lock it when there are some
+    low_priority_len: AtomicUsize,
+    /// How long low priority tasks wait before being treated as high priority ones
+    starvation_threshold: Duration,
+
+    /// Maximum number of tasks that can be running at the same time
+    max_concurrent_tasks: Option<usize>,
+    /// Number of tasks currently holding a running permit
+    running_tasks: AtomicUsize,
+    /// Conditional variable notified when a running permit is released
+    permit_cv: Cv,
+
+    /// Whether the workers are paused and must not start new tasks
+    paused: AtomicBool,
+    /// Number of workers currently looking for or executing tasks
+    active: AtomicUsize,
+    /// Conditional variable notified when the workers are resumed
+    pause_cv: Cv,
+    /// Number of workers between calling `before_work` and finishing `after_work`
+    in_work: AtomicUsize,
+    /// Conditional variable notified when no worker is running a task or its hooks
+    work_section_cv: Cv,
+
+    /// Number of tasks executed by the workers, wraps around on overflow
+    completed_tasks: AtomicU64,
+    /// Counters accumulated since the threadpool was built or they were last reset
+    pub stats: StatCounters,
+    /// Queue latencies of the tasks, if tracking them is enabled
+    latency: Option<LatencyHistogram>,
+    /// Receives the outputs of detached tasks
+    result_sink: Option<ResultSink>,
+    /// Receives the final statistics once every worker exited after a shutdown
+    final_metrics: Mutex<Option<FinalMetrics>>,
+    /// Chooses the workers to steal tasks from
+    steal_strategy: Box<dyn StealStrategy + Send + Sync>,
+    /// Minimum stack a worker must have left to start a task
+    #[cfg(feature = "stack-guard")]
+    task_stack_guard: Option<usize>,
+    /// Conditional variable notified each time a task completes, while someone waits on it
+    completion_cv: Cv,
+    /// Number of threads waiting on `completion_cv`
+    completion_waiters: AtomicUsize,
+    /// Partitions created in the threadpool
+    partitions: RwLock<Vec<Arc<PartitionInner>>>,
+    /// Next partition to take a task from
+    partition_cursor: AtomicUsize,
+    /// Number of threadpools built before this one, used to name the workers
+    epoch: AtomicU64,
+    /// Timer running delayed checks, started the first time it's needed
+    timer: OnceLock<Timer>,
+    /// Threads running the tasks spawned as blocking
+    pub blocking: BlockingPool,
+    /// Thread sampling the metrics for the configured sink, joined on shutdown
+    pub sampler: Mutex<Option<JoinHandle<()>>>,
+    /// Keyed tasks queued or running, along with their duplicates
+    pub keyed: KeyedTasks,
+    /// Detached tasks the shutdown waits for, see [`JoinHandle::detach_and_wait_on_shutdown`]
+    ///
+    /// [`JoinHandle::detach_and_wait_on_shutdown`]: crate::join::JoinHandle::detach_and_wait_on_shutdown
+    awaited_on_shutdown: Mutex<Vec<AbortHandle>>,
+    /// Whether a shutdown was requested and waits for the awaited tasks before stopping
+    shutdown_pending: AtomicBool,
+    /// Tasks handed to the threadpool that didn't finish yet
+    #[cfg(feature = "inspect")]
+    pub registry: crate::inspect::Registry,
 }
 
 unsafe impl Send for CoreInner {}
@@ -50,33 +176,196 @@ impl Core {
             threads: RwLock::new(Vec::new()),
             used_ids: Mutex::new(HashSet::new()),
             hooks: builder.hooks,
+            epoch: AtomicU64::new(NEXT_POOL_EPOCH.fetch_add(1, Ordering::Relaxed)),
+            timer: OnceLock::new(),
+            keyed: KeyedTasks::default(),
+            awaited_on_shutdown: Mutex::new(Vec::new()),
+            shutdown_pending: AtomicBool::new(false),
+            final_metrics: Mutex::new(builder.final_metrics),
+            blocking: BlockingPool::new(builder.max_blocking_threads, builder.blocking_queue_policy, builder.blocking_timeout),
+            sampler: Mutex::new(None),
+            #[cfg(feature = "inspect")]
+            registry: Default::default(),
             stop: UnsafeCell::new(false),
-            timeout: builder.timeout,
+            handles: AtomicUsize::new(0),
+            timeout: AtomicU64::new(duration_to_nanos(builder.timeout)),
             idle: AtomicUsize::new(0),
             working: AtomicUsize::new(0),
+            live: AtomicUsize::new(0),
             stack_size: builder.stack_size,
-            max_threads: builder.max_threads,
-            shutdown_cv: Cv::new()
+            max_threads: AtomicUsize::new(builder.max_threads),
+            id_space: AtomicUsize::new(builder.max_threads),
+            thread_limit: AtomicUsize::new(builder.max_threads),
+            launch_on_build: builder.launch_on_build,
+            spawn_threads_cap: AtomicUsize::new(match builder.autoscale.as_ref() {
+                _ if builder.test_mode => 0,
+                Some(autoscale) => autoscale.min_threads.min(builder.max_threads),
+                None => builder.max_threads
+            }),
+            autoscale_max_threads: builder.autoscale.as_ref().map(|autoscale| autoscale.max_threads),
+            reap_requests: AtomicUsize::new(0),
+            shutdown_cv: Cv::new(),
+            stop_wakers: Mutex::new(Vec::new()),
+            slot_cv: Cv::new(),
+            spawn_policy: builder.spawn_policy,
+            saturation_policy: builder.saturation_policy,
+            injector_interval: builder.injector_interval,
+            spin_before_park: builder.spin_before_park,
+            work_stealing: builder.work_stealing,
+            local_queue_order: builder.local_queue_order,
+            eager_caller_run: builder.eager_caller_run,
+            dedicated_stealers: builder.dedicated_stealers,
+            parked_stealers: AtomicUsize::new(0),
+            detect_deadlocks: builder.detect_deadlocks,
+            thread_spawn_rate: builder.thread_spawn_rate,
+            last_thread_spawn: Timestamp::new(),
+            low_priority: Mutex::new(VecDeque::new()),
+            low_priority_len: AtomicUsize::new(0),
+            starvation_threshold: builder.starvation_threshold,
+            max_concurrent_tasks: builder.max_concurrent_tasks,
+            running_tasks: AtomicUsize::new(0),
+            permit_cv: Cv::new(),
+            paused: AtomicBool::new(false),
+            active: AtomicUsize::new(0),
+            pause_cv: Cv::new(),
+            in_work: AtomicUsize::new(0),
+            work_section_cv: Cv::new(),
+            completed_tasks: AtomicU64::new(0),
+            stats: StatCounters::default(),
+            latency: builder.track_latency.then(LatencyHistogram::new),
+            result_sink: builder.result_sink,
+            steal_strategy: builder.steal_strategy,
+            #[cfg(feature = "stack-guard")]
+            task_stack_guard: builder.task_stack_guard,
+            completion_cv: Cv::new(),
+            completion_waiters: AtomicUsize::new(0),
+            partitions: RwLock::new(Vec::new()),
+            partition_cursor: AtomicUsize::new(0)
         }))
     }
 
+    pub fn config(&self) -> PoolConfig {
+        PoolConfig {
+            max_threads: self.thread_limit(),
+            stack_size: self.stack_size,
+            timeout: self.timeout(),
+            launch_on_build: self.launch_on_build,
+            max_concurrent_tasks: self.max_concurrent_tasks,
+            spawn_policy: self.spawn_policy,
+            saturation_policy: self.saturation_policy,
+        }
+    }
+
+    pub fn spawn_policy(&self) -> SpawnPolicy {
+        self.spawn_policy
+    }
+
     pub fn spawn_task(&self, task: TypeErasedTask) {
+        self.spawn_task_with_policy(task, self.spawn_policy);
+    }
+
+    /// Spawns a task without using the local queue of the current worker, so it's
+    /// placed at the back of the global injector.
+    pub fn inject_task(&self, task: TypeErasedTask) {
+        self.spawn_task_with_policy(task, SpawnPolicy::GlobalFanout);
+    }
+
+    /// Queues the tasks given to the builder into the global injector.
+    pub fn inject_initial(&self, tasks: Vec<TypeErasedTask>) {
+        for task in tasks {
+            self.attach_result_sink(task.header());
+            self.inject_task(task);
+        }
+    }
+
+    /// Takes ownership of a task handed to the threadpool, before it's visible to
+    /// anything that could run it. Every submit path goes through here.
+    pub fn accept(&self, task: &TypeErasedTask) {
+        task.header().enqueued_at.mark();
+        task.header().set_owner(self);
+        #[cfg(feature = "inspect")]
+        self.registry.register(task);
+    }
+
+    /// Schedules the task according to `policy`, returning where it was placed.
+    pub fn spawn_task_with_policy(&self, task: TypeErasedTask, policy: SpawnPolicy) -> Placement {
+        self.accept(&task);
+        tracing_feat!(trace!("Task {} enqueued", task.header().id));
+
+        if worker::is_stopping(self) {
+            // the worker is going away, so run tasks spawned from `on_stop` right here
+            tracing_feat!(trace!("Task spawned from on_stop, running inline"));
+            self.execute_task(task);
+            return Placement::Inline;
+        }
+
+        if self.should_stop() {
+            tracing_feat!(trace!("Threadpool stopped, cancelling task"));
+            task.cancel();
+            return Placement::Cancelled;
+        }
+
         if !self.should_spawn_thread() {
-            if let Some(worker) = worker::try_get_worker() {
+            if let Some(worker) = self.local_worker(policy) {
                 tracing_feat!(trace!("Pushing task into current worker"));
                 worker.queue.push(task);
-                return;
+
+                // let a parked dedicated stealer know once there is backlog to drain,
+                // it keeps stealing until the queue is empty again
+                if worker.queue.len() == 1 && self.parked_stealers.load(Ordering::SeqCst) > 0 {
+                    self.notify_worker();
+                }
+
+                return Placement::LocalQueue;
+            }
+
+            if self.is_saturated() {
+                match self.saturation_policy {
+                    SaturationPolicy::Queue => {},
+                    SaturationPolicy::CallerRuns => {
+                        tracing_feat!(trace!("Threadpool saturated, running task on the caller"));
+                        self.execute_task(task);
+                        return Placement::Inline;
+                    },
+                    SaturationPolicy::Reject => {
+                        tracing_feat!(trace!("Threadpool saturated, rejecting task"));
+                        task.cancel();
+                        return Placement::Rejected;
+                    }
+                }
             }
 
             tracing_feat!(trace!("Task spawned, injecting into global injector"));
 
             self.injector.push(task);
-            self.condvar.notify_one(); // wake if a thread is parked
-            return;
+            self.condvar.notify_one_locked(); // wake if a thread is parked
+            return Placement::Injector;
+        }
+
+        if self.eager_caller_run && worker::try_get_worker().is_none() {
+            tracing_feat!(trace!("Task spawned, running on the caller while a new thread starts"));
+            self.spawn_thread_on_demand(None);
+            self.execute_task(task);
+            return Placement::Inline;
         }
 
         tracing_feat!(trace!("Task spawned, spawning new thread"));
-        self.spawn_thread_with(Some(task));
+        self.spawn_thread_on_demand(Some(task));
+        Placement::NewThread
+    }
+
+    /// Worker whose local queue receives tasks spawned with `policy` from the current thread.
+    fn local_worker(&self, policy: SpawnPolicy) -> Option<&'static WorkerCore> {
+        worker::try_get_worker()
+            .filter(|w| policy == SpawnPolicy::LocalFirst && !w.is_participant() && !w.stealer)
+    }
+
+    /// Whether a task spawned with `policy` from the current thread would be rejected.
+    pub fn rejects_spawn(&self, policy: SpawnPolicy) -> bool {
+        self.saturation_policy == SaturationPolicy::Reject
+            && !self.should_stop()
+            && self.local_worker(policy).is_none()
+            && self.is_saturated()
     }
 
     #[allow(mismatched_lifetime_syntaxes)]
@@ -102,23 +391,37 @@ impl Core {
     pub fn spawn_thread_with(&self, task: Option<TypeErasedTask>) {
         let mut lock = self.lock_threads();
         let mut ids = self.used_ids.lock().unwrap_or_else(|s| s.into_inner());
-        assert!(lock.len() < self.max_threads);
-        assert!(ids.len() < self.max_threads);
+        let id_space = self.worker_id_space();
+        assert!(lock.len() < self.max_threads());
+        assert!(ids.len() < id_space);
 
         let id = loop {
-            let id = fastrand::usize(0..self.max_threads);
+            let id = fastrand::usize(0..id_space);
 
             if ids.insert(id) {
                 break id;
             }
         };
 
-        let worker = WorkerCore::new(self.clone(), id);
-        let stealer = worker.queue.stealer();
+        let mut worker = WorkerCore::new(self.clone(), id);
+        // workers spawned for a task must run it, so they can't be dedicated stealers
+        worker.stealer = task.is_none()
+            && lock.iter().filter(|t| t.stealer).count() < self.dedicated_stealers;
+        let stealer = worker.stealer;
+        let queue = worker.queue.stealer();
+        let inbox = worker.inbox.clone();
+        let node = worker.node.clone();
+        let busy_nanos = worker.busy_nanos.clone();
+        let waiting_on = worker.waiting_on.clone();
         self.working.fetch_add(1, Ordering::SeqCst);
+        StatCounters::bump(&self.stats.threads_spawned);
 
         let mut thread_builder = std::thread::Builder::new()
-            .name(self.hooks.call_name_fn());
+            .name(self.hooks.call_name_fn(NameContext {
+                worker_id: id,
+                pool_epoch: self.epoch.load(Ordering::Relaxed),
+                pid: std::process::id(),
+            }));
 
         if let Some(stack_size) = self.stack_size {
             thread_builder = thread_builder.stack_size(stack_size);
@@ -131,8 +434,14 @@ impl Core {
             .unwrap_or_else(|_| panic!("Failed to spawn thread"));
 
         tracing_feat!(trace!("Adding thread {id} to threads"));
+        self.live.fetch_add(1, Ordering::SeqCst);
         lock.push(ThreadInfo {
-            queue: stealer,
+            queue,
+            inbox,
+            node,
+            busy_nanos,
+            waiting_on,
+            stealer,
             handle,
             id
         });
@@ -152,20 +461,278 @@ impl Core {
         }
 
         // If we already spawned the maximum number of threads, we can't spawn more
-        if threads.len() >= self.max_threads {
+        if threads.len() >= self.spawn_cap() {
+            return false;
+        }
+
+        // If we spawned a thread too recently, let the existing ones take the task,
+        // unless there are none
+        let throttled = self.thread_spawn_rate.zip(self.last_thread_spawn.elapsed())
+            .is_some_and(|(rate, elapsed)| elapsed < rate);
+
+        if throttled && !threads.is_empty() {
             return false;
         }
 
-        // We can spawn a new thread
         true
     }
 
+    /// Spawns a thread on demand once [`Core::should_spawn_thread`] allowed it, throttling
+    /// the next ones from the moment it actually started.
+    fn spawn_thread_on_demand(&self, task: Option<TypeErasedTask>) {
+        self.spawn_thread_with(task);
+        self.last_thread_spawn.mark();
+    }
+
+    /// Spawns threads until the current thread limit is reached, returning how many were started.
+    pub fn spawn_up_to_limit(&self) -> usize {
+        let mut spawned = 0;
+
+        loop {
+            let threads = self.lock_threads_read();
+
+            if self.should_stop() || threads.len() >= self.spawn_cap() {
+                return spawned;
+            }
+
+            drop(threads);
+            self.spawn_thread_with(None);
+            spawned += 1;
+        }
+    }
+
+    /// Number of worker threads currently alive.
+    pub fn live_threads(&self) -> usize {
+        self.live.load(Ordering::SeqCst)
+    }
+
+    /// Whether the threadpool is running, none of its locks is poisoned and its queued
+    /// tasks have workers to run them. Only takes read locks.
+    pub fn is_healthy(&self) -> bool {
+        let poisoned = self.threads.is_poisoned()
+            || self.used_ids.is_poisoned()
+            || self.low_priority.is_poisoned()
+            || self.partitions.is_poisoned();
+
+        if poisoned || self.should_stop() {
+            return false;
+        }
+
+        // tasks nobody is going to run until something spawns a worker
+        let stranded = self.live_threads() == 0
+            && (!self.injector.is_empty()
+                || self.has_low_priority()
+                || self.partitions.read()
+                    .unwrap_or_else(|s| s.into_inner())
+                    .iter()
+                    .any(|partition| !partition.is_empty()));
+
+        !stranded
+    }
+
+    /// Whether the maximum number of worker threads are alive, up to the current limit and
+    /// the one of the autoscaler, which doesn't grow the threadpool further.
+    pub fn at_capacity(&self) -> bool {
+        let capacity = self.thread_limit().min(self.max_threads());
+        let capacity = self.autoscale_max_threads.map_or(capacity, |max| max.min(capacity));
+
+        self.live_threads() >= capacity
+    }
+
+    /// Maximum number of worker threads currently allowed.
+    pub fn thread_limit(&self) -> usize {
+        self.thread_limit.load(Ordering::SeqCst)
+    }
+
+    /// Number of distinct worker ids, the largest maximum number of threads the threadpool
+    /// has had, bursts included.
+    pub fn worker_id_space(&self) -> usize {
+        self.id_space.load(Ordering::SeqCst)
+    }
+
+    fn max_threads(&self) -> usize {
+        self.max_threads.load(Ordering::SeqCst)
+    }
+
+    /// Maximum number of threads that can be spawned on demand right now.
+    fn spawn_cap(&self) -> usize {
+        self.spawn_threads_cap.load(Ordering::SeqCst).min(self.thread_limit())
+    }
+
+    /// Allows `extra` more threads until [`Core::end_burst`] is called with the same amount.
+    pub fn begin_burst(&self, extra: usize) {
+        let max_threads = self.max_threads.fetch_add(extra, Ordering::SeqCst) + extra;
+        self.id_space.fetch_max(max_threads, Ordering::SeqCst);
+        self.thread_limit.fetch_add(extra, Ordering::SeqCst);
+        self.spawn_threads_cap.fetch_add(extra, Ordering::SeqCst);
+        self.slot_cv.notify_all_locked();
+    }
+
+    /// Takes back the threads allowed by [`Core::begin_burst`], the workers in excess exit
+    /// once they are idle for the timeout.
+    pub fn end_burst(&self, extra: usize) {
+        // the limits may have been lowered during the burst, so they can't go under zero,
+        // and a threadpool keeps at least one thread
+        let _ = self.spawn_threads_cap.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |cap| {
+            Some(cap.saturating_sub(extra))
+        });
+
+        for limit in [&self.thread_limit, &self.max_threads] {
+            let _ = limit.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |limit| {
+                Some(limit.saturating_sub(extra).max(1))
+            });
+        }
+    }
+
+    /// Changes the maximum number of worker threads, up to the one the threadpool was
+    /// built with, asking the excess workers to exit.
+    pub fn set_max_threads(&self, threads: usize) {
+        assert!(threads > 0, "a threadpool needs at least one thread");
+        let threads = threads.min(self.max_threads());
+        self.thread_limit.store(threads, Ordering::SeqCst);
+        // a raised limit frees slots
+        self.slot_cv.notify_all_locked();
+
+        // requests left from a lower limit would take the workers under the new one
+        let excess = self.live_threads().saturating_sub(threads);
+        let _ = self.reap_requests.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| Some(pending.min(excess)));
+
+        while self.request_reap(threads) {}
+    }
+
+    /// Takes a pending reap request if there are more workers than allowed, so busy
+    /// workers can exit between tasks when the threadpool is downsized.
+    pub fn take_downscale_reap(&self) -> bool {
+        self.live_threads() > self.thread_limit() && self.take_reap_request()
+    }
+
+    /// Puts a task of an exiting worker back into the global injector, waking a worker
+    /// to take it.
+    pub fn requeue(&self, task: TypeErasedTask) {
+        self.injector.push(task);
+        self.notify_worker();
+    }
+
+    /// Number of worker threads currently parked waiting for work.
+    pub fn idle_threads(&self) -> usize {
+        self.idle.load(Ordering::SeqCst)
+    }
+
+    /// Number of worker threads alive and not parked.
+    pub fn working_threads(&self) -> usize {
+        self.working.load(Ordering::SeqCst)
+    }
+
+    pub fn spin_before_park(&self) -> Duration {
+        self.spin_before_park
+    }
+
+    /// Takes a task from the global injector if it's the turn of the injector for a
+    /// worker that executed `ticks` tasks.
+    pub fn poll_injector_turn(&self, ticks: u32) -> Option<TypeErasedTask> {
+        if self.injector_interval == 0 || !ticks.is_multiple_of(self.injector_interval) {
+            return None;
+        }
+
+        steal_task(&self.injector)
+    }
+
+    /// Takes the next task from the global injector, used to drive a [`TestPool`].
+    ///
+    /// [`TestPool`]: crate::testing::TestPool
+    pub fn take_injected(&self) -> Option<TypeErasedTask> {
+        steal_task(&self.injector)
+    }
+
+    /// Approximate number of tasks waiting in the global injector.
+    pub fn injector_len(&self) -> usize {
+        self.injector.len()
+    }
+
+    /// Approximate number of tasks waiting in the global injector and the partitions.
+    pub fn queued_tasks(&self) -> usize {
+        self.injector.len() + self.partitions.read()
+            .unwrap_or_else(|s| s.into_inner())
+            .iter()
+            .map(|partition| partition.len())
+            .sum::<usize>()
+    }
+
+    /// Takes all the tasks in the global injector, in the order they were queued.
+    pub fn drain_injector(&self) -> Vec<TypeErasedTask> {
+        // stolen in batches like the workers do, so each task is only taken by one of us
+        let batch = Worker::new_fifo();
+
+        while !self.injector.steal_batch(&batch).is_empty() {}
+
+        std::iter::from_fn(|| batch.pop()).collect()
+    }
+
+    /// Asks an idle worker to exit, waking the parked ones so one of them takes the request.
+    /// Does nothing if the pending requests already take the live workers down to `floor`,
+    /// returning whether a worker was asked to exit.
+    pub fn request_reap(&self, floor: usize) -> bool {
+        let excess = self.live_threads().saturating_sub(floor);
+        let requested = self.reap_requests
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| (pending < excess).then_some(pending + 1))
+            .is_ok();
+
+        if requested {
+            self.condvar.notify_all_locked();
+        }
+
+        requested
+    }
+
+    /// Takes a pending reap request, returning whether the caller worker should exit.
+    pub fn take_reap_request(&self) -> bool {
+        self.reap_requests
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |r| r.checked_sub(1))
+            .is_ok()
+    }
+
+    /// Checks whether all the threads are busy and no more threads can be spawned.
+    pub fn is_saturated(&self) -> bool {
+        self.idle.load(Ordering::SeqCst) == 0 && self.at_capacity()
+    }
+
+    /// Blocks the caller until the pool is no longer saturated.
+    pub fn wait_for_slot(&self) {
+        self.wait_for_slot_until(None);
+    }
+
+    /// Blocks the caller until the pool is no longer saturated or the deadline passes,
+    /// returning false if it was still saturated by then.
+    pub fn wait_for_slot_until(&self, deadline: Option<Instant>) -> bool {
+        // checked under the lock of the condvar, whatever frees a slot notifies it locked
+        let ready = || !self.is_saturated() || self.should_stop();
+
+        let Some(deadline) = deadline else {
+            self.slot_cv.wait_until(ready);
+            return true;
+        };
+
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+
+            if left.is_zero() {
+                return ready();
+            }
+
+            if !self.slot_cv.wait_timeout_unless(left, ready) && ready() {
+                return true;
+            }
+        }
+    }
+
     pub fn enter_idle(&self) {
         self.idle.fetch_add(1, Ordering::SeqCst);
+        self.slot_cv.notify_all_locked();
     }
 
     pub fn leave_idle(&self) {
-        self.idle.fetch_sub(1, Ordering::SeqCst);
+        let previous = self.idle.fetch_sub(1, Ordering::SeqCst);
+        debug_assert!(previous > 0, "idle counter underflowed");
     }
 
     pub fn enter_working(&self) {
@@ -173,18 +740,486 @@ impl Core {
     }
 
     pub fn leave_working(&self) {
-        self.working.fetch_sub(1, Ordering::SeqCst);
+        let previous = self.working.fetch_sub(1, Ordering::SeqCst);
+        debug_assert!(previous > 0, "working counter underflowed");
+        self.slot_cv.notify_all_locked();
+    }
+
+    /// Waits until a task can start running without exceeding the concurrent tasks limit,
+    /// taking a permit given back once the returned guard is dropped. Threads already
+    /// holding a permit of the threadpool run their nested tasks under it, so `None` is
+    /// returned for them as well as when there's no limit.
+    pub fn acquire_task_permit(&self) -> Option<TaskPermit> {
+        self.max_concurrent_tasks?;
+
+        if HELD_PERMITS.with_borrow(|held| held.iter().any(|core| core.as_ptr() == self.as_ptr())) {
+            return None;
+        }
+
+        self.claim_task_permit();
+        HELD_PERMITS.with_borrow_mut(|held| held.push(self.clone()));
+
+        Some(TaskPermit(self.clone()))
+    }
+
+    fn claim_task_permit(&self) {
+        let Some(max) = self.max_concurrent_tasks else {
+            return;
+        };
+
+        // claimed under the lock of the condvar, so a permit released meanwhile wakes us up
+        self.permit_cv.wait_until(|| {
+            self.running_tasks
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |running| (running < max).then_some(running + 1))
+                .is_ok()
+        });
+    }
+
+    fn release_task_permit(&self) {
+        if self.max_concurrent_tasks.is_some() {
+            let previous = self.running_tasks.fetch_sub(1, Ordering::SeqCst);
+            debug_assert!(previous > 0, "running tasks counter underflowed");
+            self.permit_cv.notify_one_locked();
+        }
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::SeqCst);
+
+        if !paused {
+            self.pause_cv.notify_all_locked();
+        } else {
+            // the awaited tasks that didn't start won't run anymore
+            self.try_finish_shutdown();
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Marks the caller worker as active, so it can look for and execute tasks. Returns
+    /// false if the workers are paused, in which case the worker must not touch any task.
+    pub fn enter_active(&self) -> bool {
+        // announce ourselves before checking the flag, so either we see the pause or
+        // whoever paused the pool sees us as active
+        self.active.fetch_add(1, Ordering::SeqCst);
+
+        if self.is_paused() {
+            self.leave_active();
+            return false;
+        }
+
+        true
+    }
+
+    pub fn leave_active(&self) {
+        let previous = self.active.fetch_sub(1, Ordering::SeqCst);
+        debug_assert!(previous > 0, "active counter underflowed");
+
+        // whoever paused the pool may be waiting for the last active worker
+        if previous == 1 && self.is_paused() {
+            self.pause_cv.notify_all_locked();
+        }
+    }
+
+    /// Blocks the caller worker while the threadpool is paused.
+    pub fn wait_resumed(&self) {
+        self.pause_cv.wait_until(|| !self.is_paused() || self.should_stop());
+    }
+
+    /// Blocks until no worker is looking for or executing tasks.
+    pub fn wait_inactive(&self) {
+        self.pause_cv.wait_until(|| self.active.load(Ordering::SeqCst) == 0);
+    }
+
+    /// Marks the caller worker as running a task along with its work hooks.
+    pub fn enter_work_section(&self) {
+        self.in_work.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Marks the end of a section started with [`Core::enter_work_section`], waking up
+    /// whoever waits for the workers once the last one leaves.
+    pub fn leave_work_section(&self) {
+        let previous = self.in_work.fetch_sub(1, Ordering::SeqCst);
+        debug_assert!(previous > 0, "work section counter underflowed");
+
+        if previous == 1 {
+            self.work_section_cv.notify_all_locked();
+        }
+    }
+
+    /// Blocks until no worker is running a task or its work hooks.
+    pub fn wait_work_sections(&self) {
+        self.work_section_cv.wait_until(|| self.in_work.load(Ordering::SeqCst) == 0);
+    }
+
+    /// Whether the current worker has less stack left than the configured guard.
+    #[cfg(feature = "stack-guard")]
+    pub fn stack_exhausted(&self) -> bool {
+        let Some(guard) = self.task_stack_guard else {
+            return false;
+        };
+
+        crate::stack::remaining(self.stack_size).is_some_and(|remaining| remaining < guard)
+    }
+
+    /// Counts a task the workers tried to run in the cumulative stats, as aborted if it was
+    /// aborted before or while running, as panicked if it panicked and as completed otherwise.
+    pub fn record_outcome(&self, state: Snapshot) {
+        let counter = if state.get(State::ABORTED) {
+            &self.stats.tasks_aborted
+        } else if state.get(State::PANICKED) {
+            &self.stats.tasks_panicked
+        } else {
+            &self.stats.tasks_completed
+        };
+
+        StatCounters::bump(counter);
+    }
+
+    /// Runs a task taken out of the threadpool on the current thread, doing the bookkeeping
+    /// around it: the stack guard, work hooks, concurrency permits and stats. Returns how
+    /// long the task itself ran.
+    pub fn execute_task(&self, task: TypeErasedTask) -> Duration {
+        #[cfg(feature = "stack-guard")]
+        if self.stack_exhausted() {
+            tracing_feat!(warn!("Refusing to run task {} due to low remaining stack", task.header().id));
+            task.abort_with(crate::join::JoinError::StackExhausted);
+            StatCounters::bump(&self.stats.tasks_aborted);
+            return Duration::ZERO;
+        }
+
+        self.enter_work_section();
+        defer!(|| self.leave_work_section());
+
+        // the task may be freed once it runs, so keep its id around for the after_work hook
+        let task_id = task.header().id;
+        self.hooks.call_before_work_fn(task_id);
+        let permit = self.acquire_task_permit();
+        self.record_latency(&task);
+        let started = Instant::now();
+        let state = task.run();
+        let elapsed = started.elapsed();
+        let counted = !state.get(State::INTERNAL);
+
+        if counted {
+            self.record_outcome(state);
+        }
+
+        drop(permit);
+        self.hooks.call_after_work_fn(task_id);
+
+        if counted {
+            self.task_completed(state);
+        }
+
+        elapsed
+    }
+
+    /// Records the completion of a task if it actually ran, unlike tasks aborted before
+    /// starting, waking up anyone waiting for completions.
+    pub fn task_completed(&self, state: Snapshot) {
+        if !state.get(State::STARTED) {
+            return;
+        }
+
+        self.completed_tasks.fetch_add(1, Ordering::SeqCst);
+
+        // only take the lock of the condvar if somebody waits, waiters register before
+        // checking their condition, so they either see this completion or get notified
+        if self.completion_waiters.load(Ordering::SeqCst) > 0 {
+            self.completion_cv.notify_all_locked();
+        }
+    }
+
+    /// Makes the output of a task that gets a handle go to the result sink, if there's one,
+    /// when the handle doesn't take it.
+    pub fn attach_result_sink(&self, header: &Header) {
+        if let Some(sink) = &self.result_sink {
+            let _ = header.result_sink.set(sink.clone());
+        }
+    }
+
+    /// Records the time the task waited in the queues, if tracking is enabled.
+    pub fn record_latency(&self, task: &TypeErasedTask) {
+        if let Some(histogram) = &self.latency {
+            histogram.record(task.header().enqueued_at.elapsed().unwrap_or_default());
+        }
+    }
+
+    pub fn latency_stats(&self) -> LatencyStats {
+        self.latency.as_ref()
+            .map(LatencyHistogram::stats)
+            .unwrap_or_default()
+    }
+
+    /// Number of tasks executed by the workers since the threadpool was built.
+    pub fn completed_tasks(&self) -> u64 {
+        self.completed_tasks.load(Ordering::SeqCst)
+    }
+
+    /// Returns the threadpool to the state it had after being built, keeping the live
+    /// workers. Does nothing and returns false if there are tasks queued or running.
+    pub fn reset(&self) -> bool {
+        if !self.is_drained() {
+            return false;
+        }
+
+        self.stats.reset();
+        self.completed_tasks.store(0, Ordering::SeqCst);
+        self.epoch.store(NEXT_POOL_EPOCH.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+
+        let threads = self.lock_threads_read();
+        self.used_ids.lock()
+            .unwrap_or_else(|s| s.into_inner())
+            .retain(|id| threads.iter().any(|t| t.id == *id));
+
+        true
+    }
+
+    /// Whether no task is queued anywhere in the threadpool nor running in a worker.
+    fn is_drained(&self) -> bool {
+        self.in_work.load(Ordering::SeqCst) == 0
+            && self.injector.is_empty()
+            && !self.has_low_priority()
+            && self.partitions.read()
+                .unwrap_or_else(|s| s.into_inner())
+                .iter()
+                .all(|partition| partition.is_empty())
+            && self.lock_threads_read()
+                .iter()
+                .all(|t| t.queue.is_empty() && t.inbox.is_empty())
+    }
+
+    /// Blocks until `done` holds, checking it each time a task completes, or until the
+    /// deadline passes if there's one. Returns whether `done` held.
+    pub fn wait_completions(&self, deadline: Option<Instant>, done: impl Fn() -> bool) -> bool {
+        self.completion_waiters.fetch_add(1, Ordering::SeqCst);
+        defer!(|| { self.completion_waiters.fetch_sub(1, Ordering::SeqCst); });
+
+        let Some(deadline) = deadline else {
+            self.completion_cv.wait_until(done);
+            return true;
+        };
+
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+
+            if left.is_zero() {
+                return done();
+            }
+
+            if !self.completion_cv.wait_timeout_unless(left, &done) && done() {
+                return true;
+            }
+        }
     }
 
     pub fn remove_worker(&self, id: usize) {
         let mut threads = self.lock_threads();
-        threads.retain(|t| t.id != id);
-        self.shutdown_cv.notify_all();
+        let removed = threads.iter()
+            .position(|t| t.id == id)
+            .map(|index| threads.remove(index));
+        self.live.store(threads.len(), Ordering::SeqCst);
+        self.used_ids.lock().unwrap_or_else(|s| s.into_inner()).remove(&id);
+        drop(threads);
+        // there's room for a new worker now
+        self.slot_cv.notify_all_locked();
+
+        // tasks placed on the worker after it stopped looking for work
+        if let Some(info) = removed {
+            while let Some(task) = steal_task(&info.inbox) {
+                self.inject_task(task);
+            }
+        }
+
+        self.notify_stopped();
+    }
+
+    /// Wakes whoever waits for the workers to exit, if the threadpool stopped and they did.
+    fn notify_stopped(&self) {
+        self.shutdown_cv.notify_all_locked();
+
+        if self.all_stopped() {
+            let wakers = std::mem::take(&mut *self.stop_wakers.lock().unwrap_or_else(|s| s.into_inner()));
+            wakers.into_iter().for_each(Waker::wake);
+            self.emit_final_metrics();
+        }
+    }
+
+    /// Calls the final metrics callback if the threadpool shut down and every worker
+    /// exited, only the first time.
+    fn emit_final_metrics(&self) {
+        if !self.all_stopped() {
+            return;
+        }
+
+        let callback = self.final_metrics.lock().unwrap_or_else(|s| s.into_inner()).take();
+
+        if let Some(callback) = callback {
+            callback(self.stats.snapshot());
+        }
+    }
+
+    /// Watches a task, calling the on_slow_task hook if it's still running once `soft_limit`
+    /// elapsed since it started.
+    pub fn watch_slow_task(&self, task: AbortHandle, soft_limit: Duration) {
+        let core = self.downgrade();
+
+        self.timer.get_or_init(Timer::new).schedule(Instant::now() + soft_limit, move || {
+            let header = task.header();
+            let state = header.state_snapshot();
+
+            if state.get(State::FINISHED) {
+                return None;
+            }
+
+            // still queued, it can't be slow until it has run for the whole limit
+            let Some(started) = header.started_at.get() else {
+                return Some(Instant::now() + soft_limit);
+            };
+
+            let elapsed = started.elapsed();
+
+            if elapsed < soft_limit {
+                return Some(started + soft_limit);
+            }
+
+            if let Some(core) = core.upgrade().filter(|_| state.get(State::RUNNING)) {
+                tracing_feat!(warn!("Task {} running for {elapsed:?}, past its soft limit", header.id));
+                core.hooks.call_on_slow_task_fn(header.id, elapsed);
+            }
+
+            None
+        });
+    }
+
+    /// Watches a task, aborting it if it's still running once `limit` elapsed since it started,
+    /// see [`Planetary::spawn_hard_timeout`].
+    ///
+    /// [`Planetary::spawn_hard_timeout`]: crate::handle::Planetary::spawn_hard_timeout
+    pub fn watch_hard_timeout(&self, task: AbortHandle, limit: Duration) {
+        self.timer.get_or_init(Timer::new).schedule(Instant::now() + limit, move || {
+            let header = task.header();
+
+            if header.state_snapshot().get(State::FINISHED) {
+                return None;
+            }
+
+            // still queued, the limit only counts while running
+            let Some(started) = header.started_at.get() else {
+                return Some(Instant::now() + limit);
+            };
+
+            if started.elapsed() < limit {
+                return Some(started + limit);
+            }
+
+            if header.time_out() {
+                tracing_feat!(warn!("Task {} ran past its hard timeout of {limit:?}, aborting it", header.id));
+            }
+
+            None
+        });
+    }
+
+    /// Order in which workers run the tasks of their local queue.
+    pub fn local_queue_order(&self) -> QueueOrder {
+        self.local_queue_order
+    }
+
+    /// Spawns a worker in place of one that crashed, unless the threadpool is stopping
+    /// or already has enough workers.
+    pub fn replace_worker(&self) {
+        if self.should_stop() || self.live_threads() >= self.spawn_cap() {
+            return;
+        }
+
+        tracing_feat!(warn!("Worker crashed, spawning a replacement"));
+        self.spawn_thread_with(None);
+    }
+
+    /// Whether work stealing is enabled and any worker has tasks in its local queue, so
+    /// an idle worker could steal them.
+    pub fn has_stealable_tasks(&self) -> bool {
+        self.work_stealing
+            && self.lock_threads_read()
+                .iter()
+                .any(|t| !t.queue.is_empty())
+    }
+
+    /// Whether workers check for deadlocks before blocking in a join, see
+    /// [`worker::wait_on`]. The check only looks at the workers of this threadpool, so it
+    /// reports awaited tasks something else would still start, like a blocking thread, the
+    /// waker of a future or another threadpool, as deadlocked too.
+    pub fn detects_deadlocks(&self) -> bool {
+        self.detect_deadlocks
+    }
+
+    /// Whether the task of `awaited` can't ever run, because it didn't start while every
+    /// worker is blocked joining and no more workers can be spawned.
+    pub fn is_join_deadlocked(&self, awaited: &Header) -> bool {
+        let state = awaited.state_snapshot();
+
+        if state.get(State::RUNNING) || state.get(State::FINISHED) {
+            return false;
+        }
+
+        let threads = self.lock_threads_read();
+        threads.len() >= self.thread_limit()
+            && threads.iter().all(|t| t.waiting_on.load(Ordering::SeqCst) != worker::NOT_WAITING)
+    }
+
+    /// Time each live worker spent running tasks, along with its id.
+    pub fn worker_cpu_times(&self) -> Vec<(usize, Duration)> {
+        self.lock_threads_read()
+            .iter()
+            .map(|t| (t.id, Duration::from_nanos(t.busy_nanos.load(Ordering::Relaxed))))
+            .collect()
+    }
+
+    /// Ids of the worker threads currently alive.
+    pub fn worker_ids(&self) -> Vec<usize> {
+        self.lock_threads_read()
+            .iter()
+            .map(|t| t.id)
+            .collect()
+    }
+
+    /// Spawns the runnable on the worker with the given id, giving it back if there
+    /// is no live worker with that id or it's a dedicated stealer.
+    pub fn try_spawn_on<M, F: IntoRunnable<M>>(&self, worker_id: usize, runnable: F) -> Result<join::JoinHandle<RunnableOutput<F, M>>, F> {
+        let threads = self.lock_threads_read();
+
+        let Some(target) = threads.iter().find(|t| t.id == worker_id && !t.stealer) else {
+            return Err(runnable);
+        };
+
+        let task = Task::new(runnable).erase();
+        self.attach_result_sink(task.header());
+        // created before the task is visible to the worker, see `Planetary::submit`
+        let handle = join::JoinHandle::new(task.header);
+        self.accept(&task);
+        tracing_feat!(trace!("Task {} placed on worker {worker_id}", task.header().id));
+
+        if self.should_stop() {
+            task.cancel();
+            return Ok(handle);
+        }
+
+        // the read lock keeps the worker from being removed until the task is pushed
+        target.inbox.push(task);
+        drop(threads);
+        // parked workers can't be woken individually, so wake all of them
+        self.condvar.notify_all_locked();
+
+        Ok(handle)
     }
 
     /// Tries taking a task from the injector, if it fails, it will try
     /// to steal it from a worker queue.
-    pub fn try_steal(&self, worker_id: usize) -> Option<TypeErasedTask> {
+    pub fn try_steal(&self, worker_id: usize, scratch: &mut StealScratch) -> Option<TypeErasedTask> {
         tracing_feat!(trace!("Worker {worker_id} trying to steal a task"));
 
         if let Steal::Success(task) = self.injector.steal() {
@@ -192,36 +1227,237 @@ impl Core {
             return Some(task);
         }
 
+        if !self.work_stealing {
+            return None;
+        }
+
         let threads = self.lock_threads_read();
-        let len = threads.len(); // - 1; the one stealing doesnt count, but the range is non inclusive, so not -1
+        let StealScratch { all, local, peers, failed } = scratch;
 
-        (0..len)
-            .find_map(|_| {
-                let target = fastrand::usize(0..len);
+        // dedicated stealers never have tasks to steal
+        all.clear();
+        all.extend((0..threads.len()).filter(|&index| !threads[index].stealer || threads[index].id == worker_id));
 
-                let target_worker = unsafe { threads.get_unchecked(target) };
+        // workers on the same node are tried first, crossing nodes is costly
+        let node = threads.iter()
+            .find(|t| t.id == worker_id)
+            .and_then(|t| t.node.get().copied());
 
-                if target_worker.id == worker_id {
-                    return None;
-                }
+        if let Some(node) = node {
+            local.clear();
+            local.extend(all.iter().copied().filter(|&index| threads[index].node.get() == Some(&node)));
 
-                // SAFETY:
-                // We are in bounds due to how we got the index, so this cant be UB
-                let steal = target_worker
-                    .queue
-                    .steal();
+            if local.len() > 1
+                && local.len() < all.len()
+                && let Some(task) = self.steal_among(worker_id, &threads, local, peers)
+            {
+                return Some(task);
+            }
+        }
 
-                match steal {
-                    Steal::Empty | Steal::Retry => {
-                        tracing_feat!(trace!("Worker {worker_id} failed to steal a task from worker {}", target_worker.id));
-                        None
-                    },
-                    Steal::Success(task) => {
-                        tracing_feat!(trace!("Worker {worker_id} stole a task from worker {}", target_worker.id));
-                        Some(task)
-                    }
+        if let Some(task) = self.steal_among(worker_id, &threads, all, peers) {
+            return Some(task);
+        }
+
+        // reported by the worker once it gives up looking for tasks, see `report_steal_failure`
+        *failed = true;
+        None
+    }
+
+    /// Records that the given worker found nothing to steal while looking for tasks.
+    pub fn report_steal_failure(&self, worker_id: usize) {
+        StatCounters::bump(&self.stats.steals_failed);
+        self.hooks.call_on_steal_fail_fn(worker_id);
+    }
+
+    /// Lets the steal strategy pick victims among the workers at the given indexes,
+    /// offering them through `peers`.
+    fn steal_among(&self, worker_id: usize, threads: &[ThreadInfo], candidates: &[usize], peers: &mut Vec<Peer>) -> Option<TypeErasedTask> {
+        peers.clear();
+        peers.extend(candidates.iter()
+            .map(|&index| &threads[index])
+            .map(|t| Peer::new(t.id, t.queue.len(), t.node.get().copied())));
+
+        for _ in 0..candidates.len() {
+            let Some(target) = self.steal_strategy.pick(worker_id, peers) else {
+                break;
+            };
+
+            let Some(target_worker) = candidates.get(target).map(|&index| &threads[index]) else {
+                continue;
+            };
+
+            if target_worker.id == worker_id {
+                continue;
+            }
+
+            match target_worker.queue.steal() {
+                Steal::Empty | Steal::Retry => {
+                    tracing_feat!(trace!("Worker {worker_id} failed to steal a task from worker {}", target_worker.id));
+                    peers[target].queue_len = target_worker.queue.len();
+                },
+                Steal::Success(task) => {
+                    tracing_feat!(trace!("Worker {worker_id} stole a task from worker {}", target_worker.id));
+                    StatCounters::bump(&self.stats.steals_succeeded);
+                    self.hooks.call_on_steal_fn(worker_id, target_worker.id);
+                    return Some(task);
                 }
-            })
+            }
+        }
+
+        None
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_nanos(self.timeout.load(Ordering::Relaxed))
+    }
+
+    pub fn set_timeout(&self, timeout: Duration) {
+        self.timeout.store(duration_to_nanos(timeout), Ordering::Relaxed);
+    }
+
+    /// Cancels all the tasks waiting in the global injector and the partitions.
+    pub fn cancel_injected(&self) {
+        while let Some(task) = steal_task(&self.injector) {
+            task.cancel();
+        }
+
+        // taken one by one, so cancelling doesn't happen under the lock
+        while let Some(task) = self.take_low_priority() {
+            task.cancel();
+        }
+
+        self.partitions.read()
+            .unwrap_or_else(|s| s.into_inner())
+            .iter()
+            .for_each(|partition| partition.cancel_queued());
+    }
+
+    /// Gets the partition with the given name, creating it with `config` if it doesn't exist.
+    pub fn partition(&self, name: &str, config: PartitionConfig) -> Arc<PartitionInner> {
+        let mut partitions = self.partitions.write().unwrap_or_else(|s| s.into_inner());
+
+        if let Some(partition) = partitions.iter().find(|p| p.name() == name) {
+            return Arc::clone(partition);
+        }
+
+        let partition = Arc::new(PartitionInner::new(name, config));
+        partitions.push(Arc::clone(&partition));
+        partition
+    }
+
+    /// Queues a task into the given partition, waking or spawning a worker to run it.
+    pub fn spawn_partitioned(&self, partition: &PartitionInner, task: TypeErasedTask) {
+        self.accept(&task);
+        tracing_feat!(trace!("Task {} spawned into partition {}", task.header().id, partition.name()));
+
+        if self.should_stop() {
+            tracing_feat!(trace!("Threadpool stopped, cancelling task"));
+            task.cancel();
+            return;
+        }
+
+        partition.push(task);
+
+        if self.should_spawn_thread() {
+            self.spawn_thread_on_demand(None);
+        } else {
+            self.notify_worker();
+        }
+    }
+
+    /// Queues a task spawned with low priority.
+    pub fn spawn_low_priority(&self, task: TypeErasedTask) {
+        self.accept(&task);
+        tracing_feat!(trace!("Task {} enqueued with low priority", task.header().id));
+
+        if self.should_stop() {
+            tracing_feat!(trace!("Threadpool stopped, cancelling task"));
+            task.cancel();
+            return;
+        }
+
+        {
+            let mut queue = self.lock_low_priority();
+            queue.push_back(task);
+            self.low_priority_len.store(queue.len(), Ordering::SeqCst);
+        }
+
+        if self.should_spawn_thread() {
+            self.spawn_thread_on_demand(None);
+        } else {
+            self.notify_worker();
+        }
+    }
+
+    /// Takes the oldest low priority task if it waited for longer than the starvation
+    /// threshold, so it runs ahead of high priority ones.
+    pub fn take_starved(&self) -> Option<TypeErasedTask> {
+        if !self.has_low_priority() {
+            return None;
+        }
+
+        let mut queue = self.lock_low_priority();
+        let starved = queue.front()?
+            .header()
+            .enqueued_at
+            .elapsed()
+            .is_some_and(|waited| waited >= self.starvation_threshold);
+
+        if starved { self.pop_low_priority(&mut queue) } else { None }
+    }
+
+    /// Takes the oldest low priority task.
+    pub fn take_low_priority(&self) -> Option<TypeErasedTask> {
+        if !self.has_low_priority() {
+            return None;
+        }
+
+        self.pop_low_priority(&mut self.lock_low_priority())
+    }
+
+    fn pop_low_priority(&self, queue: &mut VecDeque<TypeErasedTask>) -> Option<TypeErasedTask> {
+        let task = queue.pop_front();
+        self.low_priority_len.store(queue.len(), Ordering::SeqCst);
+        task
+    }
+
+    /// Whether there are low priority tasks queued, without taking the lock.
+    fn has_low_priority(&self) -> bool {
+        self.low_priority_len.load(Ordering::SeqCst) > 0
+    }
+
+    fn lock_low_priority(&self) -> std::sync::MutexGuard<'_, VecDeque<TypeErasedTask>> {
+        self.low_priority.lock().unwrap_or_else(|s| s.into_inner())
+    }
+
+    /// Wakes a parked worker, if any.
+    pub fn notify_worker(&self) {
+        self.condvar.notify_one_locked();
+    }
+
+    /// Takes a task from the next partition with queued tasks and room to run them,
+    /// along with the slot to release once it has run.
+    pub fn take_partitioned(&self) -> Option<(TypeErasedTask, PartitionSlot)> {
+        let partitions = self.partitions.read().unwrap_or_else(|s| s.into_inner());
+
+        if partitions.is_empty() {
+            return None;
+        }
+
+        let start = self.partition_cursor.fetch_add(1, Ordering::Relaxed);
+
+        (0..partitions.len())
+            .map(|offset| &partitions[(start + offset) % partitions.len()])
+            .find_map(|partition| partition.take(self))
+    }
+
+    /// Whether any partition has a task that can run right away.
+    fn has_runnable_partition(&self) -> bool {
+        self.partitions.read()
+            .unwrap_or_else(|s| s.into_inner())
+            .iter()
+            .any(|partition| partition.is_runnable())
     }
 
     pub fn should_stop(&self) -> bool {
@@ -236,51 +1472,270 @@ impl Core {
         }
     }
 
+    pub fn acquire_handle(&self) {
+        self.handles.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Releases a user handle, returning whether it was the last one.
+    pub fn release_handle(&self) -> bool {
+        let previous = self.handles.fetch_sub(1, Ordering::SeqCst);
+        debug_assert!(previous > 0, "handles counter underflowed");
+        previous == 1
+    }
+
+    /// Tells the workers to stop, waking the parked ones, without waiting for them.
+    /// The stop is deferred until the tasks registered with [`Core::await_on_shutdown`]
+    /// finish, unless the threadpool is paused.
+    pub fn begin_shutdown(&self) {
+        self.shutdown_pending.store(true, Ordering::SeqCst);
+        self.try_finish_shutdown();
+    }
+
+    /// Stops the threadpool if a shutdown is pending and no awaited task is left, or the
+    /// threadpool is paused, so the ones that didn't start can't run anymore.
+    pub fn try_finish_shutdown(&self) {
+        let mut awaited = self.awaited_on_shutdown.lock().unwrap_or_else(|s| s.into_inner());
+        awaited.retain(|task| !task.is_finished());
+
+        if !awaited.is_empty() && !self.is_paused() {
+            return;
+        }
+
+        // checked under the lock, so only one caller stops the threadpool
+        if !self.shutdown_pending.swap(false, Ordering::SeqCst) {
+            return;
+        }
+
+        awaited.clear();
+        drop(awaited);
+
+        self.set_stop(true);
+        self.condvar.notify_all_locked();
+        self.slot_cv.notify_all_locked();
+        self.pause_cv.notify_all_locked();
+        self.blocking.shutdown();
+        // no worker may be left to exit and notify it
+        self.notify_stopped();
+    }
+
+    /// Makes the shutdown wait for the given task to finish. The task wakes the threadpool
+    /// once it finishes, so a pending shutdown goes on without polling it.
+    pub fn await_on_shutdown(&self, task: AbortHandle) {
+        let header = task.header();
+        let waker = Waker::from(Arc::new(AwaitedTaskWaker::new(self)));
+        header.parker().lock().unwrap_or_else(|s| s.into_inner()).set_waker(self as *const Self as usize, waker);
+
+        let finished = task.is_finished();
+        if !finished {
+            self.awaited_on_shutdown.lock().unwrap_or_else(|s| s.into_inner()).push(task);
+        }
+
+        // the task may have finished before the waker was registered, or meanwhile
+        if finished || self.shutdown_pending.load(Ordering::SeqCst) {
+            self.try_finish_shutdown();
+        }
+    }
+
+    /// Waits for a pending shutdown to stop the threadpool, once the awaited tasks finish,
+    /// and for all the workers to exit. Workers run their `on_stop` hook before removing
+    /// themselves, so every hook has completed once this returns.
     pub fn wait_stop(&self) {
-        let all_stopped = || {
-            self.threads.read().unwrap().len() == 0
-        };
+        self.shutdown_cv.wait_until(|| self.all_stopped());
+        self.blocking.join_threads();
 
-        while !all_stopped() {
-            self.shutdown_cv.wait_no_timeout();
+        let sampler = self.sampler.lock().unwrap_or_else(|s| s.into_inner()).take();
+        if let Some(sampler) = sampler.filter(|s| s.thread().id() != std::thread::current().id()) {
+            let _ = sampler.join();
+        }
+    }
+
+    /// Blocks until the threadpool stops or the timeout elapses.
+    pub fn sleep_unless_stopped(&self, timeout: Duration) {
+        self.shutdown_cv.wait_timeout_unless(timeout, || self.should_stop());
+    }
+
+    /// Like [`Core::wait_stop`], but gives up once the timeout elapses, returning
+    /// whether all the workers exited.
+    pub fn wait_stop_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+
+        while !self.all_stopped() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if remaining.is_zero() {
+                return false;
+            }
+
+            self.shutdown_cv.wait_timeout_unless(remaining, || self.all_stopped());
+        }
+
+        true
+    }
+
+    /// Whether the threadpool stopped and all the workers have exited.
+    pub fn all_stopped(&self) -> bool {
+        self.should_stop() && self.lock_threads_read().is_empty()
+    }
+
+    /// Registers a waker to be woken once all the workers have exited.
+    pub fn register_stop_waker(&self, waker: &Waker) {
+        let mut wakers = self.stop_wakers.lock().unwrap_or_else(|s| s.into_inner());
+
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
         }
     }
 
     /// Parks the caller thread until a task is made available or it exceeds
     /// its timeout lifespan. Returns whether the park has timed out
-    pub fn park(&self) -> bool {
+    ///
+    /// Dedicated stealers are also woken once a local queue gets tasks to steal.
+    pub fn park(&self, inbox: &Injector<TypeErasedTask>, stealer: bool) -> bool {
         self.leave_working();
         self.enter_idle();
+
+        // announced before checking the queues, so either the stealer sees the tasks
+        // or whoever pushed them sees the stealer parked
+        if stealer {
+            self.parked_stealers.fetch_add(1, Ordering::SeqCst);
+        }
+
+        defer!(|| {
+            if stealer {
+                self.parked_stealers.fetch_sub(1, Ordering::SeqCst);
+            }
+
+            self.leave_idle();
+
+            // a hook panicked, go back to the working state the worker exits from
+            if std::thread::panicking() {
+                self.enter_working();
+            }
+        });
+
         self.hooks.call_on_park_fn();
-        let res = self.condvar.wait_timeout(self.timeout);
+        // new tasks and the shutdown are only announced once, so check for them
+        // under the condvar lock
+        let res = self.condvar.wait_timeout_unless(self.timeout(), || {
+            !inbox.is_empty() || !self.injector.is_empty() || self.should_stop() || self.has_runnable_partition()
+                || self.has_low_priority() || (stealer && self.has_stealable_tasks())
+            || self.reap_requests.load(Ordering::SeqCst) > 0
+        });
         self.hooks.call_on_unpark_fn();
 
-        if !res {
+        if res {
+            StatCounters::bump(&self.stats.threads_reaped);
+        } else {
             // only enter working state if we got a wakeup for a task
             self.enter_working();
         }
 
-        // leave idle either way, if we didnt get work, we will just kill the worker
-        self.leave_idle();
-        
+        // leaves idle either way when returning, if we didnt get work, we will just kill the worker
         res
     }
 }
 
+impl Core {
+    pub fn as_ptr(&self) -> *const CoreInner {
+        Arc::as_ptr(&self.0)
+    }
+
+    pub fn downgrade(&self) -> Weak<CoreInner> {
+        Arc::downgrade(&self.0)
+    }
+
+    pub fn upgrade(weak: &Weak<CoreInner>) -> Option<Self> {
+        weak.upgrade().map(Self)
+    }
+}
+
 impl Deref for Core {
     type Target = CoreInner;
 
     fn deref(&self) -> &Self::Target {
-        &*self.0
+        &self.0
+    }
+}
+
+/// Permit of a task counted against the concurrent tasks limit of its threadpool, given
+/// back when dropped.
+pub struct TaskPermit(Core);
+
+impl Drop for TaskPermit {
+    fn drop(&mut self) {
+        HELD_PERMITS.with_borrow_mut(|held| {
+            if let Some(index) = held.iter().rposition(|core| core.as_ptr() == self.0.as_ptr()) {
+                held.remove(index);
+            }
+        });
+
+        self.0.release_task_permit();
     }
 }
 
+/// Gives back the task permits held by the current thread while it blocks waiting on
+/// something else, like joining a task, so blocked tasks don't keep the ones they wait
+/// on from running. The permits are taken again once the returned guard is dropped.
+pub(crate) fn suspend_task_permits() -> Option<impl Drop + use<>> {
+    let held = HELD_PERMITS.with_borrow_mut(std::mem::take);
+
+    if held.is_empty() {
+        return None;
+    }
+
+    held.iter().for_each(|core| core.release_task_permit());
+
+    Some(crate::defer::Defer::new(move || {
+        held.iter().for_each(|core| core.claim_task_permit());
+        HELD_PERMITS.with_borrow_mut(|current| *current = held);
+    }))
+}
+
+/// Buffers a worker reuses across steal attempts, so looking for a victim doesn't allocate.
+#[derive(Default)]
+pub struct StealScratch {
+    /// Indexes of the workers that can be stolen from
+    all: Vec<usize>,
+    /// Indexes of the ones on the same NUMA node as the stealer
+    local: Vec<usize>,
+    /// Workers offered to the steal strategy
+    peers: Vec<Peer>,
+    /// Whether a steal failed since the worker last ran a task
+    pub failed: bool,
+}
+
+/// Takes a task from the given injector, retrying while the steal is contended.
+pub(crate) fn steal_task(injector: &Injector<TypeErasedTask>) -> Option<TypeErasedTask> {
+    loop {
+        match injector.steal() {
+            Steal::Success(task) => return Some(task),
+            Steal::Retry => continue,
+            Steal::Empty => return None
+        }
+    }
+}
+
+/// Converts the duration to nanoseconds, saturating if it doesn't fit in a `u64`.
+pub(crate) fn duration_to_nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
 struct ThreadInfo {
     /// The thread stealer that will be used to steal tasks from its local queue
     queue: Stealer<TypeErasedTask>,
+    /// Queue of tasks placed directly on this worker, which can't be stolen
+    inbox: Arc<Injector<TypeErasedTask>>,
     /// The thread handle
     #[allow(unused)]
     handle: JoinHandle<()>,
+    /// NUMA node the worker runs on, if known
+    node: Arc<OnceLock<usize>>,
+    /// Nanoseconds the worker spent running tasks
+    busy_nanos: Arc<AtomicU64>,
+    /// Id of the task the worker is blocked joining, if any
+    waiting_on: Arc<AtomicU64>,
+    /// Whether the worker is a dedicated stealer
+    stealer: bool,
     /// Thread id
     id: usize
 }
@@ -0,0 +1,184 @@
+use std::{future::Future, panic::{catch_unwind, resume_unwind, AssertUnwindSafe}, pin::Pin, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, task::{Context, Poll, Wake, Waker}};
+
+use crate::{core::Core, join::JoinHandle, task::{state::State, Task, TypeErasedTask}};
+
+type Output<F> = Mutex<Option<std::thread::Result<<F as Future>::Output>>>;
+
+/// Drives a future spawned into the threadpool, polling it from the workers
+/// each time it gets woken up.
+struct FutureDriver<F: Future> {
+    core: Core,
+    future: Mutex<Option<Pin<Box<F>>>>,
+    /// Task in charge of handing the output to the handle, ran once the future completes.
+    completion: Mutex<Option<TypeErasedTask>>,
+    /// Output produced by the future, taken by the completion task.
+    output: Arc<Output<F>>,
+    /// Whether a poll of the future is already enqueued
+    scheduled: AtomicBool,
+}
+
+impl<F> FutureDriver<F>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static
+{
+    /// Enqueues a poll of the future at the back of the global injector,
+    /// unless there is one already enqueued.
+    fn schedule(self: Arc<Self>) {
+        if self.scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let core = self.core.clone();
+        core.inject_task(poll_task(self));
+    }
+
+    fn poll(self: Arc<Self>) {
+        self.scheduled.store(false, Ordering::Release);
+
+        let mut future = self.future.lock().unwrap_or_else(|l| l.into_inner());
+        let Some(fut) = future.as_mut() else {
+            return;
+        };
+
+        if self.is_aborted() {
+            // nobody will take the output, so drop the future right away
+            *future = None;
+            return;
+        }
+
+        let waker = Waker::from(Arc::clone(&self));
+        let mut cx = Context::from_waker(&waker);
+
+        let output = match catch_unwind(AssertUnwindSafe(|| fut.as_mut().poll(&mut cx))) {
+            Ok(Poll::Pending) => return,
+            Ok(Poll::Ready(output)) => Ok(output),
+            Err(payload) => Err(payload)
+        };
+
+        *future = None;
+        drop(future);
+
+        *self.output.lock().unwrap_or_else(|l| l.into_inner()) = Some(output);
+
+        // the polls aren't counted, the future completes along with this task instead
+        let completion = self.completion.lock().unwrap_or_else(|l| l.into_inner()).take();
+        if let Some(task) = completion {
+            let state = task.run();
+            self.core.record_outcome(state);
+            self.core.task_completed(state);
+        }
+    }
+
+    fn is_aborted(&self) -> bool {
+        self.completion.lock()
+            .unwrap_or_else(|l| l.into_inner())
+            .as_ref()
+            .is_none_or(|task| task.header().state.get(State::ABORTED))
+    }
+}
+
+impl<F> Wake for FutureDriver<F>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static
+{
+    fn wake(self: Arc<Self>) {
+        self.schedule();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        Arc::clone(self).schedule();
+    }
+}
+
+impl<F: Future> Drop for FutureDriver<F> {
+    fn drop(&mut self) {
+        // the future won't be polled anymore, most likely because the threadpool stopped
+        let completion = self.completion.get_mut().unwrap_or_else(|l| l.into_inner()).take();
+        if let Some(task) = completion {
+            task.cancel();
+        }
+    }
+}
+
+/// Task polling the future once, left out of the stats as it's just a step of the future.
+fn poll_task<F>(driver: Arc<FutureDriver<F>>) -> TypeErasedTask
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static
+{
+    let task = Task::new(move || driver.poll()).erase();
+    task.header().state.set(State::INTERNAL, true);
+    task
+}
+
+pub(crate) fn spawn_future<F>(core: &Core, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static
+{
+    let output = Arc::new(Mutex::new(None));
+
+    let completion = Task::new({
+        let output = Arc::clone(&output);
+
+        move || {
+            let output = output.lock()
+                .unwrap_or_else(|l| l.into_inner())
+                .take()
+                .expect("Completion task ran before the future finished");
+
+            match output {
+                Ok(output) => output,
+                Err(payload) => resume_unwind(payload)
+            }
+        }
+    }).erase();
+
+    core.attach_result_sink(completion.header());
+    let handle = JoinHandle::new(completion.header);
+
+    let driver = Arc::new(FutureDriver {
+        core: core.clone(),
+        future: Mutex::new(Some(Box::pin(future))),
+        completion: Mutex::new(Some(completion)),
+        output,
+        scheduled: AtomicBool::new(true),
+    });
+
+    core.spawn_task(poll_task(driver));
+
+    handle
+}
+
+/// Future returned by [`reschedule`].
+#[must_use = "futures do nothing unless polled"]
+pub struct Reschedule {
+    yielded: bool
+}
+
+impl Future for Reschedule {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// Yields the current future back to the threadpool. Futures spawned with
+/// [`Planetary::spawn_future`] are placed at the back of the global queue, so other
+/// tasks get the chance to run before they resume.
+///
+/// [`Planetary::spawn_future`]: crate::handle::Planetary::spawn_future
+pub fn reschedule() -> Reschedule {
+    Reschedule {
+        yielded: false
+    }
+}
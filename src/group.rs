@@ -0,0 +1,92 @@
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+
+use crate::{handle::Planetary, join::JoinHandle, task::{IntoRunnable, Runnable}, JoinResult};
+
+/// Group of tasks that can be aborted or joined together, created with [`Planetary::group`].
+///
+/// Aborting the group aborts the tasks that didn't start yet, running tasks can check
+/// [`TaskGroup::abort_flag`] to stop early.
+///
+/// [`Planetary::group`]: crate::handle::Planetary::group
+pub struct TaskGroup<T> {
+    pool: Planetary,
+    handles: Vec<JoinHandle<T>>,
+    aborted: Arc<AtomicBool>,
+    abort_on_drop: bool,
+}
+
+impl<T> TaskGroup<T> {
+    pub(crate) fn new(pool: Planetary) -> Self {
+        Self {
+            pool,
+            handles: Vec::new(),
+            aborted: Arc::new(AtomicBool::new(false)),
+            abort_on_drop: false,
+        }
+    }
+
+    /// Sets whether dropping the group aborts its tasks, disabled by default.
+    pub fn abort_on_drop(mut self, enabled: bool) -> Self {
+        self.abort_on_drop = enabled;
+        self
+    }
+
+    /// Spawns a new [`Runnable`] into the threadpool as part of the group. Tasks spawned
+    /// after the group was aborted are aborted right away.
+    pub fn spawn<M, F>(&mut self, runnable: F)
+    where
+        F: IntoRunnable<M>,
+        F::Runnable: Runnable<Output = T>
+    {
+        let handle = self.pool.spawn(runnable);
+
+        if self.is_aborted() {
+            handle.abort();
+        }
+
+        self.handles.push(handle);
+    }
+
+    /// Aborts every task of the group.
+    pub fn abort_all(&self) {
+        self.aborted.store(true, Ordering::Release);
+        self.handles.iter().for_each(JoinHandle::abort);
+    }
+
+    /// Whether [`TaskGroup::abort_all`] was called on the group.
+    pub fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::Acquire)
+    }
+
+    /// Flag set once the group is aborted, to be checked by its running tasks.
+    pub fn abort_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.aborted)
+    }
+
+    /// Number of tasks spawned into the group.
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// Whether no task was spawned into the group.
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    /// Waits for every task of the group to finish, returning their outputs in the order
+    /// they were spawned.
+    pub fn join_all(mut self) -> Vec<JoinResult<T>> {
+        std::mem::take(&mut self.handles)
+            .into_iter()
+            .map(JoinHandle::join)
+            .collect()
+    }
+}
+
+impl<T> Drop for TaskGroup<T> {
+    fn drop(&mut self) {
+        if self.abort_on_drop {
+            self.abort_all();
+        }
+    }
+}
@@ -1,12 +1,17 @@
-use crate::{core::Core, join::JoinHandle, task::{Runnable, Task}};
+use std::{any::Any, fmt::Debug, hash::Hash, ptr::NonNull, panic::{catch_unwind, AssertUnwindSafe}, sync::{Arc, Weak}, thread, time::{Duration, Instant}};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+
+use crate::{JoinResult, barrier::CompletionBarrier, channel::{ResultChannel, ToChannel}, defer, builder::{PoolConfig, SpawnPolicy}, core::{Core, CoreInner}, group::TaskGroup, join::{JoinError, JoinHandle, WeakJoinHandle}, latency::LatencyStats, local::WorkerLocal, macros::tracing_feat, metrics::{CumulativeStats, Metrics}, partition::{Partition, PartitionConfig}, shutdown::ShutdownWaiter, task::{Finalized, Header, IntoRunnable, RunnableOutput, Task, TaskStorage, TypeErasedTask}};
 
 pub(crate) mod sealed {
     use std::cell::RefCell;
 
-    use crate::handle::Planetary;
+    use crate::{core::Core, handle::Planetary};
 
     thread_local! {
-        static HANDLE: RefCell<Option<Planetary>> = RefCell::new(None);
+        // stores the core rather than a handle, so it doesn't keep the pool running
+        static HANDLE: RefCell<Option<Core>> = const { RefCell::new(None) };
     }
 
     pub fn get_handle() -> Planetary {
@@ -14,10 +19,14 @@ pub(crate) mod sealed {
     }
 
     pub fn try_get_handle() -> Option<Planetary> {
+        try_get_core().map(Planetary::new)
+    }
+
+    pub fn try_get_core() -> Option<Core> {
         HANDLE.with(|h| h.borrow().clone())
     }
 
-    pub fn set_handle(new_handle: Planetary) -> Option<Planetary> {
+    pub fn set_handle(new_handle: Core) -> Option<Core> {
         HANDLE.with(|h| h.borrow_mut().replace(new_handle))
     }
 
@@ -26,26 +35,842 @@ pub(crate) mod sealed {
     }
 }
 
+/// Where a task was placed when spawned, reported by [`Planetary::spawn_traced`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    /// Pushed into the local queue of the worker that spawned it.
+    LocalQueue,
+    /// Pushed into the global injector.
+    Injector,
+    /// Handed to a newly spawned worker thread.
+    NewThread,
+    /// Executed right away by a worker running its `on_stop` hook, by the caller of a
+    /// saturated threadpool using [`SaturationPolicy::CallerRuns`], or by the caller
+    /// starting a new thread with [`eager_caller_run`] enabled.
+    ///
+    /// [`SaturationPolicy::CallerRuns`]: crate::builder::SaturationPolicy::CallerRuns
+    /// [`eager_caller_run`]: crate::builder::PlanetaryBuilder::eager_caller_run
+    Inline,
+    /// Cancelled because the threadpool is shutting down.
+    Cancelled,
+    /// Cancelled because the threadpool is saturated and uses [`SaturationPolicy::Reject`].
+    ///
+    /// [`SaturationPolicy::Reject`]: crate::builder::SaturationPolicy::Reject
+    Rejected,
+}
+
+/// Priority of a task, see [`Planetary::spawn_with_priority`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Scheduled like any other task.
+    #[default]
+    High,
+    /// Only run when workers find nothing else to do, or once it waited for longer than
+    /// the [starvation threshold](crate::builder::PlanetaryBuilder::starvation_threshold).
+    Low,
+}
+
 /// The handle to an instance of a planetary threadpool,
 /// can be used to interact with it and spawn tasks.
-#[derive(Clone)]
+///
+/// Cloning a handle is cheap, as all the clones share the same threadpool.
+/// Once every handle is dropped the threadpool shuts down by itself, without
+/// waiting for the workers to exit.
 pub struct Planetary {
     pub(crate) inner: Core
 }
 
 impl Planetary {
+    pub(crate) fn new(inner: Core) -> Self {
+        inner.acquire_handle();
+
+        Self {
+            inner
+        }
+    }
+
     /// Creates a new (builder)[`crate::builder::PlanetaryBuilder`]
     pub fn builder() -> crate::builder::PlanetaryBuilder {
         crate::builder::PlanetaryBuilder::new()
     }
 
-    /// Spawns a new [`Runnable`] into the threadpool, returning a handle to interact with it.
-    pub fn spawn<F: Runnable>(&self, runnable: F) -> JoinHandle<F::Output> {
+    /// Spawns a new [`Runnable`](crate::task::Runnable) into the threadpool, returning a handle to interact with it.
+    pub fn spawn<M, F: IntoRunnable<M>>(&self, runnable: F) -> JoinHandle<RunnableOutput<F, M>> {
+        self.submit(Task::new(runnable).erase())
+    }
+
+    /// Spawns a new [`Runnable`](crate::task::Runnable) into the threadpool with a name,
+    /// which tells it apart when inspecting the threadpool.
+    pub fn spawn_named<M, F: IntoRunnable<M>>(&self, name: impl Into<Box<str>>, runnable: F) -> JoinHandle<RunnableOutput<F, M>> {
+        self.submit(Task::new(runnable).with_name(name).erase())
+    }
+
+    /// Spawns a new [`Runnable`](crate::task::Runnable) into the threadpool, returning a
+    /// [`WeakJoinHandle`] that doesn't keep its output alive, so fire and forget tasks
+    /// can still be joined while someone cares about their output.
+    pub fn spawn_weak<M, F: IntoRunnable<M>>(&self, runnable: F) -> WeakJoinHandle<RunnableOutput<F, M>> {
+        self.spawn(runnable).downgrade()
+    }
+
+    /// Spawns a closure into the threadpool along with a context captured on the spawning
+    /// thread, like a tracing span or a tenant id, which the closure can read while it runs
+    /// through [`current_context`](crate::current_context). Tasks spawned from it don't
+    /// inherit the context.
+    pub fn spawn_with_context<C, F, R>(&self, ctx: C, f: F) -> JoinHandle<R>
+    where
+        C: Send + 'static,
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static
+    {
+        self.submit(Task::new(f).with_context(Box::new(ctx)).erase())
+    }
+
+    /// Spawns a new [`Runnable`](crate::task::Runnable) into the threadpool, giving it back if the threadpool
+    /// is saturated and uses [`SaturationPolicy::Reject`]. Otherwise behaves like
+    /// [`Planetary::spawn`].
+    ///
+    /// Workers may get busy between the check and the spawn, in which case the task
+    /// is still rejected and its handle reports [`JoinError::Cancelled`].
+    ///
+    /// [`SaturationPolicy::Reject`]: crate::builder::SaturationPolicy::Reject
+    pub fn try_spawn<M, F: IntoRunnable<M>>(&self, runnable: F) -> Result<JoinHandle<RunnableOutput<F, M>>, F> {
+        if self.inner.rejects_spawn(self.inner.spawn_policy()) {
+            return Err(runnable);
+        }
+
+        Ok(self.spawn(runnable))
+    }
+
+    /// Spawns a new [`Runnable`](crate::task::Runnable) into the threadpool, placing it according to `policy`
+    /// instead of the default [`SpawnPolicy`] of the threadpool.
+    pub fn spawn_with_policy<M, F: IntoRunnable<M>>(&self, policy: SpawnPolicy, runnable: F) -> JoinHandle<RunnableOutput<F, M>> {
+        self.submit_with_policy(Task::new(runnable).erase(), policy)
+    }
+
+    /// Spawns a closure receiving its own clone of `arc`, saving the caller from cloning
+    /// it before moving it into the closure.
+    pub fn spawn_with_arc<T, F, R>(&self, arc: &Arc<T>, f: F) -> JoinHandle<R>
+    where
+        T: Send + Sync + 'static,
+        F: FnOnce(Arc<T>) -> R + Send + 'static,
+        R: Send + 'static
+    {
+        let arc = Arc::clone(arc);
+        self.spawn(move || f(arc))
+    }
+
+    /// Spawns a new [`Runnable`](crate::task::Runnable) into the threadpool with the given
+    /// priority. High priority tasks are spawned like with [`Planetary::spawn`].
+    pub fn spawn_with_priority<M, F: IntoRunnable<M>>(&self, priority: Priority, runnable: F) -> JoinHandle<RunnableOutput<F, M>> {
+        match priority {
+            Priority::High => self.spawn(runnable),
+            Priority::Low => {
+                let task = Task::new(runnable).erase();
+                self.inner.attach_result_sink(task.header());
+                // created before the task is visible to the workers, see `Planetary::submit`
+                let handle = JoinHandle::new(task.header);
+                self.inner.spawn_low_priority(task);
+
+                handle
+            }
+        }
+    }
+
+    /// Spawns a closure whose panic is turned into an output by `map_panic`, so its handle
+    /// never fails with [`JoinError::Panic`]. A panic in `map_panic` itself is still
+    /// reported as such.
+    pub fn spawn_catch<F, R, M>(&self, f: F, map_panic: M) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+        M: FnOnce(Box<dyn Any + Send>) -> R + Send + 'static
+    {
+        self.spawn(move || catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(map_panic))
+    }
+
+    /// Spawns a closure returning a [`Result`], reporting its errors to the on_task_error
+    /// hook in addition to returning them through the handle.
+    pub fn spawn_fallible<F, T, E>(&self, f: F) -> JoinHandle<Result<T, E>>
+    where
+        F: FnOnce() -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: Debug + Send + 'static
+    {
+        // weak, so queued tasks don't keep the pool alive
+        let core = self.inner.downgrade();
+
+        self.spawn(move || {
+            let res = f();
+
+            if let (Err(e), Some(core)) = (&res, core.upgrade()) {
+                core.hooks.call_on_task_error_fn(e);
+            }
+
+            res
+        })
+    }
+
+    /// Spawns a new [`Runnable`](crate::task::Runnable) into the threadpool, running
+    /// `finalizer` with a reference to its outcome once it completes, even if it panicked,
+    /// so cleanup doesn't depend on someone holding the handle. The finalizer runs on the
+    /// worker that executed the task, before the handle gets the output.
+    ///
+    /// If the task is discarded before it starts, because it was aborted or cancelled,
+    /// the finalizer runs with [`JoinError::Aborted`] wherever it was discarded.
+    pub fn spawn_with_finalizer<M, F, G>(&self, runnable: F, finalizer: G) -> JoinHandle<RunnableOutput<F, M>>
+    where
+        F: IntoRunnable<M>,
+        G: FnOnce(&JoinResult<RunnableOutput<F, M>>) + Send + 'static
+    {
+        self.spawn(Finalized::new(runnable.into_runnable(), finalizer))
+    }
+
+    /// Spawns a new [`Runnable`](crate::task::Runnable) into the threadpool, calling the
+    /// on_slow_task hook if it's still running once `soft_limit` elapsed since it started.
+    /// Unlike a deadline, the task is left running.
+    pub fn spawn_timed<M, F: IntoRunnable<M>>(&self, soft_limit: Duration, runnable: F) -> JoinHandle<RunnableOutput<F, M>> {
+        let handle = self.spawn(runnable);
+        self.inner.watch_slow_task(handle.abort_handle(), soft_limit);
+        handle
+    }
+
+    /// Spawns a new [`Runnable`](crate::task::Runnable) into the threadpool, aborting it if it's
+    /// still running once `limit` elapsed since it started. Joining it then returns
+    /// [`JoinError::TimedOut`] right away.
+    ///
+    /// Tasks can't be preempted, so this is best-effort: tasks checking [`is_aborted`] can
+    /// stop early, but the ones ignoring it keep running in the background, occupying
+    /// their worker, and their output is discarded.
+    ///
+    /// [`is_aborted`]: crate::is_aborted
+    pub fn spawn_hard_timeout<M, F: IntoRunnable<M>>(&self, limit: Duration, runnable: F) -> JoinHandle<RunnableOutput<F, M>> {
+        let handle = self.spawn(runnable);
+        self.inner.watch_hard_timeout(handle.abort_handle(), limit);
+        handle
+    }
+
+    /// Splits `data` into chunks of `chunk_size` elements, the last one being smaller if
+    /// the length is not a multiple of it, and spawns a task applying `f` to each chunk.
+    /// Returns a handle per chunk, in the order of the data.
+    ///
+    /// Panics if `chunk_size` is zero.
+    pub fn spawn_chunks<T, F, R>(&self, data: Vec<T>, chunk_size: usize, f: F) -> Vec<JoinHandle<Vec<R>>>
+    where
+        T: Send + 'static,
+        F: Fn(&[T]) -> Vec<R> + Send + Sync + Clone + 'static,
+        R: Send + 'static
+    {
+        assert!(chunk_size > 0, "chunk size must be non-zero");
+
+        let mut handles = Vec::with_capacity(data.len().div_ceil(chunk_size));
+        let mut data = data.into_iter();
+
+        loop {
+            let chunk = data.by_ref().take(chunk_size).collect::<Vec<_>>();
+
+            if chunk.is_empty() {
+                return handles;
+            }
+
+            let f = f.clone();
+            handles.push(self.spawn(move || f(&chunk)));
+        }
+    }
+
+    /// Spawns a new [`Runnable`](crate::task::Runnable) on the worker with the given id, which will be the only
+    /// one executing it. Falls back to [`Planetary::spawn`] if there is no live worker
+    /// with that id or it's a [dedicated stealer], use [`Planetary::try_spawn_on`] to handle that case instead.
+    ///
+    /// [dedicated stealer]: crate::builder::PlanetaryBuilder::dedicated_stealers
+    pub fn spawn_on<M, F: IntoRunnable<M>>(&self, worker_id: usize, runnable: F) -> JoinHandle<RunnableOutput<F, M>> {
+        match self.inner.try_spawn_on(worker_id, runnable) {
+            Ok(handle) => handle,
+            Err(runnable) => self.spawn(runnable)
+        }
+    }
+
+    /// Spawns a new [`Runnable`](crate::task::Runnable) on the worker with the given id, giving the runnable
+    /// back if there is no live worker with that id or it's a [dedicated stealer].
+    ///
+    /// [dedicated stealer]: crate::builder::PlanetaryBuilder::dedicated_stealers
+    pub fn try_spawn_on<M, F: IntoRunnable<M>>(&self, worker_id: usize, runnable: F) -> Result<JoinHandle<RunnableOutput<F, M>>, F> {
+        self.inner.try_spawn_on(worker_id, runnable)
+    }
+
+    /// Runs `f` on the worker with the given id and blocks until it returns, e.g. to inspect
+    /// or change its [`WorkerLocal`] values from the outside. Returns `None` if there is no
+    /// live worker with that id, it's a [dedicated stealer], or the threadpool shut down
+    /// before running `f`. When called from that same worker, `f` runs right away.
+    ///
+    /// [dedicated stealer]: crate::builder::PlanetaryBuilder::dedicated_stealers
+    pub fn on_worker<R: Send>(&self, worker_id: usize, f: impl FnOnce() -> R + Send) -> Option<R> {
+        let on_target = crate::worker::try_get_worker().is_some_and(|w| {
+            !w.is_participant() && w.id() == worker_id && w.core().as_ptr() == self.inner.as_ptr()
+        });
+
+        if on_target {
+            return Some(f());
+        }
+
+        let mut output = None;
+        let slot = &mut output;
+        let joined = crate::scope::scope(self, |scope| {
+            scope.try_spawn_on(worker_id, move || *slot = Some(f())).map(JoinHandle::join)
+        });
+
+        match joined? {
+            Ok(()) => output,
+            Err(JoinError::Panic(payload)) => std::panic::resume_unwind(payload),
+            Err(_) => None,
+        }
+    }
+
+    /// Spawns a new [`Runnable`](crate::task::Runnable) into the threadpool, also returning a receiver that
+    /// reports where the task was placed. Useful to check placement expectations.
+    pub fn spawn_traced<M, F: IntoRunnable<M>>(&self, runnable: F) -> (JoinHandle<RunnableOutput<F, M>>, Receiver<Placement>) {
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        let (handle, placement) = self.submit_traced(Task::new(runnable).erase(), self.inner.spawn_policy());
+        let _ = tx.send(placement);
+
+        (handle, rx)
+    }
+
+    /// Spawns a new [`Runnable`](crate::task::Runnable) into the threadpool unless a task
+    /// spawned with the same `key` is still queued or running, in which case it's dropped
+    /// and the returned handle receives a clone of the output of that task instead. Meant
+    /// for idempotent work, like refreshing a cache entry.
+    ///
+    /// Keys only match tasks with the same output type. If the task panics, the handles of
+    /// the duplicates get a panic too, with a generic message.
+    pub fn spawn_keyed<K, M, F>(&self, key: K, runnable: F) -> JoinHandle<RunnableOutput<F, M>>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        F: IntoRunnable<M>,
+        RunnableOutput<F, M>: Clone + Sync
+    {
+        crate::keyed::spawn_keyed(&self.inner, key, runnable.into_runnable())
+    }
+
+    /// Spawns a new [`Runnable`](crate::task::Runnable) into the threadpool that pushes its
+    /// output into `channel` once it completes, blocking the worker while the channel is full.
+    /// The returned handle finishes once the output is in the channel, or reports the panic.
+    pub fn spawn_to_result_channel<M, F: IntoRunnable<M>>(&self, channel: &ResultChannel<RunnableOutput<F, M>>, runnable: F) -> JoinHandle<()> {
+        self.spawn(ToChannel::new(runnable.into_runnable(), channel.sender()))
+    }
+
+    /// Spawns a future into the threadpool, polling it from the worker threads each time
+    /// it gets woken up.
+    pub fn spawn_future<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static
+    {
+        crate::future::spawn_future(&self.inner, future)
+    }
+
+    /// Spawns a new [`Runnable`](crate::task::Runnable) that blocks for long, like file or
+    /// network io, on a separate set of threads so it doesn't keep workers from running
+    /// the rest of the tasks. Blocking threads are spawned on demand up to
+    /// [`max_blocking_threads`], and exit after the [`blocking_timeout`] without work.
+    ///
+    /// Once they are all busy, the task is handled as set with [`blocking_queue_policy`].
+    ///
+    /// [`max_blocking_threads`]: crate::builder::PlanetaryBuilder::max_blocking_threads
+    /// [`blocking_timeout`]: crate::builder::PlanetaryBuilder::blocking_timeout
+    /// [`blocking_queue_policy`]: crate::builder::PlanetaryBuilder::blocking_queue_policy
+    pub fn spawn_blocking<M, F: IntoRunnable<M>>(&self, runnable: F) -> JoinHandle<RunnableOutput<F, M>> {
         let task = Task::new(runnable).erase();
-        let header = task.header;
+        self.inner.attach_result_sink(task.header());
+        let handle = JoinHandle::new(task.header);
+        self.inner.blocking.spawn(&self.inner, task);
+        handle
+    }
+
+    /// Number of threads alive running tasks spawned with [`Planetary::spawn_blocking`].
+    pub fn blocking_threads(&self) -> usize {
+        self.inner.blocking.threads()
+    }
+
+    /// Spawns a new [`Runnable`](crate::task::Runnable) into the threadpool, blocking the caller while all the
+    /// worker threads are busy and no more threads can be spawned.
+    ///
+    /// When called from within a worker thread this behaves like [`Planetary::spawn`],
+    /// as blocking the worker could prevent the pool from ever freeing a slot.
+    pub fn spawn_blocking_slot<M, F: IntoRunnable<M>>(&self, runnable: F) -> JoinHandle<RunnableOutput<F, M>> {
+        if crate::worker::try_get_worker().is_none() {
+            self.inner.wait_for_slot();
+        }
+
+        self.spawn(runnable)
+    }
+
+    /// Like [`Planetary::spawn_blocking_slot`], but waits at most `wait` for a worker to
+    /// free up, giving the runnable back if the threadpool is still saturated by then.
+    ///
+    /// With [`SaturationPolicy::Reject`](crate::builder::SaturationPolicy::Reject), the
+    /// runnable is also given back if the threadpool saturates again before it's spawned.
+    pub fn spawn_blocking_timeout<M, F: IntoRunnable<M>>(&self, runnable: F, wait: Duration) -> Result<JoinHandle<RunnableOutput<F, M>>, F> {
+        if crate::worker::try_get_worker().is_none()
+            && !self.inner.wait_for_slot_until(Instant::now().checked_add(wait))
+        {
+            return Err(runnable);
+        }
+
+        self.try_spawn(runnable)
+    }
+
+    /// Spawns a new [`Runnable`](crate::task::Runnable) into the threadpool, placing the task into the provided
+    /// [`TaskStorage`] instead of allocating it on the heap.
+    ///
+    /// Fails to compile if the task does not fit into `N` bytes. With the `inspect` feature,
+    /// registering the task may still allocate.
+    pub fn spawn_in<const N: usize, M, F: IntoRunnable<M>>(&self, storage: &'static mut TaskStorage<N>, runnable: F) -> JoinHandle<RunnableOutput<F, M>> {
+        self.submit(Task::new(runnable).erase_into(storage))
+    }
+
+    /// Takes the output of a task through a pointer to its header, as returned by
+    /// [`JoinHandle::into_raw`]. Returns `None` if the output is not ready yet or was
+    /// already taken.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be the output type of the task, and the pointer must be kept alive by a
+    /// handle turned into it, which must not be joined or polled while the output is taken.
+    pub unsafe fn take_output_raw<T>(header: NonNull<Header>) -> Option<JoinResult<T>> {
+        let mut output = None;
+
+        unsafe {
+            Header::try_get_output(header, &mut output as *mut Option<JoinResult<T>> as *mut ());
+        }
+
+        output
+    }
+
+    /// Spawns a task built with a [`TaskBuilder`], whose output is received by the
+    /// handle returned by the builder.
+    ///
+    /// [`TaskBuilder`]: crate::task::TaskBuilder
+    pub fn spawn_erased(&self, task: TypeErasedTask) {
+        self.inner.attach_result_sink(task.header());
         self.inner.spawn_task(task);
+    }
+
+    /// Hands an erased task to the threadpool, returning a handle to it.
+    fn submit<T>(&self, task: TypeErasedTask) -> JoinHandle<T> {
+        self.submit_with_policy(task, self.inner.spawn_policy())
+    }
+
+    fn submit_with_policy<T>(&self, task: TypeErasedTask, policy: SpawnPolicy) -> JoinHandle<T> {
+        self.submit_traced(task, policy).0
+    }
+
+    fn submit_traced<T>(&self, task: TypeErasedTask, policy: SpawnPolicy) -> (JoinHandle<T>, Placement) {
+        // create the handle before the task is made available to the workers, otherwise
+        // it could run and be deallocated before the handle marks itself as alive
+        self.inner.attach_result_sink(task.header());
+        let handle = JoinHandle::new(task.header);
+        let placement = self.inner.spawn_task_with_policy(task, policy);
+
+        (handle, placement)
+    }
+
+    /// Aborts every live task of the threadpool for which `pred` returns true, returning
+    /// how many were aborted. Tasks that already started keep running, see [`JoinHandle::abort`].
+    #[cfg(feature = "inspect")]
+    pub fn abort_where(&self, pred: impl Fn(&crate::inspect::TaskInfo) -> bool) -> usize {
+        self.inner.registry.abort_where(pred)
+    }
+
+    /// Pauses the worker threads, which will finish the task they are executing
+    /// and then wait without starting new ones until [`Planetary::resume`] is called.
+    pub fn pause(&self) {
+        self.inner.set_paused(true);
+    }
+
+    /// Resumes the worker threads after a [`Planetary::pause`].
+    pub fn resume(&self) {
+        self.inner.set_paused(false);
+    }
+
+    /// Whether the worker threads are paused.
+    pub fn is_paused(&self) -> bool {
+        self.inner.is_paused()
+    }
+
+    /// Pauses the worker threads, waits for the tasks being executed to finish and runs
+    /// the provided closure while no task is executing, resuming the workers afterwards,
+    /// even if the closure panics.
+    ///
+    /// Panics if called from a worker thread, as it would wait for itself forever.
+    pub fn with_workers_paused<R>(&self, f: impl FnOnce() -> R) -> R {
+        assert!(
+            crate::worker::try_get_worker().is_none(),
+            "with_workers_paused cannot be called from within a worker thread"
+        );
+
+        self.pause();
+        defer!(|| self.resume());
+
+        self.inner.wait_inactive();
+        f()
+    }
+
+    /// Blocks until no worker is in the middle of the `before_work` hook, a task or the
+    /// `after_work` hook, so all the work hooks of the tasks finished so far have completed.
+    /// Unlike [`Planetary::with_workers_paused`], workers keep starting new tasks afterwards.
+    ///
+    /// Panics if called from a worker thread, as it would wait for itself forever.
+    pub fn quiesce_hooks(&self) {
+        assert!(
+            crate::worker::try_get_worker().is_none(),
+            "quiesce_hooks cannot be called from within a worker thread"
+        );
 
-        JoinHandle::new(header)
+        self.inner.wait_work_sections();
+    }
+
+    /// Spawns every closure received through the channel into the threadpool. The closures are
+    /// forwarded from a separate thread, which stops once all the senders are dropped or the
+    /// threadpool shuts down. Returns the handle of the forwarding thread.
+    pub fn consume_channel(&self, rx: Receiver<Box<dyn FnOnce() + Send>>) -> thread::JoinHandle<()> {
+        let pool = self.clone();
+
+        thread::Builder::new()
+            .name("planetary-channel".to_string())
+            .spawn(move || {
+                while !pool.inner.should_stop() {
+                    match rx.recv_timeout(Duration::from_millis(100)) {
+                        Ok(fun) => pool.spawn(fun).detach(),
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => break
+                    }
+                }
+            })
+            .unwrap_or_else(|_| panic!("Failed to spawn channel thread"))
+    }
+
+    /// Spawns a new [`Runnable`](crate::task::Runnable) and blocks until it completes, returning its result.
+    ///
+    /// When called from a worker thread, the worker executes other tasks while waiting,
+    /// including the spawned one, so it can't deadlock even on a single threaded pool.
+    pub fn run<M, F: IntoRunnable<M>>(&self, runnable: F) -> JoinResult<RunnableOutput<F, M>> {
+        let handle = self.spawn(runnable);
+
+        if let Some(worker) = crate::worker::try_get_worker() {
+            crate::worker::help_until(worker, || handle.is_finished(), |thread| handle.register_thread(thread.clone()));
+        }
+
+        handle.join()
+    }
+
+    /// Spawns a new [`Runnable`](crate::task::Runnable) and makes the calling thread execute tasks of the
+    /// threadpool as an extra worker until it completes, returning its output.
+    ///
+    /// The runnable itself is left to the workers, so it can block waiting for the
+    /// tasks it spawns while the calling thread helps executing them.
+    ///
+    /// Panics if called from a worker thread, or if the runnable panics.
+    pub fn run_until<M, F: IntoRunnable<M>>(&self, runnable: F) -> RunnableOutput<F, M> {
+        assert!(
+            crate::worker::try_get_worker().is_none(),
+            "run_until must not be called from within a worker"
+        );
+
+        let handle = self.spawn(runnable);
+
+        while !handle.has_started() {
+            thread::yield_now();
+        }
+
+        crate::worker::participate(&self.inner, || handle.is_finished());
+
+        match handle.join() {
+            Ok(output) => output,
+            Err(JoinError::Panic(payload)) => std::panic::resume_unwind(payload),
+            Err(e) => panic!("run_until task did not complete: {e}")
+        }
+    }
+
+    /// Maps `items` with `f` in parallel, returning the results in the order of the items.
+    ///
+    /// The items are split into one chunk per live worker plus one for the calling thread,
+    /// which executes tasks of the threadpool until every chunk is mapped, so `f` may borrow
+    /// from the caller. If `f` panics, the panic is resumed once every chunk finished.
+    pub fn parallelize<T, F, R>(&self, items: Vec<T>, f: F) -> Vec<R>
+    where
+        F: Fn(T) -> R + Sync,
+        T: Send + 'static,
+        R: Send + 'static
+    {
+        if items.is_empty() {
+            return Vec::new();
+        }
+
+        let chunks = self.inner.live_threads() + 1;
+        let chunk_size = items.len().div_ceil(chunks);
+        let f = &f;
+
+        let handles = crate::scope::scope(self, |scope| {
+            let mut items = items.into_iter();
+            let handles = std::iter::from_fn(|| {
+                let chunk = items.by_ref().take(chunk_size).collect::<Vec<_>>();
+                (!chunk.is_empty()).then(|| scope.spawn(move || chunk.into_iter().map(f).collect::<Vec<_>>()))
+            }).collect::<Vec<_>>();
+
+            let done = || handles.iter().all(JoinHandle::is_finished);
+            let register = |thread: &std::thread::Thread| handles.iter()
+                .for_each(|handle| handle.register_thread(thread.clone()));
+
+            match crate::worker::try_get_worker() {
+                Some(worker) => crate::worker::help_until(worker, done, register),
+                None => crate::worker::participate(&self.inner, done),
+            }
+
+            handles
+        });
+
+        let mut results = Vec::with_capacity(chunk_size * handles.len());
+        let mut panic = None;
+
+        for handle in handles {
+            match handle.join() {
+                Ok(chunk) => results.extend(chunk),
+                Err(JoinError::Panic(payload)) => panic = panic.or(Some(payload)),
+                Err(e) => panic!("parallelize chunk did not complete: {e}")
+            }
+        }
+
+        if let Some(payload) = panic {
+            std::panic::resume_unwind(payload);
+        }
+
+        results
+    }
+
+    /// Joins all the given handles, returning each result paired with the index its
+    /// handle had in `handles`. Results are returned in the order the tasks complete,
+    /// so the fastest tasks come first.
+    pub fn join_indexed<T>(&self, handles: Vec<JoinHandle<T>>) -> Vec<(usize, JoinResult<T>)> {
+        self.join_n(handles, usize::MAX).0
+    }
+
+    /// Blocks until at least `n` of the given handles complete, returning their results
+    /// paired with the index their handle had in `handles`, in the order they completed,
+    /// along with the handles still pending in their original order. `n` is clamped to the
+    /// number of handles, so this generalizes [`select`](crate::select) and
+    /// [`Planetary::join_indexed`], e.g. to wait for a quorum of tasks.
+    ///
+    /// Tasks that complete at the same time as the `n`th one are returned as well.
+    #[allow(clippy::type_complexity)]
+    pub fn join_n<T>(&self, handles: Vec<JoinHandle<T>>, n: usize) -> (Vec<(usize, JoinResult<T>)>, Vec<JoinHandle<T>>) {
+        let n = n.min(handles.len());
+        let mut results = Vec::with_capacity(n);
+        let mut pending = handles.into_iter().enumerate().collect::<Vec<_>>();
+        let current = std::thread::current();
+        let worker = crate::worker::try_get_worker().filter(|w| w.core().as_ptr() == self.inner.as_ptr());
+
+        while results.len() < n {
+            match worker {
+                // the tasks may be queued behind the calling worker, so it runs them meanwhile
+                Some(worker) => crate::worker::help_until(
+                    worker,
+                    || pending.iter().any(|(_, handle)| handle.is_finished()),
+                    |thread| pending.iter().for_each(|(_, handle)| handle.register_thread(thread.clone()))
+                ),
+                None => for (_, handle) in pending.iter() {
+                    handle.register_thread(current.clone());
+                }
+            }
+
+            // check after registering, so completions in between aren't missed
+            let before = results.len();
+            pending.retain_mut(|(index, handle)| match handle.try_join() {
+                Some(output) => {
+                    results.push((*index, output));
+                    false
+                }
+                None => true
+            });
+
+            if worker.is_none() && results.len() == before {
+                let _suspended = crate::core::suspend_task_permits();
+                std::thread::park();
+            }
+        }
+
+        (results, pending.into_iter().map(|(_, handle)| handle).collect())
+    }
+
+    /// Creates an empty [`TaskGroup`] spawning its tasks into this threadpool.
+    pub fn group<T>(&self) -> TaskGroup<T> {
+        TaskGroup::new(self.clone())
+    }
+
+    /// Gets the partition with the given name, creating it with `config` if it doesn't
+    /// exist yet. The configuration of an existing partition is left unchanged.
+    pub fn partition(&self, name: &str, config: PartitionConfig) -> Partition {
+        Partition::new(self.inner.clone(), self.inner.partition(name, config))
+    }
+
+    /// Creates a value with a separate instance for each worker, built with `init` the
+    /// first time each worker accesses it through [`WorkerLocal::get`].
+    pub fn worker_local<T, F>(&self, init: F) -> WorkerLocal<T>
+    where
+        F: Fn() -> T + Send + Sync + 'static
+    {
+        WorkerLocal::new(&self.inner, init)
+    }
+
+    /// Spawns worker threads up to the thread limit and waits for each of them to run a
+    /// no-op task, so every worker has completed its `on_start` hook by the time this
+    /// returns and the first real tasks don't pay for thread creation or lazy
+    /// initialization. Can be called at any time, e.g. after [`Planetary::set_max_threads`].
+    ///
+    /// Dedicated stealers are spawned but not waited for, as tasks can't be placed on them.
+    /// Returns the number of threads this call started, zero if every worker was running.
+    pub fn prewarm(&self) -> usize {
+        let spawned = self.inner.spawn_up_to_limit();
+
+        let handles = self.worker_ids()
+            .into_iter()
+            .filter_map(|id| self.try_spawn_on(id, || ()).ok())
+            .collect::<Vec<_>>();
+
+        // a worker calling this runs its own warmup task while waiting
+        handles.into_iter().for_each(|handle| handle.map_join(drop));
+        spawned
+    }
+
+    /// Creates a barrier that waits for `count` tasks to complete in this threadpool,
+    /// counting the tasks that complete after this call. Every task executed by the
+    /// workers counts, including those spawned by other parts of the program.
+    pub fn barrier(&self, count: usize) -> CompletionBarrier {
+        CompletionBarrier::new(self.inner.clone(), count)
+    }
+
+    /// Returns the configuration the threadpool was built with. The timeout reflects
+    /// the current value, which may have been changed with [`Planetary::set_timeout`].
+    pub fn config(&self) -> PoolConfig {
+        self.inner.config()
+    }
+
+    /// Returns a snapshot of the threadpool statistics.
+    pub fn metrics(&self) -> Metrics {
+        Metrics::sample(&self.inner)
+    }
+
+    /// Gets the counters accumulated since the threadpool was built or
+    /// [`Planetary::reset_stats`] was last called.
+    pub fn stats(&self) -> CumulativeStats {
+        self.inner.stats.snapshot()
+    }
+
+    /// Sets the counters returned by [`Planetary::stats`] back to zero.
+    pub fn reset_stats(&self) {
+        self.inner.stats.reset()
+    }
+
+    /// Returns the threadpool to a clean state so it can be reused, as if it had just
+    /// been built: the [statistics](Planetary::stats) and completed task count go back
+    /// to zero and new workers get a fresh pool epoch in their [`NameContext`](crate::NameContext).
+    /// The live workers are kept.
+    ///
+    /// Returns false, leaving the threadpool untouched, if there are still tasks
+    /// queued or running, which can be waited for by joining their handles.
+    pub fn reset(&self) -> bool {
+        self.inner.reset()
+    }
+
+    /// Cheap liveness probe, e.g. for health check endpoints. The threadpool is healthy
+    /// when all of these hold:
+    ///
+    /// - It hasn't been shut down.
+    /// - None of its internal locks got poisoned by a panic.
+    /// - Its queued tasks aren't stranded, i.e. there is at least a live worker if there
+    ///   are tasks waiting in the global, low priority or partition queues.
+    ///
+    /// Only takes read locks, so it doesn't contend with the workers.
+    pub fn is_healthy(&self) -> bool {
+        self.inner.is_healthy()
+    }
+
+    /// Returns the approximate time each live worker spent running tasks, along with
+    /// its id, which helps spotting load imbalance across the workers. Tasks run on
+    /// threads participating in the pool, like those running [`Planetary::run_until`],
+    /// aren't accounted.
+    pub fn worker_cpu_times(&self) -> Vec<(usize, Duration)> {
+        self.inner.worker_cpu_times()
+    }
+
+    /// Returns the ids of the worker threads currently alive, which can be used
+    /// with [`Planetary::spawn_on`].
+    pub fn worker_ids(&self) -> Vec<usize> {
+        self.inner.worker_ids()
+    }
+
+    /// Returns the percentiles of the time tasks waited before starting to run. All the
+    /// values are zero unless enabled with [`PlanetaryBuilder::track_latency`].
+    ///
+    /// [`PlanetaryBuilder::track_latency`]: crate::builder::PlanetaryBuilder::track_latency
+    pub fn latency_percentiles(&self) -> LatencyStats {
+        self.inner.latency_stats()
+    }
+
+    /// Returns the number of tasks waiting in the global injector, without taking any
+    /// lock. The value is approximate while tasks are being pushed or taken concurrently.
+    pub fn injector_len(&self) -> usize {
+        self.inner.injector_len()
+    }
+
+    /// Takes all the tasks waiting in the global injector without running them, in the
+    /// order they were queued, so custom schedulers can inspect or reorder them before
+    /// handing them back with [`Planetary::spawn_erased`]. Their handles keep working.
+    ///
+    /// Tasks taken by the workers meanwhile aren't returned, and dropping the returned
+    /// tasks cancels them.
+    pub fn drain_injector(&self) -> Vec<TypeErasedTask> {
+        self.inner.drain_injector()
+    }
+
+    /// Changes the maximum number of worker threads, which can't exceed the one the
+    /// threadpool was built with. When lowered, the excess workers exit once they finish
+    /// their current task, handing the tasks of their local queues to the rest.
+    ///
+    /// Panics if `threads` is zero.
+    pub fn set_max_threads(&self, threads: usize) {
+        self.inner.set_max_threads(threads);
+    }
+
+    /// Runs `f` allowing `extra` worker threads over the maximum, for bursts of work that
+    /// need more parallelism than usual. The maximum is restored when `f` returns or panics,
+    /// and the workers in excess exit once they've been idle for the configured timeout.
+    pub fn with_burst<R>(&self, extra: usize, f: impl FnOnce() -> R) -> R {
+        self.inner.begin_burst(extra);
+        defer!(|| self.inner.end_burst(extra));
+
+        f()
+    }
+
+    /// Checks whether the threadpool can spawn more worker threads.
+    pub fn can_grow(&self) -> bool {
+        !self.at_capacity()
+    }
+
+    /// Checks whether the threadpool already runs its maximum number of worker threads, or
+    /// the maximum of its autoscaler if that's lower. Doesn't take any lock.
+    pub fn at_capacity(&self) -> bool {
+        self.inner.at_capacity()
+    }
+
+    /// Gets the timeout for worker threads without work.
+    pub fn timeout(&self) -> Duration {
+        self.inner.timeout()
+    }
+
+    /// Sets the timeout for worker threads without work. Workers that are currently parked
+    /// will use the new timeout on their next park.
+    pub fn set_timeout(&self, timeout: Duration) {
+        self.inner.set_timeout(timeout);
     }
 
     /// Gets the current [`Planetary`] in scope. Will panic if not inside the context of a
@@ -59,11 +884,93 @@ impl Planetary {
         sealed::try_get_handle()
     }
 
+    /// Creates a [`WeakPool`] pointing to this threadpool, which doesn't keep it alive.
+    /// Useful to store a reference to another pool inside tasks without risking
+    /// a shutdown waiting on them.
+    pub fn downgrade(&self) -> WeakPool {
+        WeakPool(self.inner.downgrade())
+    }
+
+    /// Starts shutting down the threadpool like [`Planetary::shutdown`], but returns right
+    /// away with a [`ShutdownWaiter`] that can be awaited until every worker has exited.
+    /// The tasks detached with [`JoinHandle::detach_and_wait_on_shutdown`] finish before
+    /// the waiter completes.
+    pub fn shutdown_async(self) -> ShutdownWaiter {
+        sealed::remove_handle();
+        self.inner.begin_shutdown();
+        ShutdownWaiter::new(self.inner.clone())
+    }
+
     /// Shuts down the threadpool connected to this particular handle. Subsequent calls to
-    /// [`Planetary::spawn`] will have no effect, and enqueued tasks will not run.
+    /// [`Planetary::spawn`] will have no effect, and enqueued tasks will not run, except
+    /// the ones detached with [`JoinHandle::detach_and_wait_on_shutdown`], which are waited for first.
+    ///
+    /// Blocks until every worker and blocking thread has exited, so the `on_stop` hook of
+    /// every worker has completed by the time this returns. When called from a worker of this same
+    /// threadpool, which can't wait for itself to exit, it only starts the shutdown and
+    /// returns right away, the worker exits once the current task finishes.
     pub fn shutdown(self) {
         sealed::remove_handle();
-        self.inner.set_stop(true);
+        self.inner.begin_shutdown();
+
+        if self.is_own_worker() {
+            tracing_feat!(debug!("Shutdown called from a worker, not waiting for the workers to exit"));
+            return;
+        }
+
         self.inner.wait_stop();
     }
+
+    /// Whether the current thread is a worker of this threadpool.
+    fn is_own_worker(&self) -> bool {
+        crate::worker::try_get_worker()
+            .is_some_and(|w| !w.is_participant() && w.core().as_ptr() == self.inner.as_ptr())
+    }
+}
+
+impl Clone for Planetary {
+    fn clone(&self) -> Self {
+        Self::new(self.inner.clone())
+    }
+}
+
+/// Lets the threadpool run futures for libraries expecting a futures executor, through
+/// [`Planetary::spawn_future`].
+#[cfg(feature = "async")]
+impl futures_task::Spawn for Planetary {
+    fn spawn_obj(&self, future: futures_task::FutureObj<'static, ()>) -> Result<(), futures_task::SpawnError> {
+        self.status()?;
+        self.spawn_future(future).detach();
+        Ok(())
+    }
+
+    fn status(&self) -> Result<(), futures_task::SpawnError> {
+        if self.inner.should_stop() {
+            Err(futures_task::SpawnError::shutdown())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Drop for Planetary {
+    fn drop(&mut self) {
+        if self.inner.release_handle() {
+            tracing_feat!(debug!("Last handle dropped, shutting down the threadpool"));
+            self.inner.begin_shutdown();
+        }
+    }
+}
+
+/// Non-owning reference to a threadpool, obtained through [`Planetary::downgrade`].
+#[derive(Clone)]
+pub struct WeakPool(Weak<CoreInner>);
+
+impl WeakPool {
+    /// Gets a handle to the threadpool, or `None` if it was dropped or shut down.
+    pub fn upgrade(&self) -> Option<Planetary> {
+        Core::upgrade(&self.0)
+            .filter(|core| !core.should_stop())
+            .map(Planetary::new)
+    }
 }
@@ -1,3 +1,5 @@
+use std::{fmt::Debug, time::Duration};
+
 //pub type HookFn<T> = dyn Fn() -> T + Send + Sync + 'static;
 pub trait HookFn<T>: Fn() -> T + Send + Sync + 'static {}
 impl<F, T> HookFn<T> for F
@@ -5,10 +7,24 @@ where
     F: Fn() -> T + Send + Sync + 'static
 {}
 
+/// Information about a worker thread, given to the name function to build its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NameContext {
+    /// Id of the worker, reused when workers exit.
+    pub worker_id: usize,
+    /// Number of threadpools built in the process before the one the worker belongs to.
+    pub pool_epoch: u64,
+    /// Id of the process.
+    pub pid: u32,
+}
+
+/// Function receiving the errors returned by fallible tasks.
+type TaskErrorFn = dyn Fn(&dyn Debug) + Send + Sync + 'static;
+
 /// Hooks to be called on threadpool events
 pub struct Hooks {
-    /// Called when a thread is created, must provide a name for the thread
-    name_fn: Box<dyn HookFn<String>>,
+    /// Called when a thread is created with the context of the worker, must provide a name for the thread
+    name_fn: Box<dyn Fn(NameContext) -> String + Send + Sync + 'static>,
     /// Called when a thread is started, before it starts working
     on_start_fn: Option<Box<dyn HookFn<()>>>,
     /// Called when a thread is stopped, before it stops working
@@ -17,27 +33,54 @@ pub struct Hooks {
     on_park_fn: Option<Box<dyn HookFn<()>>>,
     /// Called when a thread is unparked
     on_unpark_fn: Option<Box<dyn HookFn<()>>>,
-    /// Called before a thread executes a task
-    before_work_fn: Option<Box<dyn HookFn<()>>>,
-    /// Called after a thread executes a task
-    after_work_fn: Option<Box<dyn HookFn<()>>>,
+    /// Called before a thread executes a task, with the id of the task
+    before_work_fn: Option<Box<dyn Fn(u64) + Send + Sync + 'static>>,
+    /// Called after a thread executes a task, with the id of the task
+    after_work_fn: Option<Box<dyn Fn(u64) + Send + Sync + 'static>>,
+    /// Called when a worker steals a task from another one, with the ids of both
+    on_steal_fn: Option<Box<dyn Fn(usize, usize) + Send + Sync + 'static>>,
+    /// Called when a worker finds nothing to steal, with the id of the worker
+    on_steal_fail_fn: Option<Box<dyn Fn(usize) + Send + Sync + 'static>>,
+    /// Called when a task spawned with `Planetary::spawn_fallible` returns an error
+    on_task_error_fn: Option<Box<TaskErrorFn>>,
+    /// Called when a task spawned with `Planetary::spawn_timed` runs past its soft limit
+    on_slow_task_fn: Option<Box<dyn Fn(u64, Duration) + Send + Sync + 'static>>,
 }
 
 impl Hooks {
     pub fn new() -> Self {
         Self {
-            name_fn: Box::new(|| "Unnamed".to_string()),
+            name_fn: Box::new(|_| "Unnamed".to_string()),
             on_start_fn: None,
             on_stop_fn: None,
             on_park_fn: None,
             on_unpark_fn: None,
             before_work_fn: None,
             after_work_fn: None,
+            on_steal_fn: None,
+            on_steal_fail_fn: None,
+            on_task_error_fn: None,
+            on_slow_task_fn: None,
         }
     }
 
     /// Set the name function
     pub fn set_name_fn(&mut self, name_fn: impl HookFn<String>) -> &mut Self {
+        self.name_fn = Box::new(move |_| name_fn());
+        self
+    }
+
+    /// Set the name function, which receives the id of the worker. Since ids are reused
+    /// when workers exit, a name derived only from the id stays the same across respawns.
+    pub fn set_name_with_id_fn(&mut self, name_fn: impl Fn(usize) -> String + Send + Sync + 'static) -> &mut Self {
+        self.name_fn = Box::new(move |ctx| name_fn(ctx.worker_id));
+        self
+    }
+
+    /// Set the name function, which receives a [`NameContext`] with the id of the worker,
+    /// the epoch of the threadpool and the id of the process, useful to tell apart
+    /// threads in logs gathered from several pools or processes.
+    pub fn set_name_ctx_fn(&mut self, name_fn: impl Fn(NameContext) -> String + Send + Sync + 'static) -> &mut Self {
         self.name_fn = Box::new(name_fn);
         self
     }
@@ -49,6 +92,14 @@ impl Hooks {
     }
 
     /// Set the on_stop function
+    ///
+    /// Tasks spawned from within this hook run inline on the stopping worker before
+    /// the hook call returns, as the worker won't be around to execute them later.
+    ///
+    /// Workers run this hook concurrently and in no particular order, but all of them
+    /// complete before [`Planetary::shutdown`] returns.
+    ///
+    /// [`Planetary::shutdown`]: crate::handle::Planetary::shutdown
     pub fn set_on_stop_fn(&mut self, on_stop_fn: impl HookFn<()>) -> &mut Self {
         self.on_stop_fn = Some(Box::new(on_stop_fn));
         self
@@ -68,19 +119,64 @@ impl Hooks {
 
     /// Set the before_work function
     pub fn set_before_work_fn(&mut self, before_work_fn: impl HookFn<()>) -> &mut Self {
+        self.before_work_fn = Some(Box::new(move |_| before_work_fn()));
+        self
+    }
+
+    /// Set the before_work function, which receives the id of the task about to run
+    pub fn set_before_work_with_id_fn(&mut self, before_work_fn: impl Fn(u64) + Send + Sync + 'static) -> &mut Self {
         self.before_work_fn = Some(Box::new(before_work_fn));
         self
     }
 
     /// Set the after_work function
     pub fn set_after_work_fn(&mut self, after_work_fn: impl HookFn<()>) -> &mut Self {
+        self.after_work_fn = Some(Box::new(move |_| after_work_fn()));
+        self
+    }
+
+    /// Set the after_work function, which receives the id of the task that just ran
+    pub fn set_after_work_with_id_fn(&mut self, after_work_fn: impl Fn(u64) + Send + Sync + 'static) -> &mut Self {
         self.after_work_fn = Some(Box::new(after_work_fn));
         self
     }
 
+    /// Set the on_steal function, which receives the id of the worker that stole a task
+    /// and the id of the worker it was stolen from
+    pub fn set_on_steal_fn(&mut self, on_steal_fn: impl Fn(usize, usize) + Send + Sync + 'static) -> &mut Self {
+        self.on_steal_fn = Some(Box::new(on_steal_fn));
+        self
+    }
+
+    /// Set the on_steal_fail function, which receives the id of a worker that looked
+    /// for a task to steal from the rest of workers and found nothing
+    pub fn set_on_steal_fail_fn(&mut self, on_steal_fail_fn: impl Fn(usize) + Send + Sync + 'static) -> &mut Self {
+        self.on_steal_fail_fn = Some(Box::new(on_steal_fail_fn));
+        self
+    }
+
+    /// Set the on_task_error function, which receives the error returned by a task spawned
+    /// with [`Planetary::spawn_fallible`]. Panics are not reported through this hook.
+    ///
+    /// [`Planetary::spawn_fallible`]: crate::handle::Planetary::spawn_fallible
+    pub fn set_on_task_error_fn(&mut self, on_task_error_fn: impl Fn(&dyn Debug) + Send + Sync + 'static) -> &mut Self {
+        self.on_task_error_fn = Some(Box::new(on_task_error_fn));
+        self
+    }
+
+    /// Set the on_slow_task function, which receives the id of a task spawned with
+    /// [`Planetary::spawn_timed`] that is still running past its soft limit, along with
+    /// the time it has been running for. The task is not aborted.
+    ///
+    /// [`Planetary::spawn_timed`]: crate::handle::Planetary::spawn_timed
+    pub fn set_on_slow_task_fn(&mut self, on_slow_task_fn: impl Fn(u64, Duration) + Send + Sync + 'static) -> &mut Self {
+        self.on_slow_task_fn = Some(Box::new(on_slow_task_fn));
+        self
+    }
+
     /// Call the name function
-    pub(crate) fn call_name_fn(&self) -> String {
-        (self.name_fn)()
+    pub(crate) fn call_name_fn(&self, ctx: NameContext) -> String {
+        (self.name_fn)(ctx)
     }
 
     /// Call the on_start function
@@ -112,16 +208,44 @@ impl Hooks {
     }
 
     /// Call the before_work function
-    pub(crate) fn call_before_work_fn(&self) {
+    pub(crate) fn call_before_work_fn(&self, task_id: u64) {
         if let Some(ref f) = self.before_work_fn {
-            f();
+            f(task_id);
         }
     }
 
     /// Call the after_work function
-    pub(crate) fn call_after_work_fn(&self) {
+    pub(crate) fn call_after_work_fn(&self, task_id: u64) {
         if let Some(ref f) = self.after_work_fn {
-            f();
+            f(task_id);
+        }
+    }
+
+    /// Call the on_steal function
+    pub(crate) fn call_on_steal_fn(&self, stealer: usize, victim: usize) {
+        if let Some(ref f) = self.on_steal_fn {
+            f(stealer, victim);
+        }
+    }
+
+    /// Call the on_steal_fail function
+    pub(crate) fn call_on_steal_fail_fn(&self, stealer: usize) {
+        if let Some(ref f) = self.on_steal_fail_fn {
+            f(stealer);
+        }
+    }
+
+    /// Call the on_task_error function
+    pub(crate) fn call_on_task_error_fn(&self, error: &dyn Debug) {
+        if let Some(ref f) = self.on_task_error_fn {
+            f(error);
+        }
+    }
+
+    /// Call the on_slow_task function
+    pub(crate) fn call_on_slow_task_fn(&self, id: u64, elapsed: Duration) {
+        if let Some(ref f) = self.on_slow_task_fn {
+            f(id, elapsed);
         }
     }
 }
@@ -0,0 +1,71 @@
+//! Registry of the live tasks of a threadpool, used to inspect and abort them in bulk
+//! through [`Planetary::abort_where`].
+//!
+//! [`Planetary::abort_where`]: crate::handle::Planetary::abort_where
+
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::{join::AbortHandle, task::{state::State, Header, TypeErasedTask}};
+
+/// Information about a live task, given to the predicate of [`Planetary::abort_where`].
+///
+/// [`Planetary::abort_where`]: crate::handle::Planetary::abort_where
+#[derive(Debug, Clone, Copy)]
+pub struct TaskInfo<'a> {
+    /// Unique identifier of the task
+    pub id: u64,
+    /// Name given to the task with [`Planetary::spawn_named`]
+    ///
+    /// [`Planetary::spawn_named`]: crate::handle::Planetary::spawn_named
+    pub name: Option<&'a str>,
+    /// Whether the task is running
+    pub running: bool,
+    /// Whether the task has been aborted
+    pub aborted: bool,
+}
+
+impl<'a> TaskInfo<'a> {
+    fn new(header: &'a Header) -> Self {
+        let state = header.state_snapshot();
+
+        Self {
+            id: header.id,
+            name: header.name(),
+            running: state.get(State::RUNNING),
+            aborted: state.get(State::ABORTED),
+        }
+    }
+}
+
+/// Tasks handed to a threadpool that didn't finish yet, unregistered by the tasks
+/// themselves once they finish or get aborted. The abort handles keep the tasks
+/// allocated while they are registered.
+#[derive(Default)]
+pub(crate) struct Registry {
+    tasks: Mutex<HashMap<u64, AbortHandle>>,
+}
+
+impl Registry {
+    pub fn register(&self, task: &TypeErasedTask) {
+        self.lock().insert(task.header().id, AbortHandle::new(task.header));
+    }
+
+    pub fn unregister(&self, id: u64) {
+        self.lock().remove(&id);
+    }
+
+    /// Aborts the live tasks matching `pred`, returning how many were aborted.
+    pub fn abort_where(&self, pred: impl Fn(&TaskInfo) -> bool) -> usize {
+        // aborting a task unregisters it, so don't hold the lock meanwhile
+        let tasks = self.lock().values().cloned().collect::<Vec<_>>();
+
+        tasks.iter()
+            .filter(|task| !task.is_aborted() && pred(&TaskInfo::new(task.header())))
+            .inspect(|task| task.abort())
+            .count()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, AbortHandle>> {
+        self.tasks.lock().unwrap_or_else(|s| s.into_inner())
+    }
+}
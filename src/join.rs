@@ -1,7 +1,97 @@
-use std::{marker::PhantomData, pin::Pin, ptr::NonNull, task::{Context, Poll}};
+use std::{any::Any, fmt, marker::PhantomData, pin::Pin, ptr::NonNull, task::{Context, Poll}, time::{Duration, Instant}};
 
 use crate::{task::{state::State, Header}, JoinResult};
 
+/// Error returned when a task didn't produce an output.
+pub enum JoinError {
+    /// The task panicked, contains the panic payload.
+    Panic(Box<dyn Any + Send + 'static>),
+    /// The task was aborted before it started running.
+    Aborted,
+    /// The task was discarded by the threadpool before it could run, e.g. due to a shutdown.
+    Cancelled,
+    /// The task was refused because the worker didn't have enough stack left to run it,
+    /// only produced with the `stack-guard` feature.
+    StackExhausted,
+    /// The task ran past the limit given to [`Planetary::spawn_hard_timeout`], it may still
+    /// be running if it doesn't check whether it was aborted.
+    ///
+    /// [`Planetary::spawn_hard_timeout`]: crate::handle::Planetary::spawn_hard_timeout
+    TimedOut,
+}
+
+impl JoinError {
+    /// Whether the task panicked.
+    pub fn is_panic(&self) -> bool {
+        matches!(self, Self::Panic(_))
+    }
+
+    /// Whether the task was aborted.
+    pub fn is_aborted(&self) -> bool {
+        matches!(self, Self::Aborted)
+    }
+
+    /// Whether the task was cancelled by the threadpool.
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, Self::Cancelled)
+    }
+
+    /// Whether the task was refused due to low remaining stack.
+    pub fn is_stack_exhausted(&self) -> bool {
+        matches!(self, Self::StackExhausted)
+    }
+
+    /// Whether the task ran past its hard timeout.
+    pub fn is_timed_out(&self) -> bool {
+        matches!(self, Self::TimedOut)
+    }
+
+    /// Returns the panic payload if the task panicked.
+    pub fn into_panic(self) -> Option<Box<dyn Any + Send + 'static>> {
+        match self {
+            Self::Panic(payload) => Some(payload),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Debug for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Panic(_) => f.write_str("Panic(..)"),
+            Self::Aborted => f.write_str("Aborted"),
+            Self::Cancelled => f.write_str("Cancelled"),
+            Self::StackExhausted => f.write_str("StackExhausted"),
+            Self::TimedOut => f.write_str("TimedOut"),
+        }
+    }
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Panic(_) => f.write_str("task panicked"),
+            Self::Aborted => f.write_str("task was aborted"),
+            Self::Cancelled => f.write_str("task was cancelled by the threadpool"),
+            Self::StackExhausted => f.write_str("task was refused due to low remaining stack"),
+            Self::TimedOut => f.write_str("task ran past its hard timeout"),
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+/// Converts the error into the boxed payload previously used by [`JoinResult`], panics
+/// keep their original payload while other errors are boxed as is.
+impl From<JoinError> for Box<dyn Any + Send + 'static> {
+    fn from(error: JoinError) -> Self {
+        match error {
+            JoinError::Panic(payload) => payload,
+            other => Box::new(other)
+        }
+    }
+}
+
 /// Handle used to wait for a task's output.
 /// 
 /// If the handle is not required, please call [`JoinHandle::detach`]
@@ -26,32 +116,118 @@ impl<T> JoinHandle<T> {
 
     /// Waits for the underlying task to dinish and returns the output.
     pub fn join(mut self) -> JoinResult<T> {
-        if let Some(output) = self.try_join() {
-            return output;
+        loop {
+            if let Some(output) = self.try_join() {
+                return output;
+            }
+
+            self.register_thread(std::thread::current());
+
+            // the task may have finished before we registered ourselves, in which
+            // case nobody would unpark us
+            if let Some(output) = self.try_join() {
+                return output;
+            }
+
+            let _waiting = crate::worker::wait_on(unsafe { self.header.as_ref() });
+            let _suspended = crate::core::suspend_task_permits();
+            std::thread::park();
         }
+    }
+
+    /// Waits for the underlying task to finish and returns the output, calling `on_tick`
+    /// every `interval` while waiting, e.g. to send heartbeats during a long join.
+    pub fn join_with_tick(mut self, interval: Duration, mut on_tick: impl FnMut()) -> JoinResult<T> {
+        let mut next_tick = Instant::now() + interval;
 
         loop {
-            {
-                let thread = std::thread::current();
-                let header = unsafe { self.header.as_ref() };
-                header.parker()
-                    .lock()
-                    .unwrap_or_else(|t| t.into_inner())
-                    .set_thread(thread);
+            if let Some(output) = self.try_join() {
+                return output;
             }
-    
-            std::thread::park();
-            
-            let Some(data) = self.try_join() else {
-                continue;
-            };
 
-            break data;
+            self.register_thread(std::thread::current());
+
+            // the task may have finished before we registered ourselves, in which
+            // case nobody would unpark us
+            if let Some(output) = self.try_join() {
+                return output;
+            }
+
+            let now = Instant::now();
+
+            // wakeups before the interval elapsed don't count as a tick
+            if now >= next_tick {
+                on_tick();
+                next_tick = Instant::now() + interval;
+            } else {
+                let _suspended = crate::core::suspend_task_permits();
+                std::thread::park_timeout(next_tick - now);
+            }
+        }
+    }
+
+    /// Waits for the underlying task to finish and returns its output, resuming the panic
+    /// on the caller thread if the task panicked, like [`std::thread::JoinHandle::join`]
+    /// followed by an unwrap would.
+    ///
+    /// Panics if the task was aborted or cancelled.
+    pub fn join_unwrap(self) -> T {
+        match self.join() {
+            Ok(output) => output,
+            Err(JoinError::Panic(payload)) => std::panic::resume_unwind(payload),
+            Err(e) => panic!("task did not complete: {e}")
+        }
+    }
+
+    /// Waits for the underlying task to finish and returns the result of applying `f` to
+    /// its output, e.g. `handle.map_join(|r| r.map_err(MyError::from))`.
+    ///
+    /// When called from a worker thread, the worker executes other tasks while waiting,
+    /// so it can't deadlock waiting for a task queued behind it. Once there is nothing left
+    /// to run, it parks until the task finishes, checking for new tasks every now and then.
+    pub fn map_join<U, F: FnOnce(JoinResult<T>) -> U>(self, f: F) -> U {
+        if let Some(worker) = crate::worker::try_get_worker() {
+            crate::worker::help_until(worker, || self.is_finished(), |thread| self.register_thread(thread.clone()));
+        }
+
+        f(self.join())
+    }
+
+    /// Waits for the underlying task to finish and returns the output, executing tasks of
+    /// the threadpool it was spawned into meanwhile. Unlike [`JoinHandle::join`], a thread
+    /// that isn't a worker acts as an extra one while waiting, possibly running the awaited
+    /// task itself, instead of sitting parked.
+    ///
+    /// Behaves like [`JoinHandle::join`] if that threadpool is gone, or when called from a
+    /// worker of a different threadpool.
+    pub fn join_helping(self) -> JoinResult<T> {
+        let Some(core) = (unsafe { self.header.as_ref() }).owner() else {
+            return self.join();
+        };
+
+        match crate::worker::try_get_worker() {
+            Some(worker) if worker.core().as_ptr() == core.as_ptr() => {
+                crate::worker::help_until(worker, || self.is_finished(), |thread| self.register_thread(thread.clone()));
+            }
+            Some(_) => (),
+            None => crate::worker::participate(&core, || self.is_finished())
         }
+
+        self.join()
+    }
+
+    /// Registers the given thread to be unparked once the task finishes.
+    pub(crate) fn register_thread(&self, thread: std::thread::Thread) {
+        let header = unsafe { self.header.as_ref() };
+        header.parker()
+            .lock()
+            .unwrap_or_else(|t| t.into_inner())
+            .set_thread(thread);
     }
 
     /// Marks the underlying task as aborted, telling the workers to don't run it
-    /// if they haven't already.
+    /// if they haven't already. Joining a task aborted before it started running
+    /// returns [`JoinError::Aborted`].
     pub fn abort(&self) {
         Header::abort(self.header);
     }
@@ -62,6 +238,22 @@ impl<T> JoinHandle<T> {
         }
     }
 
+    /// Returns a handle that can abort the task, which can be shared with other threads.
+    pub fn abort_handle(&self) -> AbortHandle {
+        AbortHandle::new(self.header)
+    }
+
+    /// Returns a handle that doesn't keep the output of the task alive, see [`WeakJoinHandle`].
+    pub fn downgrade(&self) -> WeakJoinHandle<T> {
+        WeakJoinHandle::new(self.header)
+    }
+
+    /// Checks whether the task started running or is already finished.
+    pub(crate) fn has_started(&self) -> bool {
+        let state = unsafe { self.header.as_ref().state_snapshot() };
+        state.get(State::RUNNING) || state.get(State::FINISHED)
+    }
+
     /// Checks whether the task is finished
     pub fn is_finished(&self) -> bool {
         unsafe {
@@ -69,12 +261,91 @@ impl<T> JoinHandle<T> {
         }
     }
 
+    /// Gets the last progress reported by the task with [`report_progress`], between 0 and 1,
+    /// or `None` if it never reported any.
+    ///
+    /// [`report_progress`]: crate::report_progress
+    pub fn progress(&self) -> Option<f32> {
+        unsafe { self.header.as_ref().progress() }
+    }
+
     /// Detaches the handle from the underlying task
     pub fn detach(self) {
         drop(self);
     }
 
-    fn try_join(&mut self) -> Option<JoinResult<T>> {
+    /// Detaches the handle like [`JoinHandle::detach`], but makes shutting down the threadpool
+    /// wait for the task to finish first, so important fire-and-forget work isn't cut off.
+    /// The task can shut down the threadpool itself, but not wait for it to stop.
+    ///
+    /// If the threadpool gets paused during the shutdown, it stops without waiting for
+    /// the tasks that didn't start yet, as they can't run anymore.
+    pub fn detach_and_wait_on_shutdown(self) {
+        // the threadpool is gone if there's no owner, so there's no shutdown to wait for
+        let task = self.abort_handle();
+
+        if let Some(core) = task.header().owner() {
+            core.await_on_shutdown(task);
+        }
+    }
+
+    /// Chains `f` to the task, running it with the output once the task finishes, and returns
+    /// the handle of the continuation. Chains of any length can be built this way.
+    ///
+    /// Aborting the returned handle aborts the task too, and the rest of the chain behind it,
+    /// as nobody is going to consume their output. Conversely, if the task panics or is aborted,
+    /// `f` doesn't run and joining the continuation returns [`JoinError::Aborted`].
+    ///
+    /// The continuation is spawned into the threadpool the task was spawned into. If that
+    /// threadpool is gone, it falls back to the one of the current context, like [`spawn`],
+    /// panicking if called outside of the context of a threadpool.
+    ///
+    /// [`spawn`]: crate::spawn
+    pub fn then<U, F>(self, f: F) -> JoinHandle<U>
+    where
+        T: Send + 'static,
+        U: Send + 'static,
+        F: FnOnce(T) -> U + Send + 'static
+    {
+        let core = unsafe { self.header.as_ref() }.owner()
+            .or_else(crate::handle::sealed::try_get_core)
+            .expect("Cannot get handle outside of the context of a threadpool");
+
+        crate::chain::then(core, self, f)
+    }
+
+    /// Consumes the handle, returning a pointer to the header of the task.
+    ///
+    /// The task is kept alive until the pointer is turned back into a handle with
+    /// [`JoinHandle::from_raw`] and dropped. The output can be taken from the pointer with
+    /// [`Planetary::take_output_raw`].
+    ///
+    /// [`Planetary::take_output_raw`]: crate::handle::Planetary::take_output_raw
+    pub fn into_raw(self) -> NonNull<Header> {
+        let header = self.header;
+        std::mem::forget(self);
+        header
+    }
+
+    /// Rebuilds a handle from a pointer returned by [`JoinHandle::into_raw`].
+    ///
+    /// # Safety
+    ///
+    /// The pointer must come from [`JoinHandle::into_raw`] and be turned back into a
+    /// handle only once, and `T` must be the output type of the task.
+    pub unsafe fn from_raw(header: NonNull<Header>) -> Self {
+        Self {
+            header,
+            _marker: PhantomData
+        }
+    }
+
+    pub(crate) fn try_join(&mut self) -> Option<JoinResult<T>> {
+        // the output of a timed out task is discarded along with the handle
+        if unsafe { self.header.as_ref().state_snapshot().get(State::TIMED_OUT) } {
+            return Some(Err(JoinError::TimedOut));
+        }
+
         let mut res = None;
         // SAFETY: This method can only be called from join, so we have ownership
         // of the handle, and this function is only called by handles
@@ -85,6 +356,40 @@ impl<T> JoinHandle<T> {
     }
 }
 
+/// Blocks until any of the tasks finishes, returning its index, its output and the rest of
+/// the handles in their original order, so they can be awaited again or aborted. If
+/// several tasks finished by the time the caller wakes up, the lowest index is returned.
+///
+/// Panics if `handles` is empty.
+pub fn select<T>(mut handles: Vec<JoinHandle<T>>) -> (usize, JoinResult<T>, Vec<JoinHandle<T>>) {
+    assert!(!handles.is_empty(), "select needs at least one handle");
+
+    loop {
+        if let Some(index) = handles.iter().position(JoinHandle::is_finished) {
+            let output = handles.remove(index).join();
+            return (index, output, handles);
+        }
+
+        let thread = std::thread::current();
+        handles.iter().for_each(|handle| handle.register_thread(thread.clone()));
+
+        // a task may have finished before we registered ourselves, in which case
+        // nobody would unpark us
+        if !handles.iter().any(JoinHandle::is_finished) {
+            let _suspended = crate::core::suspend_task_permits();
+            std::thread::park();
+        }
+    }
+}
+
+// SAFETY: The header is only accessed through atomics and locks, and the output
+// is moved to the thread that joins the handle.
+unsafe impl<T: Send> Send for JoinHandle<T> {}
+unsafe impl<T: Send> Sync for JoinHandle<T> {}
+
+// The handle only points to the task, polling it never relies on the handle staying put.
+impl<T> Unpin for JoinHandle<T> {}
+
 impl<T> Future for JoinHandle<T> {
     type Output = JoinResult<T>;
 
@@ -95,23 +400,159 @@ impl<T> Future for JoinHandle<T> {
         }
 
         unsafe {
-            let this = this.header.as_ref();
-            this.parker()
+            let header = this.header.as_ref();
+            header.parker()
                 .lock()
                 .unwrap_or_else(|l| l.into_inner())
-                .set_waker(cx.waker().clone());
+                .set_waker(this as *const Self as usize, cx.waker().clone());
         }
 
-        Poll::Pending
+        // the task may have finished or been aborted before the waker was registered,
+        // in which case the waker would never be called
+        match this.try_join() {
+            Some(out) => Poll::Ready(out),
+            None => Poll::Pending
+        }
     }
 }
 
 impl<T> Drop for JoinHandle<T> {
     fn drop(&mut self) {
+        let last = unsafe {
+            self.header.as_ref().state.release(State::HANDLE_ALIVE)
+        };
+
+        if last {
+            Header::try_dealloc(self.header);
+        } else {
+            Header::try_reclaim(self.header);
+        }
+    }
+}
+
+/// Handle used to abort a task without being able to join it, obtained through
+/// [`JoinHandle::abort_handle`]. Keeps the task allocation alive, but not its output.
+pub struct AbortHandle {
+    pub(crate) header: NonNull<Header>
+}
+
+// SAFETY: Aborting only touches the header, which is thread safe.
+unsafe impl Send for AbortHandle {}
+unsafe impl Sync for AbortHandle {}
+
+impl AbortHandle {
+    pub(crate) fn new(header: NonNull<Header>) -> Self {
+        unsafe {
+            header.as_ref().state.acquire_ref();
+        }
+
+        Self {
+            header
+        }
+    }
+
+    pub(crate) fn header(&self) -> &Header {
+        unsafe { self.header.as_ref() }
+    }
+
+    /// Marks the underlying task as aborted, see [`JoinHandle::abort`].
+    pub fn abort(&self) {
+        Header::abort(self.header);
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        unsafe {
+            self.header.as_ref().state_snapshot().get(State::ABORTED)
+        }
+    }
+
+    /// Checks whether the task is finished
+    pub fn is_finished(&self) -> bool {
+        unsafe {
+            self.header.as_ref().state_snapshot().get(State::FINISHED)
+        }
+    }
+}
+
+impl Clone for AbortHandle {
+    fn clone(&self) -> Self {
+        Self::new(self.header)
+    }
+}
+
+impl Drop for AbortHandle {
+    fn drop(&mut self) {
+        let last = unsafe {
+            self.header.as_ref().state.release_ref()
+        };
+
+        if last {
+            Header::try_dealloc(self.header);
+        }
+    }
+}
+
+/// Handle to a task that doesn't keep its output alive, obtained through
+/// [`JoinHandle::downgrade`] or [`Planetary::spawn_weak`].
+///
+/// Once the task finished and no [`JoinHandle`] is left, the output is discarded, or
+/// sent to the result sink if the pool has one, and the handle can't be upgraded anymore.
+///
+/// [`Planetary::spawn_weak`]: crate::handle::Planetary::spawn_weak
+pub struct WeakJoinHandle<T> {
+    header: NonNull<Header>,
+    _marker: PhantomData<T>
+}
+
+// SAFETY: The output is only accessed through the handles it upgrades to.
+unsafe impl<T: Send> Send for WeakJoinHandle<T> {}
+unsafe impl<T: Send> Sync for WeakJoinHandle<T> {}
+
+impl<T> WeakJoinHandle<T> {
+    fn new(header: NonNull<Header>) -> Self {
+        unsafe {
+            header.as_ref().state.acquire_ref();
+        }
+
+        Self {
+            header,
+            _marker: PhantomData
+        }
+    }
+
+    /// Gets a [`JoinHandle`] to the task, or `None` if its output was already discarded
+    /// or another [`JoinHandle`] to it is alive, since only one can wait for the output.
+    pub fn upgrade(&self) -> Option<JoinHandle<T>> {
+        let acquired = unsafe {
+            self.header.as_ref().state.try_acquire_handle()
+        };
+
+        // SAFETY: The handle flag was set on behalf of the new handle.
+        acquired.then(|| unsafe { JoinHandle::from_raw(self.header) })
+    }
+
+    /// Checks whether the task is finished
+    pub fn is_finished(&self) -> bool {
         unsafe {
-            self.header.as_ref().state.set(State::HANDLE_ALIVE, false);
+            self.header.as_ref().state_snapshot().get(State::FINISHED)
+        }
+    }
+}
+
+impl<T> Clone for WeakJoinHandle<T> {
+    fn clone(&self) -> Self {
+        Self::new(self.header)
+    }
+}
+
+impl<T> Drop for WeakJoinHandle<T> {
+    fn drop(&mut self) {
+        let last = unsafe {
+            self.header.as_ref().state.release_ref()
+        };
+
+        if last {
+            Header::try_dealloc(self.header);
         }
-        
-        Header::try_dealloc(self.header);
     }
 }
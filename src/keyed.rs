@@ -0,0 +1,151 @@
+//! Coalescing of tasks spawned with the same key, see [`Planetary::spawn_keyed`].
+//!
+//! [`Planetary::spawn_keyed`]: crate::handle::Planetary::spawn_keyed
+
+use std::{any::{Any, TypeId}, collections::HashMap, hash::Hash, panic::{catch_unwind, resume_unwind, AssertUnwindSafe}, sync::{Arc, Mutex, OnceLock}};
+
+use crate::{core::Core, join::JoinHandle, task::{Runnable, Task, TypeErasedTask}};
+
+/// Output of a keyed task shared with the duplicates, `None` if the task panicked.
+type Shared<R> = Arc<OnceLock<Option<R>>>;
+
+/// Task queued or running for a key.
+struct Entry<R> {
+    output: Shared<R>,
+    /// Tasks handing the output to the handles of the duplicates, ran once the task finishes
+    followers: Vec<TypeErasedTask>,
+}
+
+type Entries<K, R> = HashMap<K, Entry<R>>;
+
+/// Keyed tasks queued or running in the threadpool, by key and output type.
+#[derive(Default)]
+pub(crate) struct KeyedTasks {
+    by_type: Mutex<HashMap<TypeId, Box<dyn Any + Send>>>,
+}
+
+impl KeyedTasks {
+    /// Runs `f` with the entries of the key and output type, under the lock.
+    fn with_entries<K, R, U>(&self, f: impl FnOnce(&mut Entries<K, R>) -> U) -> U
+    where
+        K: Eq + Hash + Send + 'static,
+        R: Send + Sync + 'static
+    {
+        let mut by_type = self.by_type.lock().unwrap_or_else(|l| l.into_inner());
+        let entries = by_type.entry(TypeId::of::<(K, R)>())
+            .or_insert_with(|| Box::new(Entries::<K, R>::new()))
+            .downcast_mut::<Entries<K, R>>()
+            .expect("keyed entries stored under the id of another type");
+
+        f(entries)
+    }
+
+    /// Removes the entry of the key, returning its followers.
+    fn finish<K, R>(&self, key: &K) -> Vec<TypeErasedTask>
+    where
+        K: Eq + Hash + Send + 'static,
+        R: Send + Sync + 'static
+    {
+        self.with_entries::<K, R, _>(|entries| entries.remove(key))
+            .map(|entry| entry.followers)
+            .unwrap_or_default()
+    }
+}
+
+/// Runs the runnable of a keyed task, handing its output to the duplicates once finished.
+struct KeyedRunnable<K, T>
+where
+    K: Eq + Hash + Send + 'static,
+    T: Runnable,
+    T::Output: Clone + Sync
+{
+    core: Core,
+    key: K,
+    runnable: Option<T>,
+    output: Shared<T::Output>,
+}
+
+impl<K, T> Runnable for KeyedRunnable<K, T>
+where
+    K: Eq + Hash + Send + 'static,
+    T: Runnable,
+    T::Output: Clone + Sync
+{
+    type Output = T::Output;
+
+    fn run(mut self) -> Self::Output {
+        let runnable = self.runnable.take().expect("keyed runnable ran twice");
+        let result = catch_unwind(AssertUnwindSafe(|| runnable.run()));
+        let _ = self.output.set(result.as_ref().ok().cloned());
+
+        // followers only clone the shared output, so they run within the leader, which
+        // already holds a permit and is in its work hooks, and are just counted
+        for follower in self.core.keyed.finish::<K, T::Output>(&self.key) {
+            let state = follower.run();
+            self.core.record_outcome(state);
+            self.core.task_completed(state);
+        }
+
+        match result {
+            Ok(output) => output,
+            Err(payload) => resume_unwind(payload)
+        }
+    }
+}
+
+impl<K, T> Drop for KeyedRunnable<K, T>
+where
+    K: Eq + Hash + Send + 'static,
+    T: Runnable,
+    T::Output: Clone + Sync
+{
+    fn drop(&mut self) {
+        // discarded without running, dropping the followers cancels them
+        if self.runnable.is_some() {
+            drop(self.core.keyed.finish::<K, T::Output>(&self.key));
+        }
+    }
+}
+
+pub(crate) fn spawn_keyed<K, T>(core: &Core, key: K, runnable: T) -> JoinHandle<T::Output>
+where
+    K: Eq + Hash + Clone + Send + 'static,
+    T: Runnable,
+    T::Output: Clone + Sync
+{
+    let output = core.keyed.with_entries::<K, T::Output, _>(|entries| {
+        if let Some(entry) = entries.get_mut(&key) {
+            let output = Arc::clone(&entry.output);
+            let follower = Task::new(move || match output.get() {
+                Some(Some(output)) => output.clone(),
+                _ => panic!("keyed task panicked")
+            }).erase();
+
+            let handle = JoinHandle::new(follower.header);
+            core.accept(&follower);
+            entry.followers.push(follower);
+            return Err(handle);
+        }
+
+        let output = Shared::default();
+        entries.insert(key.clone(), Entry { output: Arc::clone(&output), followers: Vec::new() });
+        Ok(output)
+    });
+
+    let output = match output {
+        Ok(output) => output,
+        Err(duplicate) => return duplicate,
+    };
+
+    let task = Task::new(KeyedRunnable {
+        core: core.clone(),
+        key,
+        runnable: Some(runnable),
+        output,
+    }).erase();
+
+    core.attach_result_sink(task.header());
+    let handle = JoinHandle::new(task.header);
+    core.spawn_task(task);
+    handle
+}
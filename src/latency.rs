@@ -0,0 +1,77 @@
+use std::{sync::atomic::{AtomicU64, Ordering}, time::Duration};
+
+/// Number of buckets, each one covering twice the range of the previous one,
+/// starting at one microsecond.
+const BUCKETS: usize = 40;
+
+/// Percentiles of the time tasks spent queued before starting to run,
+/// see [`Planetary::latency_percentiles`].
+///
+/// Values are rounded up to the next power of two microseconds, except for `max`.
+///
+/// [`Planetary::latency_percentiles`]: crate::handle::Planetary::latency_percentiles
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+/// Coarse histogram of queue latencies, cheap enough to be updated by the workers
+/// on every task.
+pub(crate) struct LatencyHistogram {
+    buckets: [AtomicU64; BUCKETS],
+    /// Highest latency recorded, in microseconds
+    max: AtomicU64,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+
+        self.buckets[bucket.min(BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+        self.max.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> LatencyStats {
+        let counts = self.buckets.each_ref().map(|b| b.load(Ordering::Relaxed));
+        let total = counts.iter().sum::<u64>();
+        let max = Duration::from_micros(self.max.load(Ordering::Relaxed));
+
+        let percentile = |q: f64| {
+            let target = ((total as f64 * q).ceil() as u64).max(1);
+            let mut seen = 0;
+
+            for (bucket, count) in counts.iter().enumerate() {
+                seen += count;
+
+                if seen >= target {
+                    // bucket `n` holds latencies below 2^n microseconds
+                    return Duration::from_micros(1 << bucket).min(max);
+                }
+            }
+
+            max
+        };
+
+        if total == 0 {
+            return LatencyStats::default();
+        }
+
+        LatencyStats {
+            p50: percentile(0.50),
+            p90: percentile(0.90),
+            p99: percentile(0.99),
+            max,
+        }
+    }
+}
@@ -1,24 +1,82 @@
-use std::any::Any;
-
-use crate::{handle::Planetary, join::JoinHandle, task::Runnable};
+use crate::{handle::Planetary, join::{JoinError, JoinHandle}, task::{IntoRunnable, RunnableOutput}};
 
+pub mod autoscale;
+pub mod barrier;
+mod blocking;
 pub mod builder;
+mod chain;
+pub mod channel;
 pub mod task;
 mod condvar;
 mod core;
 #[doc(hidden)]
 pub mod defer;
+pub mod future;
+pub mod group;
 pub mod handle;
 mod hooks;
+mod keyed;
+mod scope;
+#[cfg(feature = "inspect")]
+pub mod inspect;
+#[cfg(feature = "stack-guard")]
+mod stack;
+mod timer;
+#[cfg(feature = "numa")]
+mod topology;
 mod worker;
 pub mod join;
+pub mod latency;
+pub mod local;
+pub mod metrics;
+pub mod panic;
+pub mod partition;
+pub mod shutdown;
+pub mod steal;
+pub mod sync;
+pub mod testing;
 mod macros;
 
 #[cfg(test)]
 mod tests;
 
-pub type JoinResult<T> = Result<T, Box<dyn Any + Send + 'static>>;
+pub type JoinResult<T> = Result<T, JoinError>;
+
+pub use future::reschedule;
+pub use hooks::NameContext;
+pub use join::select;
+pub use panic::set_panic_observer;
 
-pub fn spawn<F: Runnable>(fun: F) -> JoinHandle<F::Output> {
+pub fn spawn<M, F: IntoRunnable<M>>(fun: F) -> JoinHandle<RunnableOutput<F, M>> {
     Planetary::current().spawn(fun)
 }
+
+/// Reports the progress of the task running on the current thread, as a fraction between
+/// 0 and 1, which can be read from its handle with [`JoinHandle::progress`]. Does nothing
+/// outside of a task.
+pub fn report_progress(fraction: f32) {
+    task::Header::with_current(|header| header.set_progress(fraction));
+}
+
+/// Checks whether the task running on the current thread was aborted while running, e.g.
+/// by the hard timeout of [`Planetary::spawn_hard_timeout`], so long tasks can stop early.
+/// Returns `false` outside of a task.
+pub fn is_aborted() -> bool {
+    task::Header::with_current(|header| header.state_snapshot().get(task::state::State::ABORTED))
+        .unwrap_or(false)
+}
+
+/// Gets a clone of the context the task running on the current thread was spawned with
+/// through [`Planetary::spawn_with_context`], if it has one of type `C`. Returns `None`
+/// outside of a task.
+pub fn current_context<C: Clone + 'static>() -> Option<C> {
+    task::Header::with_current(|header| header.context::<C>().cloned()).flatten()
+}
+
+pub fn spawn_future<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static
+{
+    Planetary::current().spawn_future(future)
+}
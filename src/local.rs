@@ -0,0 +1,106 @@
+use std::{cell::{Cell, OnceCell}, sync::{Arc, OnceLock}};
+
+use crate::{core::{Core, CoreInner}, defer};
+
+/// Value with a separate instance for each worker of a threadpool, lazily created
+/// the first time a worker accesses it. See [`Planetary::worker_local`].
+///
+/// Workers are identified by their id, so a respawned worker reuses the value
+/// left by the previous worker with the same id.
+///
+/// [`Planetary::worker_local`]: crate::handle::Planetary::worker_local
+pub struct WorkerLocal<T> {
+    inner: Arc<Inner<T>>
+}
+
+struct Inner<T> {
+    /// Threadpool the values belong to, only used for comparisons
+    core: *const CoreInner,
+    init: Box<dyn Fn() -> T + Send + Sync>,
+    /// One slot per worker id, grouped in buckets doubling in size so the slots of the
+    /// ids added by bursts are created without moving the existing ones
+    buckets: [OnceLock<Box<[Slot<T>]>>; BUCKETS],
+}
+
+const BUCKETS: usize = usize::BITS as usize;
+
+struct Slot<T> {
+    /// Set once and only lent out as shared references after that
+    value: OnceCell<T>,
+    /// Whether the initializer is running, to catch it accessing the value it's creating
+    initializing: Cell<bool>,
+}
+
+// SAFETY: Buckets are created through `OnceLock`, each slot is only accessed by the
+// worker that currently owns its id, and
+// references can't be sent to other threads unless `T` is `Sync`.
+unsafe impl<T: Send> Send for WorkerLocal<T> {}
+unsafe impl<T: Send> Sync for WorkerLocal<T> {}
+
+impl<T> WorkerLocal<T> {
+    pub(crate) fn new(core: &Core, init: impl Fn() -> T + Send + Sync + 'static) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                core: core.as_ptr(),
+                init: Box::new(init),
+                buckets: std::array::from_fn(|_| OnceLock::new())
+            })
+        }
+    }
+
+    /// Gets the value of the current worker, creating it if needed.
+    /// Panics if called outside a worker of the threadpool that created it, or if the
+    /// initializer accesses the value it's creating.
+    pub fn get(&self) -> &T {
+        self.try_get()
+            .expect("WorkerLocal::get must be called from a worker of its threadpool")
+    }
+
+    /// Gets the value of the current worker, creating it if needed, or `None` if
+    /// called outside a worker of the threadpool that created it.
+    ///
+    /// Panics if the initializer accesses the value it's creating.
+    pub fn try_get(&self) -> Option<&T> {
+        let worker = crate::worker::try_get_worker()?;
+
+        if worker.core().as_ptr() != self.inner.core {
+            return None;
+        }
+
+        let slot = self.inner.slot(worker.id());
+
+        if let Some(value) = slot.value.get() {
+            return Some(value);
+        }
+
+        assert!(!slot.initializing.replace(true), "WorkerLocal initializer accessed the value it's creating");
+        let value = {
+            defer!(|| slot.initializing.set(false));
+            (self.inner.init)()
+        };
+
+        Some(slot.value.get_or_init(|| value))
+    }
+}
+
+impl<T> Inner<T> {
+    fn slot(&self, id: usize) -> &Slot<T> {
+        // bucket `n` holds the `2^n` ids starting at `2^n - 1`
+        let bucket = (id + 1).ilog2() as usize;
+        let slots = self.buckets[bucket].get_or_init(|| {
+            (0..1usize << bucket)
+                .map(|_| Slot { value: OnceCell::new(), initializing: Cell::new(false) })
+                .collect()
+        });
+
+        &slots[id + 1 - (1 << bucket)]
+    }
+}
+
+impl<T> Clone for WorkerLocal<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone()
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::{sync::atomic::{AtomicU64, Ordering}, time::Duration};
+
+use crate::core::Core;
+
+/// Snapshot of the threadpool statistics, obtained through [`Planetary::metrics`].
+///
+/// [`Planetary::metrics`]: crate::handle::Planetary::metrics
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metrics {
+    /// Number of worker threads alive.
+    pub live_threads: usize,
+    /// Number of worker threads parked waiting for work.
+    pub idle_threads: usize,
+    /// Number of worker threads alive and not parked.
+    pub working_threads: usize,
+    /// Approximate number of tasks waiting in the global queue and the partitions.
+    pub queued_tasks: usize,
+    /// Number of tasks executed by the workers since the threadpool was built.
+    pub completed_tasks: u64,
+}
+
+impl Metrics {
+    pub(crate) fn sample(core: &Core) -> Self {
+        Self {
+            live_threads: core.live_threads(),
+            idle_threads: core.idle_threads(),
+            working_threads: core.working_threads(),
+            queued_tasks: core.queued_tasks(),
+            completed_tasks: core.completed_tasks(),
+        }
+    }
+}
+
+/// Counters accumulated since the threadpool was built or they were last reset, obtained
+/// through [`Planetary::stats`].
+///
+/// [`Planetary::stats`]: crate::handle::Planetary::stats
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CumulativeStats {
+    /// Number of tasks that ran to completion.
+    pub tasks_completed: u64,
+    /// Number of tasks that panicked.
+    pub tasks_panicked: u64,
+    /// Number of tasks that got aborted before running.
+    pub tasks_aborted: u64,
+    /// Number of worker threads spawned.
+    pub threads_spawned: u64,
+    /// Number of worker threads that exited after being idle for the timeout. Climbing along
+    /// with `threads_spawned` means workers keep getting respawned, due to a timeout too short
+    /// for the gaps in the load.
+    pub threads_reaped: u64,
+    /// Number of tasks stolen from other workers.
+    pub steals_succeeded: u64,
+    /// Number of times a worker found nothing to steal from the rest.
+    pub steals_failed: u64,
+}
+
+/// Receives the final [`CumulativeStats`] of a threadpool once it shut down.
+pub(crate) type FinalMetrics = Box<dyn FnOnce(CumulativeStats) + Send>;
+
+/// Atomic counters backing [`CumulativeStats`].
+#[derive(Default)]
+pub(crate) struct StatCounters {
+    pub tasks_completed: AtomicU64,
+    pub tasks_panicked: AtomicU64,
+    pub tasks_aborted: AtomicU64,
+    pub threads_spawned: AtomicU64,
+    pub threads_reaped: AtomicU64,
+    pub steals_succeeded: AtomicU64,
+    pub steals_failed: AtomicU64,
+}
+
+impl StatCounters {
+    pub fn bump(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> CumulativeStats {
+        CumulativeStats {
+            tasks_completed: self.tasks_completed.load(Ordering::Relaxed),
+            tasks_panicked: self.tasks_panicked.load(Ordering::Relaxed),
+            tasks_aborted: self.tasks_aborted.load(Ordering::Relaxed),
+            threads_spawned: self.threads_spawned.load(Ordering::Relaxed),
+            threads_reaped: self.threads_reaped.load(Ordering::Relaxed),
+            steals_succeeded: self.steals_succeeded.load(Ordering::Relaxed),
+            steals_failed: self.steals_failed.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn reset(&self) {
+        for counter in [
+            &self.tasks_completed,
+            &self.tasks_panicked,
+            &self.tasks_aborted,
+            &self.threads_spawned,
+            &self.threads_reaped,
+            &self.steals_succeeded,
+            &self.steals_failed,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Receives periodic [`Metrics`] snapshots of the threadpool, see
+/// [`PlanetaryBuilder::metrics_sink`].
+///
+/// [`PlanetaryBuilder::metrics_sink`]: crate::builder::PlanetaryBuilder::metrics_sink
+pub trait MetricsSink {
+    fn record(&self, metrics: &Metrics);
+}
+
+impl<F: Fn(&Metrics)> MetricsSink for F {
+    fn record(&self, metrics: &Metrics) {
+        self(metrics)
+    }
+}
+
+/// Sink and sampling interval configured on the builder.
+pub(crate) struct SinkConfig {
+    pub sink: Box<dyn MetricsSink + Send + Sync>,
+    pub interval: Duration,
+}
+
+/// Spawns the sampler thread, which runs until the threadpool stops.
+pub(crate) fn spawn_sampler(core: Core, config: SinkConfig) {
+    let sampler_core = core.clone();
+    let handle = std::thread::Builder::new()
+        .name("planetary-metrics".to_string())
+        .spawn(move || run_sampler(sampler_core, config))
+        .unwrap_or_else(|_| panic!("Failed to spawn metrics sampler thread"));
+
+    *core.sampler.lock().unwrap_or_else(|s| s.into_inner()) = Some(handle);
+}
+
+fn run_sampler(core: Core, config: SinkConfig) {
+    while !core.should_stop() {
+        config.sink.record(&Metrics::sample(&core));
+        // woken up by the shutdown, so joining the sampler doesn't wait for a whole interval
+        core.sleep_unless_stopped(config.interval);
+    }
+}
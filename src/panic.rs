@@ -0,0 +1,45 @@
+//! Process wide observer of task panics, see [`set_panic_observer`].
+
+use std::{any::Any, panic::{catch_unwind, AssertUnwindSafe}, sync::{Arc, RwLock}};
+
+type Observer = Arc<dyn Fn(TaskPanic<'_>) + Send + Sync>;
+
+static OBSERVER: RwLock<Option<Observer>> = RwLock::new(None);
+
+/// Panic of a task, received by the observer set with [`set_panic_observer`].
+pub struct TaskPanic<'a> {
+    /// Id of the task that panicked
+    pub task_id: u64,
+    /// Payload the task panicked with
+    pub payload: &'a (dyn Any + Send),
+}
+
+impl TaskPanic<'_> {
+    /// Gets the message of the panic, if it was raised with one.
+    pub fn message(&self) -> Option<&str> {
+        self.payload.downcast_ref::<&str>()
+            .copied()
+            .or_else(|| self.payload.downcast_ref::<String>().map(String::as_str))
+    }
+}
+
+/// Sets the observer called with every task panic, on every threadpool of the process,
+/// replacing the previous one. It runs on the worker that ran the task, before the
+/// handle gets the panic, and panics raised by it are ignored.
+pub fn set_panic_observer(observer: impl Fn(TaskPanic<'_>) + Send + Sync + 'static) {
+    *OBSERVER.write().unwrap_or_else(|s| s.into_inner()) = Some(Arc::new(observer));
+}
+
+/// Removes the observer set with [`set_panic_observer`].
+pub fn clear_panic_observer() {
+    *OBSERVER.write().unwrap_or_else(|s| s.into_inner()) = None;
+}
+
+pub(crate) fn observe(task_id: u64, payload: &(dyn Any + Send)) {
+    // called without holding the lock, so the observer can replace itself
+    let observer = OBSERVER.read().unwrap_or_else(|s| s.into_inner()).clone();
+
+    if let Some(observer) = observer {
+        let _ = catch_unwind(AssertUnwindSafe(|| observer(TaskPanic { task_id, payload })));
+    }
+}
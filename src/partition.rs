@@ -0,0 +1,154 @@
+use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+
+use crossbeam_deque::Injector;
+
+use crate::{core::Core, join::JoinHandle, task::{IntoRunnable, RunnableOutput, Task, TypeErasedTask}};
+
+/// Configuration of a [`Partition`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PartitionConfig {
+    /// Maximum number of tasks of the partition running at the same time.
+    pub max_concurrent: Option<usize>,
+}
+
+impl PartitionConfig {
+    /// Limits the number of tasks of the partition running at the same time.
+    pub fn max_concurrent(mut self, tasks: usize) -> Self {
+        self.max_concurrent = Some(tasks);
+        self
+    }
+}
+
+/// Named class of tasks with its own queue and concurrency limit, executed by the
+/// workers of the threadpool it was created in, see [`Planetary::partition`].
+///
+/// Workers take turns between the partitions with queued tasks, skipping those that
+/// reached their limit, so a busy partition can't take all the workers.
+///
+/// [`Planetary::partition`]: crate::handle::Planetary::partition
+#[derive(Clone)]
+pub struct Partition {
+    core: Core,
+    inner: Arc<PartitionInner>
+}
+
+pub(crate) struct PartitionInner {
+    name: String,
+    injector: Injector<TypeErasedTask>,
+    max_concurrent: Option<usize>,
+    /// Number of tasks of the partition currently running
+    running: AtomicUsize,
+}
+
+/// Running slot of a partition, released when dropped.
+pub(crate) struct PartitionSlot {
+    core: Core,
+    partition: Arc<PartitionInner>
+}
+
+impl Partition {
+    pub(crate) fn new(core: Core, inner: Arc<PartitionInner>) -> Self {
+        Self { core, inner }
+    }
+
+    /// Name the partition was created with in [`Planetary::partition`].
+    ///
+    /// [`Planetary::partition`]: crate::handle::Planetary::partition
+    pub fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    /// Number of tasks of the partition waiting to run.
+    pub fn queued_tasks(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Spawns a new [`Runnable`](crate::task::Runnable) into the partition, returning
+    /// a handle to interact with it.
+    pub fn spawn<M, F: IntoRunnable<M>>(&self, runnable: F) -> JoinHandle<RunnableOutput<F, M>> {
+        let task = Task::new(runnable).erase();
+        self.core.attach_result_sink(task.header());
+        // created before the task is visible to the workers, see `Planetary::submit`
+        let handle = JoinHandle::new(task.header);
+        self.core.spawn_partitioned(&self.inner, task);
+
+        handle
+    }
+}
+
+impl PartitionInner {
+    pub(crate) fn new(name: &str, config: PartitionConfig) -> Self {
+        Self {
+            name: name.to_string(),
+            injector: Injector::new(),
+            max_concurrent: config.max_concurrent,
+            running: AtomicUsize::new(0)
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn push(&self, task: TypeErasedTask) {
+        self.injector.push(task);
+    }
+
+    /// Number of queued tasks.
+    pub(crate) fn len(&self) -> usize {
+        self.injector.len()
+    }
+
+    /// Whether the partition has no queued tasks.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.injector.is_empty()
+    }
+
+    /// Whether the partition has queued tasks and room to run one of them.
+    pub(crate) fn is_runnable(&self) -> bool {
+        !self.injector.is_empty()
+            && self.max_concurrent.is_none_or(|max| self.running.load(Ordering::SeqCst) < max)
+    }
+
+    /// Takes a queued task along with a running slot, if the limit allows it.
+    pub(crate) fn take(self: &Arc<Self>, core: &Core) -> Option<(TypeErasedTask, PartitionSlot)> {
+        if self.injector.is_empty() {
+            return None;
+        }
+
+        let acquired = self.running.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |running| {
+            self.max_concurrent
+                .is_none_or(|max| running < max)
+                .then_some(running + 1)
+        });
+
+        if acquired.is_err() {
+            return None;
+        }
+
+        let slot = PartitionSlot {
+            core: core.clone(),
+            partition: Arc::clone(self)
+        };
+
+        crate::core::steal_task(&self.injector).map(|task| (task, slot))
+    }
+
+    pub(crate) fn cancel_queued(&self) {
+        while let Some(task) = crate::core::steal_task(&self.injector) {
+            task.cancel();
+        }
+    }
+}
+
+impl Drop for PartitionSlot {
+    fn drop(&mut self) {
+        let previous = self.partition.running.fetch_sub(1, Ordering::SeqCst);
+        debug_assert!(previous > 0, "partition running counter underflowed");
+
+        // tasks may be waiting for this slot with every worker parked
+        if !self.partition.injector.is_empty() {
+            self.core.notify_worker();
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Tasks allowed to borrow from the thread spawning them, used by
+//! [`Planetary::parallelize`] and [`Planetary::on_worker`].
+//!
+//! This is the only place erasing the lifetime of a task. It's sound because [`scope`]
+//! doesn't return, nor unwind, until every job spawned from it was dropped, either after
+//! running or when the threadpool discarded it, so their borrows end before `'env` does.
+
+use std::{marker::PhantomData, sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}}};
+
+use crate::{condvar::Cv, defer, handle::Planetary, join::JoinHandle, task::{Parker, Runnable}};
+
+/// Job of a scope with its borrows erased from its type.
+type ErasedJob<R> = Box<dyn FnOnce() -> R + Send>;
+
+/// Counts the jobs of a scope that are still alive.
+struct Latch {
+    pending: AtomicUsize,
+    cv: Cv,
+    /// Workers waiting for the latch while helping the threadpool
+    parker: Mutex<Parker>
+}
+
+impl Latch {
+    fn count_down(&self) {
+        if self.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.cv.notify_all_locked();
+            self.parker.lock().unwrap_or_else(|s| s.into_inner()).take().wake();
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.pending.load(Ordering::SeqCst) == 0
+    }
+}
+
+/// Task spawned from a [`Scope`], which counts itself down once dropped.
+struct ScopedJob<R> {
+    job: Option<ErasedJob<R>>,
+    latch: Arc<Latch>
+}
+
+impl<R: Send + 'static> Runnable for ScopedJob<R> {
+    type Output = R;
+
+    fn run(mut self) -> R {
+        // the job is consumed by the call, so its borrows end before `self` is dropped
+        (self.job.take().expect("scoped job ran twice"))()
+    }
+}
+
+impl<R> Drop for ScopedJob<R> {
+    fn drop(&mut self) {
+        drop(self.job.take());
+        self.latch.count_down();
+    }
+}
+
+/// Spawns tasks that may borrow anything outliving `'env`, see [`scope`].
+pub(crate) struct Scope<'pool, 'env> {
+    pool: &'pool Planetary,
+    latch: Arc<Latch>,
+    /// Invariant over `'env`, so it can't be shrunk to fit borrows of shorter lifetimes.
+    env: PhantomData<&'env mut &'env ()>
+}
+
+impl<'env> Scope<'_, 'env> {
+    fn job<R>(&self, f: impl FnOnce() -> R + Send + 'env) -> ScopedJob<R> {
+        let job: Box<dyn FnOnce() -> R + Send + 'env> = Box::new(f);
+        // SAFETY: The scope waits for the latch to open before returning or unwinding,
+        // which only happens once this job was dropped, so it can't outlive `'env`.
+        let job = unsafe { std::mem::transmute::<Box<dyn FnOnce() -> R + Send + 'env>, ErasedJob<R>>(job) };
+        self.latch.pending.fetch_add(1, Ordering::SeqCst);
+
+        ScopedJob { job: Some(job), latch: Arc::clone(&self.latch) }
+    }
+
+    /// Spawns `f` into the threadpool.
+    pub fn spawn<R: Send + 'static>(&self, f: impl FnOnce() -> R + Send + 'env) -> JoinHandle<R> {
+        self.pool.spawn(self.job(f))
+    }
+
+    /// Spawns `f` on the worker with the given id, see [`Planetary::try_spawn_on`].
+    pub fn try_spawn_on<R: Send + 'static>(&self, worker_id: usize, f: impl FnOnce() -> R + Send + 'env) -> Option<JoinHandle<R>> {
+        self.pool.try_spawn_on(worker_id, self.job(f)).ok()
+    }
+
+    /// Waits until every job of the scope was dropped. Workers of the threadpool
+    /// execute other tasks meanwhile, as the jobs may be queued behind them.
+    fn wait(&self) {
+        let latch = &*self.latch;
+        let worker = crate::worker::try_get_worker()
+            .filter(|w| w.core().as_ptr() == self.pool.inner.as_ptr());
+
+        match worker {
+            Some(worker) => crate::worker::help_until(worker, || latch.is_open(), |thread| {
+                latch.parker.lock().unwrap_or_else(|s| s.into_inner()).set_thread(thread.clone());
+            }),
+            None => {
+                let _suspended = crate::core::suspend_task_permits();
+                latch.cv.wait_until(|| latch.is_open());
+            }
+        }
+    }
+}
+
+/// Runs `f` with a [`Scope`] to spawn tasks borrowing from the caller, returning
+/// once all of them are done with their borrows, even if `f` panics.
+pub(crate) fn scope<'pool, 'env, T>(pool: &'pool Planetary, f: impl FnOnce(&Scope<'pool, 'env>) -> T) -> T {
+    let scope = Scope {
+        pool,
+        latch: Arc::new(Latch { pending: AtomicUsize::new(0), cv: Cv::new(), parker: Mutex::default() }),
+        env: PhantomData
+    };
+    defer!(|| scope.wait());
+
+    f(&scope)
+}
@@ -0,0 +1,75 @@
+use std::{future::Future, pin::Pin, sync::{Arc, Weak}, task::{Context, Poll, Wake}, time::Duration};
+
+use crate::core::{Core, CoreInner};
+
+/// Waits for the workers of a threadpool to exit after [`Planetary::shutdown_async`].
+///
+/// It can be awaited, or waited on from synchronous code with [`ShutdownWaiter::wait`]
+/// and [`ShutdownWaiter::wait_timeout`].
+///
+/// [`Planetary::shutdown_async`]: crate::handle::Planetary::shutdown_async
+#[must_use = "The shutdown is already in progress, drop the waiter if there's no need to wait for it"]
+pub struct ShutdownWaiter {
+    core: Core
+}
+
+impl ShutdownWaiter {
+    pub(crate) fn new(core: Core) -> Self {
+        Self { core }
+    }
+
+    /// Whether all the workers have exited.
+    pub fn is_finished(&self) -> bool {
+        self.core.all_stopped()
+    }
+
+    /// Blocks until all the workers and blocking threads have exited.
+    pub fn wait(self) {
+        self.core.wait_stop();
+    }
+
+    /// Blocks until all the workers have exited or the timeout elapses, returning
+    /// whether the workers exited in time.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        self.core.wait_stop_timeout(timeout)
+    }
+}
+
+impl Future for ShutdownWaiter {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.core.all_stopped() {
+            return Poll::Ready(());
+        }
+
+        self.core.register_stop_waker(cx.waker());
+
+        // the last worker may have exited before the waker was registered
+        if self.core.all_stopped() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Registered on the tasks detached with [`JoinHandle::detach_and_wait_on_shutdown`],
+/// letting a pending shutdown go on once they finish.
+///
+/// [`JoinHandle::detach_and_wait_on_shutdown`]: crate::join::JoinHandle::detach_and_wait_on_shutdown
+pub(crate) struct AwaitedTaskWaker(Weak<CoreInner>);
+
+impl AwaitedTaskWaker {
+    pub fn new(core: &Core) -> Self {
+        Self(core.downgrade())
+    }
+}
+
+impl Wake for AwaitedTaskWaker {
+    fn wake(self: Arc<Self>) {
+        if let Some(core) = Core::upgrade(&self.0) {
+            core.try_finish_shutdown();
+        }
+    }
+}
@@ -0,0 +1,44 @@
+//! Approximate stack usage probing for worker threads, used to refuse running tasks
+//! once a worker is close to overflowing its stack.
+
+use std::cell::Cell;
+
+/// Stack size used by `std` when spawning threads without an explicit size.
+const DEFAULT_STACK_SIZE: usize = 2 * 1024 * 1024;
+
+thread_local! {
+    /// Address near the top of the current worker's stack, zero outside workers.
+    static STACK_TOP: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Returns the address of a local of the caller's frame, which approximates the
+/// current stack pointer.
+#[inline(never)]
+fn stack_pointer() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+/// Records the current position as the top of the stack of this thread,
+/// must be called as early as possible when a worker starts.
+pub fn mark_top() {
+    STACK_TOP.with(|top| top.set(stack_pointer()));
+}
+
+/// Approximate number of bytes left on the stack of the current worker, `None` if
+/// the thread isn't a worker or stack usage can't be probed on this platform.
+pub fn remaining(stack_size: Option<usize>) -> Option<usize> {
+    // only platforms whose stacks grow downwards are supported
+    if !cfg!(any(target_arch = "x86", target_arch = "x86_64", target_arch = "arm", target_arch = "aarch64")) {
+        return None;
+    }
+
+    let top = STACK_TOP.with(|top| top.get());
+
+    if top == 0 {
+        return None;
+    }
+
+    let used = top.saturating_sub(stack_pointer());
+    Some(stack_size.unwrap_or(DEFAULT_STACK_SIZE).saturating_sub(used))
+}
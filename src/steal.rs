@@ -0,0 +1,71 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Worker that can be picked as a steal target.
+///
+/// More fields may be added in the future, so build it with [`Peer::new`] outside this crate,
+/// e.g. to test a [`StealStrategy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Peer {
+    /// Id of the worker.
+    pub id: usize,
+    /// Approximate number of tasks in its local queue.
+    pub queue_len: usize,
+    /// NUMA node the worker runs on, `None` unless the `numa` feature is enabled
+    /// and the topology could be read.
+    pub node: Option<usize>,
+}
+
+impl Peer {
+    /// Creates a peer with the given worker id, local queue length and NUMA node.
+    pub fn new(id: usize, queue_len: usize, node: Option<usize>) -> Self {
+        Self { id, queue_len, node }
+    }
+}
+
+/// Chooses which worker to steal from once the global injector is empty,
+/// see [`PlanetaryBuilder::steal_strategy`].
+///
+/// [`PlanetaryBuilder::steal_strategy`]: crate::builder::PlanetaryBuilder::steal_strategy
+pub trait StealStrategy {
+    /// Returns the index in `peers` of the worker to steal from, or `None` to give up.
+    /// `peers` includes the worker trying to steal, identified by `self_id`, and is
+    /// called again for every failed attempt.
+    fn pick(&self, self_id: usize, peers: &[Peer]) -> Option<usize>;
+}
+
+/// Picks a random worker, the default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Random;
+
+impl StealStrategy for Random {
+    fn pick(&self, _self_id: usize, peers: &[Peer]) -> Option<usize> {
+        (!peers.is_empty()).then(|| fastrand::usize(0..peers.len()))
+    }
+}
+
+/// Cycles through the workers, shared by all the workers of the threadpool.
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    next: AtomicUsize,
+}
+
+impl StealStrategy for RoundRobin {
+    fn pick(&self, _self_id: usize, peers: &[Peer]) -> Option<usize> {
+        (!peers.is_empty()).then(|| self.next.fetch_add(1, Ordering::Relaxed) % peers.len())
+    }
+}
+
+/// Picks the worker with the most tasks in its local queue.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MostLoaded;
+
+impl StealStrategy for MostLoaded {
+    fn pick(&self, self_id: usize, peers: &[Peer]) -> Option<usize> {
+        peers.iter()
+            .enumerate()
+            .filter(|(_, peer)| peer.id != self_id && peer.queue_len > 0)
+            .max_by_key(|(_, peer)| peer.queue_len)
+            .map(|(index, _)| index)
+    }
+}
@@ -0,0 +1,188 @@
+//! Synchronization primitives for futures spawned with [`Planetary::spawn_future`].
+//!
+//! Waiting on them never blocks the worker polling the future: the future is parked
+//! and rescheduled into the threadpool once it may proceed, so the worker runs other
+//! tasks meanwhile, even on a single threaded pool.
+//!
+//! Waiters are woken all at once and race for the notification or the lock, the ones
+//! losing the race go back to wait.
+//!
+//! [`Planetary::spawn_future`]: crate::handle::Planetary::spawn_future
+
+use std::{cell::UnsafeCell, future::Future, marker::PhantomData, ops::{Deref, DerefMut}, pin::Pin, sync::MutexGuard as StdGuard, task::{Context, Poll}};
+
+use crate::task::Parker;
+
+/// Lock guarding the state of a primitive along with the wakers waiting on it.
+struct State<T> {
+    inner: std::sync::Mutex<(T, Parker)>,
+}
+
+impl<T> State<T> {
+    fn new(value: T) -> Self {
+        Self { inner: std::sync::Mutex::new((value, Parker::default())) }
+    }
+
+    fn lock(&self) -> StdGuard<'_, (T, Parker)> {
+        self.inner.lock().unwrap_or_else(|s| s.into_inner())
+    }
+
+    /// Updates the state and wakes every waiter, outside of the lock.
+    fn update_and_wake(&self, f: impl FnOnce(&mut T)) {
+        let waiters = {
+            let mut state = self.lock();
+            f(&mut state.0);
+            state.1.take()
+        };
+
+        waiters.wake();
+    }
+
+    /// Calls `f` with the state, registering the waker of the caller if it returns `None`.
+    fn poll_with<R>(&self, key: usize, cx: &mut Context<'_>, f: impl FnOnce(&mut T) -> Option<R>) -> Poll<R> {
+        let mut state = self.lock();
+
+        match f(&mut state.0) {
+            Some(output) => Poll::Ready(output),
+            None => {
+                state.1.set_waker(key, cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Notifies a future waiting on it, see [`Notify::notified`].
+pub struct Notify {
+    /// Whether there is a notification nobody took yet
+    state: State<bool>,
+}
+
+impl Notify {
+    /// Creates a `Notify` without any pending notification.
+    pub fn new() -> Self {
+        Self { state: State::new(false) }
+    }
+
+    /// Notifies a future waiting in [`Notify::notified`]. If none is waiting, the
+    /// notification is stored and taken by the next one, further notifications sent
+    /// before that are merged into it.
+    pub fn notify_one(&self) {
+        self.state.update_and_wake(|permit| *permit = true);
+    }
+
+    /// Waits until [`Notify::notify_one`] is called, or takes the stored notification.
+    pub fn notified(&self) -> Notified<'_> {
+        Notified { notify: self }
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Future returned by [`Notify::notified`].
+pub struct Notified<'a> {
+    notify: &'a Notify,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.notify.state.poll_with(&*self as *const Self as usize, cx, |permit| std::mem::take(permit).then_some(()))
+    }
+}
+
+/// Mutual exclusion lock whose [`Mutex::lock`] waits without blocking the worker.
+pub struct Mutex<T> {
+    /// Whether the lock is held
+    state: State<bool>,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: The value is only accessed through a guard, of which there is at most one.
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    /// Creates an unlocked mutex holding `value`.
+    pub fn new(value: T) -> Self {
+        Self {
+            state: State::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Waits until the lock is free and acquires it.
+    pub fn lock(&self) -> Lock<'_, T> {
+        Lock { mutex: self }
+    }
+
+    /// Acquires the lock if it's free.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let mut state = self.state.lock();
+        self.acquire(&mut state.0)
+    }
+
+    /// Consumes the mutex, returning the value it holds.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    fn acquire(&self, locked: &mut bool) -> Option<MutexGuard<'_, T>> {
+        // built lazily, dropping a guard releases the lock
+        (!std::mem::replace(locked, true)).then(|| MutexGuard { mutex: self, _marker: PhantomData })
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Future returned by [`Mutex::lock`].
+pub struct Lock<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<'a, T> Future for Lock<'a, T> {
+    type Output = MutexGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mutex = self.mutex;
+        mutex.state.poll_with(&*self as *const Self as usize, cx, |locked| mutex.acquire(locked))
+    }
+}
+
+/// Holds the lock of a [`Mutex`] until dropped, giving access to its value.
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+    /// Shares the value like a mutable reference, so the guard is only `Sync` if `T` is
+    _marker: PhantomData<&'a mut T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: The guard holds the lock.
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: The guard holds the lock.
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.state.update_and_wake(|locked| *locked = false);
+    }
+}
@@ -0,0 +1,42 @@
+use crate::join::JoinHandle;
+
+use super::{runnable::{IntoRunnable, Runnable}, storage::TaskStorage, sync::{Task, TypeErasedTask}};
+
+/// Builds a [`TypeErasedTask`] along with the typed [`JoinHandle`] of its output, so
+/// custom schedulers can create tasks ahead of time and hand them to a threadpool
+/// later through [`Planetary::spawn_erased`].
+///
+/// The handle is created together with the task and is the only way to get its output,
+/// which keeps the erased task and the output type in sync. The erased task can be
+/// spawned into any threadpool once, and dropping it without spawning it cancels it.
+///
+/// [`Planetary::spawn_erased`]: crate::handle::Planetary::spawn_erased
+pub struct TaskBuilder<T: Runnable> {
+    runnable: T
+}
+
+impl<T: Runnable> TaskBuilder<T> {
+    /// Starts building a task running the given closure or [`Runnable`].
+    pub fn new<M>(runnable: impl IntoRunnable<M, Runnable = T>) -> Self {
+        Self {
+            runnable: runnable.into_runnable()
+        }
+    }
+
+    /// Allocates the task on the heap.
+    pub fn build(self) -> (TypeErasedTask, JoinHandle<T::Output>) {
+        Self::with_handle(Task::new(self.runnable).erase())
+    }
+
+    /// Places the task into the provided storage instead of allocating it on the heap.
+    ///
+    /// Fails to compile if the task does not fit into `N` bytes.
+    pub fn build_in<const N: usize>(self, storage: &'static mut TaskStorage<N>) -> (TypeErasedTask, JoinHandle<T::Output>) {
+        Self::with_handle(Task::new(self.runnable).erase_into(storage))
+    }
+
+    fn with_handle(task: TypeErasedTask) -> (TypeErasedTask, JoinHandle<T::Output>) {
+        let handle = JoinHandle::new(task.header);
+        (task, handle)
+    }
+}
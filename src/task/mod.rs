@@ -1,12 +1,20 @@
+mod builder;
 mod park;
 mod sync;
 mod runnable;
+mod storage;
+pub(crate) mod timing;
 pub(crate) mod state;
 mod vtable;
 
 
-pub use runnable::Runnable;
+pub use runnable::{from_fn, FnMarker, FnRunnable, IntoRunnable, Runnable, RunnableMarker, RunnableOutput};
+pub use builder::TaskBuilder;
+pub use storage::TaskStorage;
+pub use sync::{Header, TypeErasedTask};
 
 pub(crate) use {
-    sync::{Task, TypeErasedTask, Header}
+    park::Parker,
+    runnable::Finalized,
+    sync::{Task, ResultSink}
 };
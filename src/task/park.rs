@@ -1,13 +1,40 @@
 use std::{task::Waker, thread::Thread};
 
-#[derive(Default)]
-pub enum Parker {
-    Waker(Waker),
+/// A party waiting for a task to finish.
+pub enum Waiter {
+    /// Waker along with the address of whatever registered it, like the polled future
+    Waker(usize, Waker),
     Thread(Thread),
-    #[default]
-    None
 }
 
+impl Waiter {
+    /// Checks whether both waiters would wake the same party, or were registered by the
+    /// same one, which only needs the last waker it gave.
+    fn wakes_same(&self, other: &Waiter) -> bool {
+        match (self, other) {
+            (Waiter::Waker(a_key, a), Waiter::Waker(b_key, b)) => a_key == b_key || a.will_wake(b),
+            (Waiter::Thread(a), Waiter::Thread(b)) => a.id() == b.id(),
+            _ => false
+        }
+    }
+
+    fn wake(self) {
+        match self {
+            Waiter::Waker(_, waker) => waker.wake(),
+            Waiter::Thread(thread) => thread.unpark(),
+        }
+    }
+}
+
+/// Parties to be woken once a task finishes.
+///
+/// The first waiter is stored inline, so the common case of a single handle waiting
+/// doesn't allocate, the rest spill into a vector.
+#[derive(Default)]
+pub struct Parker {
+    first: Option<Waiter>,
+    rest: Vec<Waiter>,
+}
 
 impl Parker {
     #[allow(unused)]
@@ -15,26 +42,37 @@ impl Parker {
         Self::default()
     }
 
-    pub fn set_waker(&mut self, waker: Waker) {
-        *self = Parker::Waker(waker);
+    /// Registers the waker of the party identified by `key`, usually its address,
+    /// replacing the waker that party registered before.
+    pub fn set_waker(&mut self, key: usize, waker: Waker) {
+        self.register(Waiter::Waker(key, waker));
     }
 
     pub fn set_thread(&mut self, thread: Thread) {
-        *self = Parker::Thread(thread);
+        self.register(Waiter::Thread(thread));
     }
 
-    pub fn wake(self) {
-        match self {
-            Parker::Waker(waker) => {
-                waker.wake();
-            }
-            Parker::Thread(thread) => {
-                thread.unpark();
-            }
-            Parker::None => {}
+    /// Adds a waiter, replacing any registered waiter of the same party, so waiting
+    /// repeatedly doesn't grow the list.
+    fn register(&mut self, waiter: Waiter) {
+        let Some(first) = &mut self.first else {
+            self.first = Some(waiter);
+            return;
+        };
+
+        match std::iter::once(first).chain(self.rest.iter_mut()).find(|w| w.wakes_same(&waiter)) {
+            Some(slot) => *slot = waiter,
+            None => self.rest.push(waiter)
         }
     }
 
+    /// Wakes every registered waiter.
+    pub fn wake(self) {
+        self.first.into_iter()
+            .chain(self.rest)
+            .for_each(Waiter::wake);
+    }
+
     pub fn take(&mut self) -> Self {
         std::mem::take(self)
     }
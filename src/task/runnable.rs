@@ -1,17 +1,124 @@
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+
+use crate::{join::JoinError, JoinResult};
+
 pub trait Runnable {
     type Output: Send + 'static;
 
     fn run(self) -> Self::Output;
 }
 
-impl<T, R> Runnable for T
+/// Runs a closure as a [`Runnable`], see [`from_fn`].
+pub struct FnRunnable<F>(F);
+
+impl<F> FnRunnable<F> {
+    /// Gives back the wrapped closure.
+    pub fn into_inner(self) -> F {
+        self.0
+    }
+}
+
+impl<F, R> Runnable for FnRunnable<F>
 where
-    T: FnOnce() -> R,
+    F: FnOnce() -> R,
     R: Send + 'static,
 {
     type Output = R;
 
     fn run(self) -> Self::Output {
-        self()
+        (self.0)()
+    }
+}
+
+/// Wraps a closure into a [`Runnable`].
+pub fn from_fn<F, R>(f: F) -> FnRunnable<F>
+where
+    F: FnOnce() -> R,
+    R: Send + 'static,
+{
+    FnRunnable(f)
+}
+
+/// Runs a finalizer with the outcome of the wrapped runnable, or with
+/// [`JoinError::Aborted`] if it gets dropped without running.
+pub(crate) struct Finalized<T: Runnable, G: FnOnce(&JoinResult<T::Output>)> {
+    runnable: Option<T>,
+    finalizer: Option<G>,
+}
+
+impl<T: Runnable, G: FnOnce(&JoinResult<T::Output>)> Finalized<T, G> {
+    pub(crate) fn new(runnable: T, finalizer: G) -> Self {
+        Self { runnable: Some(runnable), finalizer: Some(finalizer) }
+    }
+}
+
+impl<T: Runnable, G: FnOnce(&JoinResult<T::Output>)> Runnable for Finalized<T, G> {
+    type Output = T::Output;
+
+    fn run(mut self) -> Self::Output {
+        let (Some(runnable), Some(finalizer)) = (self.runnable.take(), self.finalizer.take()) else {
+            unreachable!("finalized runnable ran twice");
+        };
+
+        let result = catch_unwind(AssertUnwindSafe(|| runnable.run())).map_err(JoinError::Panic);
+        finalizer(&result);
+
+        match result {
+            Ok(output) => output,
+            Err(JoinError::Panic(payload)) => resume_unwind(payload),
+            Err(_) => unreachable!("runnables only fail by panicking"),
+        }
+    }
+}
+
+impl<T: Runnable, G: FnOnce(&JoinResult<T::Output>)> Drop for Finalized<T, G> {
+    fn drop(&mut self) {
+        // still there if the task got discarded before running
+        if let Some(finalizer) = self.finalizer.take() {
+            drop(self.runnable.take());
+            // dropped from the abort path, which must not unwind
+            let _ = catch_unwind(AssertUnwindSafe(|| finalizer(&Err(JoinError::Aborted))));
+        }
     }
 }
+
+/// Marker for [`IntoRunnable`] implemented by types implementing [`Runnable`].
+pub struct RunnableMarker;
+
+/// Marker for [`IntoRunnable`] implemented by closures.
+pub struct FnMarker;
+
+/// Conversion into a [`Runnable`], accepted by the spawn functions so both closures and
+/// [`Runnable`] types can be spawned.
+///
+/// Closures are converted through [`FnRunnable`] instead of implementing [`Runnable`]
+/// directly, leaving callable types free to implement [`Runnable`] themselves. Such types
+/// implement both conversions, so the marker must be given explicitly when spawning them.
+pub trait IntoRunnable<Marker> {
+    type Runnable: Runnable;
+
+    fn into_runnable(self) -> Self::Runnable;
+}
+
+impl<T: Runnable> IntoRunnable<RunnableMarker> for T {
+    type Runnable = T;
+
+    fn into_runnable(self) -> Self::Runnable {
+        self
+    }
+}
+
+impl<F, R> IntoRunnable<FnMarker> for F
+where
+    F: FnOnce() -> R,
+    R: Send + 'static,
+{
+    type Runnable = FnRunnable<F>;
+
+    fn into_runnable(self) -> Self::Runnable {
+        FnRunnable(self)
+    }
+}
+
+/// Output of the [`Runnable`] `F` converts into.
+pub type RunnableOutput<F, M> = <<F as IntoRunnable<M>>::Runnable as Runnable>::Output;
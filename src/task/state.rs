@@ -1,32 +1,53 @@
-use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::atomic::{AtomicU32, Ordering};
 
 /// State representing the state of a task.
-pub struct State(AtomicU16);
+pub struct State(AtomicU32);
 
 impl State {
     /// Whether the task is running.
-    pub const RUNNING: u16 = 0b0000_0000_0000_0001;
+    pub const RUNNING: u32 = 0b0000_0000_0000_0001;
     /// Whether the task finished running.
-    pub const FINISHED: u16 = 0b0000_0000_0000_0010;
+    pub const FINISHED: u32 = 0b0000_0000_0000_0010;
     /// Whether the task has been aborted and should not run.
-    pub const ABORTED: u16 = 0b0000_0000_0000_0100;
+    pub const ABORTED: u32 = 0b0000_0000_0000_0100;
+    /// Whether the task panicked while running.
+    pub const PANICKED: u32 = 0b0000_0000_0000_1000;
 
     /// Whether the executor is holding the task
-    pub const EXECUTOR_ALIVE: u16 = 0b0000_0000_0001_0000;
+    pub const EXECUTOR_ALIVE: u32 = 0b0000_0000_0001_0000;
     /// Whether there is a handle to the task alive
-    pub const HANDLE_ALIVE: u16 = 0b0000_0000_0010_0000;
+    pub const HANDLE_ALIVE: u32 = 0b0000_0000_0010_0000;
+    /// Whether the task lives in caller-provided storage instead of the heap
+    pub const EXTERNAL_STORAGE: u32 = 0b0000_0000_0100_0000;
+    /// A reference to the task held by an abort handle, the upper bits count them.
+    pub const REFERENCE: u32 = 1 << 16;
+    /// Bits that keep the task alive while set.
+    const OWNERS: u32 = Self::EXECUTOR_ALIVE | Self::HANDLE_ALIVE | !(Self::REFERENCE - 1);
 
     /// Whether the task has already produced an output.
-    pub const OUTPUT_READY: u16 = 0b0000_0001_0000_0000;
+    pub const OUTPUT_READY: u32 = 0b0000_0001_0000_0000;
     /// Whether the output of the task has been taken.
-    pub const OUTPUT_TAKEN: u16 = 0b0000_0010_0000_0000;
+    pub const OUTPUT_TAKEN: u32 = 0b0000_0010_0000_0000;
+    /// Whether the output was discarded because only references to the task were left.
+    pub const RECLAIMED: u32 = 0b0000_0100_0000_0000;
+    /// Whether the task ran past the limit given to [`Planetary::spawn_hard_timeout`].
+    ///
+    /// [`Planetary::spawn_hard_timeout`]: crate::handle::Planetary::spawn_hard_timeout
+    pub const TIMED_OUT: u32 = 0b0000_1000_0000_0000;
+    /// Whether the task started running, unlike tasks aborted before they got to run.
+    pub const STARTED: u32 = 0b0001_0000_0000_0000;
+    /// Whether the task is a step of something else, like the polls of a future,
+    /// so it's left out of the stats and completion counts.
+    pub const INTERNAL: u32 = 0b0010_0000_0000_0000;
+    /// Owners that can still get the output of the task.
+    const STRONG_OWNERS: u32 = Self::EXECUTOR_ALIVE | Self::HANDLE_ALIVE;
 
     pub fn new() -> Self {
-        State(AtomicU16::new(0))
+        State(AtomicU32::new(0))
     }
 
     /// Sets the specified flag bit with the provided value.
-    pub fn set(&self, item: u16, value: bool) {
+    pub fn set(&self, item: u32, value: bool) {
         if value {
             self.0.fetch_or(item, Ordering::AcqRel);
         } else {
@@ -34,12 +55,66 @@ impl State {
         }
     }
 
+    /// Sets the specified flag bit, returning the state previous to the change.
+    pub fn fetch_set(&self, item: u32) -> Snapshot {
+        Snapshot(self.0.fetch_or(item, Ordering::AcqRel))
+    }
+
+    /// Clears the specified owner flag bit, returning whether the task is left without
+    /// owners, in which case the caller is in charge of deallocating it.
+    pub fn release(&self, owner: u32) -> bool {
+        let previous = self.0.fetch_and(!owner, Ordering::AcqRel);
+        previous & !owner & Self::OWNERS == 0
+    }
+
+    /// Adds a reference to the task.
+    pub fn acquire_ref(&self) {
+        self.0.fetch_add(Self::REFERENCE, Ordering::AcqRel);
+    }
+
+    /// Removes a reference to the task, returning whether the task is left without
+    /// owners, in which case the caller is in charge of deallocating it.
+    pub fn release_ref(&self) -> bool {
+        let previous = self.0.fetch_sub(Self::REFERENCE, Ordering::AcqRel);
+        (previous - Self::REFERENCE) & Self::OWNERS == 0
+    }
+
+    /// Marks the task as reclaimed if nothing can get its output anymore, returning
+    /// whether the caller is in charge of discarding the output.
+    pub fn try_reclaim(&self) -> bool {
+        self.0.fetch_update(Ordering::AcqRel, Ordering::Acquire, |state| {
+            (state & (Self::STRONG_OWNERS | Self::RECLAIMED) == 0).then_some(state | Self::RECLAIMED)
+        }).is_ok()
+    }
+
+    /// Sets the handle flag if there is no handle alive and the task wasn't reclaimed,
+    /// returning whether the caller may create a handle.
+    pub fn try_acquire_handle(&self) -> bool {
+        self.0.fetch_update(Ordering::AcqRel, Ordering::Acquire, |state| {
+            (state & (Self::HANDLE_ALIVE | Self::RECLAIMED) == 0).then_some(state | Self::HANDLE_ALIVE)
+        }).is_ok()
+    }
+
+    /// Marks the task as timed out and aborted if it's still running, returning whether it was.
+    /// It's marked as finished too, as its handles already got their result.
+    pub fn try_time_out(&self) -> bool {
+        self.0.fetch_update(Ordering::AcqRel, Ordering::Acquire, |state| {
+            (state & (Self::RUNNING | Self::FINISHED) == Self::RUNNING)
+                .then_some(state | Self::TIMED_OUT | Self::ABORTED | Self::FINISHED)
+        }).is_ok()
+    }
+
+    /// Whether anything is keeping the task alive.
+    pub fn has_owners(&self) -> bool {
+        self.load_all() & Self::OWNERS != 0
+    }
+
     /// Checks if the specified flag bit is set.
-    pub fn get(&self, item: u16) -> bool {
+    pub fn get(&self, item: u32) -> bool {
         self.0.load(Ordering::Acquire) & item != 0
     }
 
-    pub fn load_all(&self) -> u16 {
+    pub fn load_all(&self) -> u32 {
         self.0.load(Ordering::Acquire)
     }
 
@@ -49,10 +124,10 @@ impl State {
 }
 
 #[derive(Clone, Copy)]
-pub struct Snapshot(u16);
+pub struct Snapshot(u32);
 
 impl Snapshot {
-    pub fn get(&self, item: u16) -> bool {
+    pub fn get(&self, item: u32) -> bool {
         self.0 & item != 0
     }
 }
@@ -0,0 +1,32 @@
+use std::mem::MaybeUninit;
+
+/// Caller-owned buffer a task can be placed into, avoiding the heap allocation
+/// performed when spawning through [`Planetary::spawn`].
+///
+/// The task is constructed in place by [`Planetary::spawn_in`], which checks at compile
+/// time that it fits in `N` bytes.
+///
+/// [`Planetary::spawn`]: crate::handle::Planetary::spawn
+/// [`Planetary::spawn_in`]: crate::handle::Planetary::spawn_in
+#[repr(C, align(16))]
+pub struct TaskStorage<const N: usize> {
+    buf: [MaybeUninit<u8>; N]
+}
+
+impl<const N: usize> TaskStorage<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [MaybeUninit::uninit(); N]
+        }
+    }
+
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.buf.as_mut_ptr().cast()
+    }
+}
+
+impl<const N: usize> Default for TaskStorage<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
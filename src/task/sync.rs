@@ -1,8 +1,25 @@
-use std::{mem::MaybeUninit, ptr::NonNull, sync::Mutex};
+use std::{any::Any, cell::Cell, mem::MaybeUninit, ptr::NonNull, sync::{atomic::{AtomicU32, AtomicU64, Ordering}, Arc, Mutex, OnceLock, Weak}};
 
-use crate::{task::state::Snapshot, JoinResult};
+use crate::{core::{Core, CoreInner}, join::{AbortHandle, JoinError}, task::state::Snapshot, JoinResult};
 
-use super::{park::Parker, runnable::Runnable, state::State, vtable::VTable};
+use super::{park::Parker, runnable::{IntoRunnable, Runnable}, state::State, storage::TaskStorage, timing::Timestamp, vtable::VTable};
+
+/// Id to be assigned to the next task created.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Size above which outputs are boxed instead of stored in the task allocation.
+pub(crate) const INLINE_OUTPUT_LIMIT: usize = 256;
+
+/// Value of the progress of tasks that never reported it, a NaN that reported values can't match.
+const NO_PROGRESS: u32 = u32::MAX;
+
+thread_local! {
+    /// Header of the task running on the current thread, if any
+    static CURRENT_TASK: Cell<Option<NonNull<Header>>> = const { Cell::new(None) };
+}
+
+/// Function receiving the outputs of tasks whose handle was dropped before taking them.
+pub(crate) type ResultSink = Arc<dyn Fn(JoinResult<Box<dyn Any + Send>>) + Send + Sync>;
 
 #[repr(C)]
 /// A task that can be run by the executor.
@@ -12,13 +29,57 @@ pub struct Task<T, R> {
     output: MaybeUninit<JoinResult<R>>,
 }
 
-/// Header of the task, used to interact with the task
+/// Runs the wrapped runnable boxing its output, used for tasks with big outputs.
+pub(crate) struct BoxedOutput<T>(T);
+
+impl<T: Runnable> Runnable for BoxedOutput<T> {
+    type Output = Box<T::Output>;
+
+    fn run(self) -> Self::Output {
+        Box::new(self.0.run())
+    }
+}
+
+/// Header of the task, used to interact with the task.
+///
+/// Its contents are opaque, pointers to it can be obtained with [`JoinHandle::into_raw`]
+/// and used with [`Planetary::take_output_raw`].
+///
+/// [`JoinHandle::into_raw`]: crate::join::JoinHandle::into_raw
+/// [`Planetary::take_output_raw`]: crate::handle::Planetary::take_output_raw
 pub struct Header {
     vtable: &'static VTable,
     pub(crate) state: State,
-    parker: Mutex<Parker>
+    parker: Mutex<Parker>,
+    /// Unique identifier of the task
+    pub(crate) id: u64,
+    /// When the task was handed to the threadpool
+    pub(crate) enqueued_at: Timestamp,
+    /// When the task started running
+    pub(crate) started_at: Timestamp,
+    /// Where the output goes if nobody takes it
+    pub(crate) result_sink: OnceLock<ResultSink>,
+    /// Progress reported by the task, as the bits of an `f32`
+    progress: AtomicU32,
+    /// Context captured when spawning the task, only read by the thread running it
+    context: Option<Box<dyn Any + Send>>,
+    /// Name given to the task when spawning it
+    name: Option<Box<str>>,
+    /// Task this one was chained to with [`JoinHandle::then`], aborted along with it
+    ///
+    /// [`JoinHandle::then`]: crate::join::JoinHandle::then
+    pub(crate) upstream: OnceLock<AbortHandle>,
+    /// Threadpool the task was handed to
+    owner: OnceLock<Weak<CoreInner>>,
 }
 
+/// A task with its runnable type erased, ready to be handed to a threadpool with
+/// [`Planetary::spawn_erased`]. Built with a [`TaskBuilder`].
+///
+/// Dropping it without spawning it cancels the task, so its handle doesn't wait forever.
+///
+/// [`Planetary::spawn_erased`]: crate::handle::Planetary::spawn_erased
+/// [`TaskBuilder`]: crate::task::TaskBuilder
 pub struct TypeErasedTask {
     pub(crate) header: NonNull<Header>
 }
@@ -30,19 +91,58 @@ impl<T, R> Task<T, R>
 where
     T: Runnable<Output = R>,
 {
-    pub fn new(runnable: T) -> Self {
+    pub fn new<M>(runnable: impl IntoRunnable<M, Runnable = T>) -> Self {
         Self {
             header: Header {
                 vtable: vtable::vtable::<T>(),
                 state: State::new(),
                 parker: Default::default(),
+                id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                enqueued_at: Timestamp::new(),
+                started_at: Timestamp::new(),
+                result_sink: OnceLock::new(),
+                progress: AtomicU32::new(NO_PROGRESS),
+                context: None,
+                name: None,
+                upstream: OnceLock::new(),
+                owner: OnceLock::new(),
             },
-            function: MaybeUninit::new(runnable),
+            function: MaybeUninit::new(runnable.into_runnable()),
             output: MaybeUninit::uninit(),
         }
     }
 
+    /// Attaches a context to the task, available while it runs through [`Header::context`].
+    pub fn with_context(mut self, context: Box<dyn Any + Send>) -> Self {
+        self.header.context = Some(context);
+        self
+    }
+
+    /// Names the task, so it can be told apart when inspecting the threadpool.
+    pub fn with_name(mut self, name: impl Into<Box<str>>) -> Self {
+        self.header.name = Some(name.into());
+        self
+    }
+
+    /// Moves the task to the heap. Outputs bigger than [`INLINE_OUTPUT_LIMIT`] are boxed
+    /// once produced, so the task allocation doesn't reserve room for them.
     pub fn erase(self) -> TypeErasedTask {
+        if size_of::<R>() <= INLINE_OUTPUT_LIMIT {
+            return self.erase_inline();
+        }
+
+        let Task { mut header, function, .. } = self;
+        header.vtable = vtable::boxed_vtable::<T>();
+
+        Task {
+            header,
+            // SAFETY: The task didn't run yet, so the function is still there.
+            function: MaybeUninit::new(BoxedOutput(unsafe { function.assume_init() })),
+            output: MaybeUninit::uninit(),
+        }.erase_inline()
+    }
+
+    fn erase_inline(self) -> TypeErasedTask {
         let header = Box::into_raw(Box::new(self)).cast::<Header>();
         
         unsafe {
@@ -54,64 +154,214 @@ where
             header: NonNull::new(header).unwrap()
         }
     }
+
+    /// Erases the task, placing it into the provided storage instead of the heap. The
+    /// output is stored inline regardless of its size.
+    pub fn erase_into<const N: usize>(self, storage: &'static mut TaskStorage<N>) -> TypeErasedTask {
+        const {
+            assert!(size_of::<Self>() <= N, "Task does not fit in the provided storage");
+            assert!(align_of::<Self>() <= align_of::<TaskStorage<N>>(), "Task alignment exceeds the storage alignment");
+        }
+
+        let header = storage.as_mut_ptr().cast::<Self>();
+
+        unsafe {
+            // SAFETY: The storage is big enough and aligned for the task, checked above,
+            // and we have exclusive access to it for the rest of the program.
+            header.write(self);
+
+            let header = header.cast::<Header>();
+            (*header).state.set(State::EXTERNAL_STORAGE, true);
+            (*header).state.set(State::EXECUTOR_ALIVE, true);
+
+            TypeErasedTask {
+                header: NonNull::new_unchecked(header)
+            }
+        }
+    }
 }
 
 impl Header {
-    fn run(this: NonNull<Self>) {
+    fn run(this: NonNull<Self>) -> Snapshot {
         unsafe {
             let run_fn = this.as_ref().vtable.run;
             run_fn(this.cast());
 
             this.as_ref().wake();
+            this.as_ref().state_snapshot()
         }
     }
 
-    pub fn abort(this: NonNull<Self>) {
+    pub(crate) fn abort(this: NonNull<Self>) {
+        Self::abort_with(this, JoinError::Aborted);
+
+        // nobody consumes the output of the tasks this one was chained to anymore, walk
+        // the chain in a loop as it can be arbitrarily long. Each task keeps the handle
+        // of the one before it, so they are all alive while walking it
+        let mut upstream = unsafe { this.as_ref() }.upstream.get();
+
+        while let Some(handle) = upstream {
+            Self::abort_with(handle.header, JoinError::Aborted);
+            upstream = unsafe { handle.header.as_ref() }.upstream.get();
+        }
+    }
+
+    pub(crate) fn cancel(this: NonNull<Self>) {
+        Self::abort_with(this, JoinError::Cancelled);
+    }
+
+    pub(crate) fn abort_with(this: NonNull<Self>, error: JoinError) {
         unsafe {
             let abort_fn = this.as_ref().vtable.abort;
-            abort_fn(this.cast());
+            abort_fn(this.cast(), error);
 
             this.as_ref().wake();
         }
     }
 
-    pub fn try_dealloc(this: NonNull<Self>) -> bool {
+    pub(crate) fn try_dealloc(this: NonNull<Self>) -> bool {
         unsafe {
             let dealloc_fn = this.as_ref().vtable.drop;
             dealloc_fn(this.cast())
         }
     }
 
+    /// Discards the output of the task if only references to it are left, so they
+    /// don't keep it alive.
+    pub(crate) fn try_reclaim(this: NonNull<Self>) {
+        unsafe {
+            if this.as_ref().state.try_reclaim() {
+                let reclaim_fn = this.as_ref().vtable.reclaim;
+                reclaim_fn(this.cast());
+            }
+        }
+    }
+
+    /// Times out the task if it's still running, waking the thread joining it so the join
+    /// returns [`JoinError::TimedOut`] without waiting for the task to finish.
+    pub(crate) fn time_out(&self) -> bool {
+        let timed_out = self.state.try_time_out();
+
+        if timed_out {
+            self.wake();
+        }
+
+        timed_out
+    }
+
+    /// Records the threadpool the task was handed to.
+    pub(crate) fn set_owner(&self, core: &Core) {
+        let _ = self.owner.set(core.downgrade());
+    }
+
+    /// Threadpool the task was handed to, if it's still alive.
+    pub(crate) fn owner(&self) -> Option<Core> {
+        Core::upgrade(self.owner.get()?)
+    }
+
+    /// Lets the owning threadpool know the task is about to reach its final state. Called
+    /// before the state is published, so whoever sees the task finished can't find it
+    /// registered anymore.
+    fn finishing(&self) {
+        #[cfg(feature = "inspect")]
+        if let Some(core) = self.owner() {
+            core.registry.unregister(self.id);
+        }
+    }
+
     fn wake(&self) {
-        self.parker.lock().unwrap_or_else(|s| s.into_inner())
-                .take()
-                .wake();
+        // taken out of the lock first, so the waker can poll the task again
+        let parker = self.parker.lock().unwrap_or_else(|s| s.into_inner()).take();
+        parker.wake();
     }
 
-    pub unsafe fn try_get_output(this: NonNull<Self>, dest: *mut ()) {
+    pub(crate) unsafe fn try_get_output(this: NonNull<Self>, dest: *mut ()) {
         unsafe {
             let get_output_fn = this.as_ref().vtable.take_output;
             get_output_fn(this.cast(), dest)
         }
     }
 
+    /// Records the progress of the task, clamped between 0 and 1.
+    pub(crate) fn set_progress(&self, fraction: f32) {
+        if !fraction.is_nan() {
+            self.progress.store(fraction.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    /// Gets the last progress reported by the task, if any.
+    pub(crate) fn progress(&self) -> Option<f32> {
+        match self.progress.load(Ordering::Relaxed) {
+            NO_PROGRESS => None,
+            bits => Some(f32::from_bits(bits))
+        }
+    }
+
+    /// Gets the context attached to the task, if it has one of type `C`.
+    pub(crate) fn context<C: 'static>(&self) -> Option<&C> {
+        self.context.as_ref()?.downcast_ref()
+    }
+
+    /// Gets the name given to the task, if any.
+    #[cfg(feature = "inspect")]
+    pub(crate) fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Calls `f` with the header of the task running on the current thread, if any.
+    pub(crate) fn with_current<R>(f: impl FnOnce(&Header) -> R) -> Option<R> {
+        // SAFETY: The header is set only while its task runs, and the executor keeps
+        // the task alive until then.
+        CURRENT_TASK.with(|current| current.get().map(|header| f(unsafe { header.as_ref() })))
+    }
+
     #[inline(always)]
-    pub fn parker(&self) -> &Mutex<Parker> {
+    pub(crate) fn parker(&self) -> &Mutex<Parker> {
         &self.parker
     }
 
-    pub fn state_snapshot(&self) -> Snapshot {
+    pub(crate) fn state_snapshot(&self) -> Snapshot {
         self.state.snapshot()
     }
 }
 
+impl Drop for Header {
+    fn drop(&mut self) {
+        // dropping the handle of the task this one was chained to may free that task,
+        // and so on through the chain, so release it in a loop instead of recursively
+        let mut upstream = self.upstream.take();
+
+        while let Some(handle) = upstream.take() {
+            let header = handle.header;
+            std::mem::forget(handle);
+
+            if unsafe { header.as_ref() }.state.release_ref() {
+                // SAFETY: That was the last reference, so nothing else accesses the task
+                upstream = unsafe { (*header.as_ptr()).upstream.take() };
+                Header::try_dealloc(header);
+            }
+        }
+    }
+}
+
 impl TypeErasedTask {
-    pub fn run(self) {
-        Header::run(self.header);
+    pub(crate) fn header(&self) -> &Header {
+        unsafe { self.header.as_ref() }
+    }
+
+    /// Runs the task, returning its state once it ran.
+    pub(crate) fn run(self) -> Snapshot {
+        Header::run(self.header)
+    }
+
+    /// Aborts the task on behalf of the threadpool, which won't be able to run it.
+    pub(crate) fn cancel(self) {
+        Header::cancel(self.header);
     }
 
-    pub fn abort(self) {
-        Header::abort(self.header);
+    /// Aborts the task with the given error instead of running it.
+    pub(crate) fn abort_with(self, error: JoinError) {
+        Header::abort_with(self.header, error);
     }
 }
 
@@ -119,11 +369,19 @@ impl Drop for TypeErasedTask {
     fn drop(&mut self) {
         unsafe {
             let header = self.header.as_ref();
+
+            // dropped without running, cancel it so the output gets ready
+            if !header.state.get(State::FINISHED) {
+                Header::cancel(self.header);
+            }
+
             // type erased task is only held by the executor, so update the state
             // to reflect the drop
-            header.state.set(State::EXECUTOR_ALIVE, false);
-
-            Header::try_dealloc(self.header);
+            if header.state.release(State::EXECUTOR_ALIVE) {
+                Header::try_dealloc(self.header);
+            } else {
+                Header::try_reclaim(self.header);
+            }
         }
     }
 }
@@ -132,9 +390,11 @@ impl Drop for TypeErasedTask {
 mod vtable {
     use std::{mem::MaybeUninit, panic::{catch_unwind, AssertUnwindSafe}, ptr::NonNull};
 
-    use crate::{task::{runnable::Runnable, state::State, vtable::VTable}, JoinResult};
+    use std::any::Any;
+
+    use crate::{join::JoinError, macros::tracing_feat, task::{runnable::Runnable, state::State, vtable::VTable}, JoinResult};
 
-    use super::{Header, Task};
+    use super::{BoxedOutput, Header, Task};
 
     pub fn vtable<T>() -> &'static VTable 
     where
@@ -142,9 +402,25 @@ mod vtable {
     {
         &VTable {
             run: run::<T>,
-            abort: abort,
+            abort: abort::<T>,
             drop: try_dealloc::<T>,
             take_output: try_get_output::<T>,
+            reclaim: reclaim::<T>,
+        }
+    }
+
+    /// Vtable of a task whose output got boxed, see [`BoxedOutput`]. Handles receive the
+    /// output of `T` unboxed.
+    pub fn boxed_vtable<T>() -> &'static VTable
+    where
+        T: Runnable
+    {
+        &VTable {
+            run: run::<BoxedOutput<T>>,
+            abort: abort::<BoxedOutput<T>>,
+            drop: try_dealloc::<BoxedOutput<T>>,
+            take_output: try_get_boxed_output::<T>,
+            reclaim: reclaim_boxed::<T>,
         }
     }
 
@@ -156,14 +432,27 @@ mod vtable {
             ptr.cast::<Header>().as_ref()
         };
 
-        assert!(!header.state.get(State::RUNNING));
-        assert!(!header.state.get(State::FINISHED));
+        let previous = header.state.fetch_set(State::RUNNING);
 
-        if header.state.get(State::ABORTED) {
+        // if the task got aborted first, the abort already took care of its output
+        if previous.get(State::ABORTED) {
+            header.state.set(State::RUNNING, false);
             return;
         }
 
-        header.state.set(State::RUNNING, true);
+        assert!(!previous.get(State::RUNNING));
+        assert!(!previous.get(State::FINISHED));
+
+        header.state.set(State::STARTED, true);
+        header.started_at.mark();
+
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("task", id = header.id).entered();
+
+        tracing_feat!(
+            let queue_latency = header.enqueued_at.elapsed().unwrap_or_default();
+            trace!(queue_latency_us = queue_latency.as_micros() as u64, "Task {} started", header.id);
+        );
 
         let mut ptr = ptr.cast::<Task<T, T::Output>>();
 
@@ -176,24 +465,62 @@ mod vtable {
                 .assume_init()
         };
 
-        let result = catch_unwind(AssertUnwindSafe(|| runnable.run()));
+        // tasks can run inline from other tasks, so restore whichever was running before
+        let previous = super::CURRENT_TASK.with(|current| current.replace(Some(ptr.cast())));
+        let result = catch_unwind(AssertUnwindSafe(|| runnable.run()))
+            .map_err(JoinError::Panic);
+        super::CURRENT_TASK.with(|current| current.set(previous));
 
+        if let Err(JoinError::Panic(payload)) = &result {
+            crate::panic::observe(task.header.id, payload.as_ref());
+        }
+
+        task.header.state.set(State::PANICKED, result.is_err());
         task.output = MaybeUninit::new(result);
 
+        task.header.finishing();
         task.header.state.set(State::RUNNING, false);
         task.header.state.set(State::FINISHED, true);
         task.header.state.set(State::OUTPUT_READY, true);
 
+        tracing_feat!(
+            let execution_time = task.header.started_at.elapsed().unwrap_or_default();
+            trace!(execution_time_us = execution_time.as_micros() as u64, "Task {} finished", task.header.id);
+        );
+
         task.header.wake();
     }
 
-    unsafe fn abort(ptr: NonNull<()>) {
+    unsafe fn abort<T>(ptr: NonNull<()>, error: JoinError)
+    where
+        T: Runnable
+    {
         let header = unsafe {
             ptr.cast::<Header>().as_ref()
         };
 
-        // just set aborted flag
-        header.state.set(State::ABORTED, true);
+        let previous = header.state.fetch_set(State::ABORTED);
+
+        // if the task already started or was aborted before, just leave the flag set
+        if previous.get(State::ABORTED)
+            || previous.get(State::RUNNING)
+            || previous.get(State::FINISHED)
+        {
+            return;
+        }
+
+        // the task will never run, so drop the function and store the error as output
+        let mut task = ptr.cast::<Task<T, T::Output>>();
+
+        unsafe {
+            let task = task.as_mut();
+            task.function.assume_init_drop();
+            task.output = MaybeUninit::new(Err(error));
+        }
+
+        header.finishing();
+        header.state.set(State::FINISHED, true);
+        header.state.set(State::OUTPUT_READY, true);
     }
 
     unsafe fn try_dealloc<T>(ptr: NonNull<()>) -> bool 
@@ -204,9 +531,7 @@ mod vtable {
             ptr.cast::<Header>().as_ref()
         };
 
-        if header.state.get(State::HANDLE_ALIVE) 
-            || header.state.get(State::EXECUTOR_ALIVE) 
-        {
+        if header.state.has_owners() {
             return false;
         }
 
@@ -221,46 +546,92 @@ mod vtable {
                 task_mut.function.assume_init_drop();
             }
 
-            // if output flag is set, the output is there
-            if task_mut.header.state.get(State::OUTPUT_READY) 
-                && !task_mut.header.state.get(State::OUTPUT_TAKEN)
-            {
-                task_mut.output.assume_init_drop();
-            }
+            let reclaim_fn = task_mut.header.vtable.reclaim;
+            reclaim_fn(ptr);
 
-            // drop the task
-            drop(Box::from_raw(task.as_ptr()));
+            // drop the task, the storage is owned by the caller if it's external
+            if task_mut.header.state.get(State::EXTERNAL_STORAGE) {
+                std::ptr::drop_in_place(task.as_ptr());
+            } else {
+                drop(Box::from_raw(task.as_ptr()));
+            }
         }
 
         true
     }
 
-    unsafe fn try_get_output<T>(ptr: NonNull<()>, dest: *mut ())
+    /// Takes the output out of the task if it's there, marking it as taken.
+    unsafe fn take_stored<T>(ptr: NonNull<()>) -> Option<JoinResult<T::Output>>
     where
         T: Runnable
     {
-        let header = unsafe {
-            ptr.cast::<Header>().as_ref()
-        };
+        let mut task = ptr.cast::<Task<T, T::Output>>();
 
-        if !header.state.get(State::OUTPUT_READY)
-            || header.state.get(State::OUTPUT_TAKEN)
-        {
-            return;
+        unsafe {
+            let task_mut = task.as_mut();
+
+            // the output is only there if it's ready and nobody took it yet
+            if !task_mut.header.state.get(State::OUTPUT_READY)
+                || task_mut.header.state.fetch_set(State::OUTPUT_TAKEN).get(State::OUTPUT_TAKEN)
+            {
+                return None;
+            }
+
+            Some(task_mut.output.assume_init_read())
         }
+    }
 
-        header.state.set(State::OUTPUT_TAKEN, true);
+    /// Sends the output to the result sink if there's one, dropping it otherwise.
+    fn send_to_sink(header: &Header, output: JoinResult<Box<dyn Any + Send>>) {
+        if let Some(sink) = header.result_sink.get() {
+            // the task may be freed from a drop, so don't let the sink unwind
+            let _ = catch_unwind(AssertUnwindSafe(|| sink(output)));
+        }
+    }
 
-        let dest = dest.cast::<Option<JoinResult<T::Output>>>();
+    unsafe fn reclaim<T>(ptr: NonNull<()>)
+    where
+        T: Runnable
+    {
+        if let Some(output) = unsafe { take_stored::<T>(ptr) } {
+            let header = unsafe { ptr.cast::<Header>().as_ref() };
+            send_to_sink(header, output.map(|out| Box::new(out) as Box<dyn Any + Send>));
+        }
+    }
 
-        let mut task = ptr.cast::<Task<T, T::Output>>();
+    unsafe fn reclaim_boxed<T>(ptr: NonNull<()>)
+    where
+        T: Runnable
+    {
+        if let Some(output) = unsafe { take_stored::<BoxedOutput<T>>(ptr) } {
+            let header = unsafe { ptr.cast::<Header>().as_ref() };
+            send_to_sink(header, output.map(|out| out as Box<dyn Any + Send>));
+        }
+    }
+
+    unsafe fn try_get_output<T>(ptr: NonNull<()>, dest: *mut ())
+    where
+        T: Runnable
+    {
+        let dest = dest.cast::<Option<JoinResult<T::Output>>>();
 
         unsafe {
-            let output = std::mem::replace(&mut task.as_mut().output, MaybeUninit::uninit())
-                .assume_init();
+            if let Some(output) = take_stored::<T>(ptr) {
+                *dest = Some(output);
+            }
+        }
+    }
+
+    unsafe fn try_get_boxed_output<T>(ptr: NonNull<()>, dest: *mut ())
+    where
+        T: Runnable
+    {
+        let dest = dest.cast::<Option<JoinResult<T::Output>>>();
 
-            *dest = Some(output);
-            task.as_mut().header.state.set(State::OUTPUT_TAKEN, true);
+        unsafe {
+            if let Some(output) = take_stored::<BoxedOutput<T>>(ptr) {
+                *dest = Some(output.map(|out| *out));
+            }
         }
     }
 }
@@ -278,13 +649,13 @@ mod tests {
 
     #[test]
     pub fn create_drop_task() {
-        let task = Task::new(runnable);
+        let _task = Task::new(runnable);
     }
 
     #[test]
     pub fn create_drop_erased() {
         let task = Task::new(runnable);
-        let erased = task.erase();
+        let _erased = task.erase();
     }
 
     #[test]
@@ -336,4 +707,109 @@ mod tests {
             drop_fn(header.cast());
         }
     }
+
+    #[test]
+    pub fn wake_every_waiter() {
+        use std::{sync::{atomic::{AtomicUsize, Ordering}, Arc, Barrier}, task::{Wake, Waker}, thread};
+
+        struct CountWaker(AtomicUsize);
+
+        impl Wake for CountWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let (gate_tx, gate_rx) = crossbeam_channel::bounded::<()>(0);
+        let erased = Task::new(move || { let _ = gate_rx.recv(); }).erase();
+        let header = erased.header;
+
+        unsafe {
+            // keep the task alive after running, like a handle would
+            header.as_ref().state.set(State::HANDLE_ALIVE, true);
+        }
+
+        let wakers = [(); 3].map(|_| Arc::new(CountWaker(AtomicUsize::new(0))));
+        let registered = Arc::new(Barrier::new(3));
+
+        let runner = thread::spawn(move || erased.run());
+        let header_addr = header.as_ptr() as usize;
+        let threads = (0..2).map(|_| {
+            let registered = Arc::clone(&registered);
+            thread::spawn(move || {
+                let header = unsafe { &*(header_addr as *const super::Header) };
+                header.parker().lock().unwrap().set_thread(thread::current());
+                registered.wait();
+
+                while !header.state.get(State::FINISHED) {
+                    thread::park();
+                }
+            })
+        }).collect::<Vec<_>>();
+
+        unsafe {
+            let mut parker = header.as_ref().parker().lock().unwrap();
+            for (key, waker) in wakers.iter().enumerate() {
+                parker.set_waker(key, Waker::from(Arc::clone(waker)));
+            }
+            // registering the same waker again doesn't wake it twice
+            parker.set_waker(wakers.len(), Waker::from(Arc::clone(&wakers[0])));
+        }
+
+        registered.wait();
+        drop(gate_tx);
+        runner.join().unwrap();
+
+        // every thread got unparked, otherwise they would be parked forever
+        threads.into_iter().for_each(|t| t.join().unwrap());
+        assert!(wakers.iter().all(|w| w.0.load(Ordering::SeqCst) == 1));
+
+        unsafe {
+            header.as_ref().state.set(State::HANDLE_ALIVE, false);
+
+            let drop_fn = header.as_ref().vtable.drop;
+            drop_fn(header.cast());
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    pub fn trace_execution_time() {
+        use std::{sync::{Arc, Mutex}, time::Duration};
+
+        use tracing::{field::{Field, Visit}, Event, Subscriber};
+        use tracing_subscriber::{layer::{Context, SubscriberExt}, Layer};
+
+        struct CaptureLayer(Arc<Mutex<Vec<u64>>>);
+
+        impl Visit for &CaptureLayer {
+            fn record_u64(&mut self, field: &Field, value: u64) {
+                if field.name() == "execution_time_us" {
+                    self.0.lock().unwrap().push(value);
+                }
+            }
+
+            fn record_debug(&mut self, _: &Field, _: &dyn std::fmt::Debug) {}
+        }
+
+        impl<S: Subscriber> Layer<S> for CaptureLayer {
+            fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+                event.record(&mut &*self);
+            }
+        }
+
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = tracing_subscriber::registry()
+            .with(CaptureLayer(Arc::clone(&captured)));
+
+        tracing::subscriber::with_default(subscriber, || {
+            Task::new(|| std::thread::sleep(Duration::from_millis(100)))
+                .erase()
+                .run();
+        });
+
+        let captured = captured.lock().unwrap();
+        assert_eq!(captured.len(), 1);
+        assert!((100_000..1_000_000).contains(&captured[0]));
+    }
 }
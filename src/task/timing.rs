@@ -0,0 +1,37 @@
+use std::{sync::{atomic::{AtomicU64, Ordering}, OnceLock}, time::{Duration, Instant}};
+
+/// Reference point all the timestamps are relative to.
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+fn epoch() -> Instant {
+    *EPOCH.get_or_init(Instant::now)
+}
+
+/// Point in time that can be shared between threads, stored as the nanoseconds
+/// elapsed since the process-wide epoch. A value of zero means it was never marked.
+pub struct Timestamp(AtomicU64);
+
+impl Timestamp {
+    pub const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    /// Sets the timestamp to the current instant.
+    pub fn mark(&self) {
+        let nanos = u64::try_from(epoch().elapsed().as_nanos()).unwrap_or(u64::MAX - 1);
+        self.0.store(nanos + 1, Ordering::Release);
+    }
+
+    /// Gets the marked instant, if any.
+    pub fn get(&self) -> Option<Instant> {
+        match self.0.load(Ordering::Acquire) {
+            0 => None,
+            nanos => Some(epoch() + Duration::from_nanos(nanos - 1))
+        }
+    }
+
+    /// Time elapsed since the timestamp was marked, if it was.
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.get().map(|instant| instant.elapsed())
+    }
+}
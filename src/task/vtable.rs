@@ -1,14 +1,18 @@
 use std::ptr::NonNull;
 
+use crate::join::JoinError;
+
 /// Vtable related to a task, used to interact with it
 pub struct VTable {
     /// Runs the task provided
     pub run: unsafe fn(NonNull<()>),
-    /// Aborts the task provided
-    pub abort: unsafe fn(NonNull<()>),
+    /// Aborts the task provided, storing the error as its output if it didn't start running
+    pub abort: unsafe fn(NonNull<()>, JoinError),
     /// Tries to drop the task provided, returning true if the task was dropped
     pub drop: unsafe fn(NonNull<()>) -> bool,
     /// Tries to put the output of the task into the pointer, which
     /// must be a type erased pointer of Option<JoinResult<T>>
-    pub take_output: unsafe fn(NonNull<()>, *mut ())
+    pub take_output: unsafe fn(NonNull<()>, *mut ()),
+    /// Discards the output of the task provided if it wasn't taken
+    pub reclaim: unsafe fn(NonNull<()>)
 }
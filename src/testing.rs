@@ -0,0 +1,76 @@
+use std::ops::Deref;
+
+use crate::handle::{sealed, Planetary};
+
+/// Threadpool without worker threads, whose tasks are run one at a time on the thread
+/// driving it, created with [`PlanetaryBuilder::test_mode`].
+///
+/// Tasks, including the ones spawned from other tasks, wait in the global queue until
+/// [`TestPool::tick`] or [`TestPool::run_all`] runs them in the order they were spawned,
+/// after which low priority tasks run, making the scheduling deterministic. Every other
+/// operation is available through the underlying [`Planetary`] handle.
+///
+/// Tasks still queued when the pool is dropped are cancelled.
+///
+/// [`PlanetaryBuilder::test_mode`]: crate::builder::PlanetaryBuilder::test_mode
+pub struct TestPool {
+    pool: Planetary
+}
+
+impl TestPool {
+    pub(crate) fn new(pool: Planetary) -> Self {
+        Self { pool }
+    }
+
+    /// Runs the next queued task, returning whether there was one.
+    pub fn tick(&self) -> bool {
+        let next = self.pool.inner.take_injected()
+            .or_else(|| self.pool.inner.take_low_priority());
+
+        let Some(task) = next else {
+            return false;
+        };
+
+        // tasks spawned by the task go into this pool, as they would from a worker
+        let previous = sealed::set_handle(self.pool.inner.clone());
+        self.pool.inner.execute_task(task);
+
+        match previous {
+            Some(previous) => { sealed::set_handle(previous); },
+            None => sealed::remove_handle()
+        }
+
+        true
+    }
+
+    /// Runs queued tasks until there are none left, including the ones spawned while
+    /// running them. Returns the number of tasks run.
+    pub fn run_all(&self) -> usize {
+        let mut ran = 0;
+
+        while self.tick() {
+            ran += 1;
+        }
+
+        ran
+    }
+
+    /// Number of tasks waiting to run.
+    pub fn queued_tasks(&self) -> usize {
+        self.pool.injector_len()
+    }
+}
+
+impl Deref for TestPool {
+    type Target = Planetary;
+
+    fn deref(&self) -> &Self::Target {
+        &self.pool
+    }
+}
+
+impl Drop for TestPool {
+    fn drop(&mut self) {
+        self.pool.inner.cancel_injected();
+    }
+}
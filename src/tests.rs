@@ -1,8 +1,8 @@
-use std::{sync::atomic::AtomicU8, thread::sleep, time::Duration};
+use std::{alloc::{GlobalAlloc, Layout, System}, cell::Cell, panic::AssertUnwindSafe, pin::pin, sync::{atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering}, Arc, Mutex}, task::{Context, Poll, Wake, Waker}, thread::sleep, time::{Duration, Instant}};
 
 use tracing::Level;
 
-use crate::{handle::Planetary, task::Runnable};
+use crate::{autoscale::AutoscaleConfig, channel::ResultChannel, builder::{BlockingQueuePolicy, ChildSpawnOrder, PoolConfig, QueueOrder, SaturationPolicy, SpawnPolicy}, handle::{Placement, Planetary, Priority}, join::{JoinError, JoinHandle}, partition::PartitionConfig, steal::{MostLoaded, Peer, RoundRobin, StealStrategy}, metrics::{CumulativeStats, Metrics}, task::{FnMarker, Runnable, RunnableMarker, TaskBuilder, TaskStorage}, JoinResult};
 
 fn enable_tracing() {
     drop(tracing_subscriber::fmt()
@@ -76,10 +76,10 @@ fn create_spawn_shutdown() {
     let handle = create_pool(2, false);
     handle.spawn(SleepFor {
         duration: Duration::from_secs(5)
-    });
+    }).detach();
     handle.spawn(SleepFor {
         duration: Duration::from_secs(2)
-    });
+    }).detach();
 
     handle.shutdown();
 }
@@ -92,11 +92,11 @@ fn create_spawn_take_from_injector() {
 
     handle.spawn(SleepFor {
         duration: Duration::from_secs(5)
-    });
+    }).detach();
 
     handle.spawn(SleepFor {
         duration: Duration::from_secs(8)
-    }); // wont execute, just to confirm goes to the injector
+    }).detach(); // wont execute, just to confirm goes to the injector
 
     handle.shutdown();
 }
@@ -110,9 +110,9 @@ fn spawn_inside_worker_single_worker() {
         println!("Spawning task into current worker");
         crate::spawn(|| {
             println!("Hello from nested task!");
-        });
+        }).detach();
         12
-    });
+    }).detach();
 
     sleep(Duration::from_secs(2));
 
@@ -127,16 +127,16 @@ fn steal_work() {
 
     handle.spawn(SleepFor {
         duration: Duration::from_secs(4)
-    });
+    }).detach();
 
     handle.spawn(|| {
         crate::spawn(SleepFor {
             duration: Duration::from_secs(5)
-        });
+        }).detach();
         println!("[{}] Sleeping 10 secs", thread_name());
         sleep(Duration::from_secs(10));
         println!("[{}] Slept 10 secs", thread_name());
-    });
+    }).detach();
 
     sleep(Duration::from_secs(15));
     println!("Shutdown");
@@ -218,3 +218,3682 @@ fn nested_joins() {
 
     pool.shutdown();
 }
+
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    static LARGEST_ALLOCATION: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        _ = ALLOCATIONS.try_with(|a| a.set(a.get() + 1));
+        _ = LARGEST_ALLOCATION.try_with(|a| a.set(a.get().max(layout.size())));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Number of allocations performed by the current thread
+fn allocations() -> usize {
+    ALLOCATIONS.with(|a| a.get())
+}
+
+/// Size of the largest allocation performed by the current thread while running `f`
+fn largest_allocation<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    LARGEST_ALLOCATION.with(|a| a.set(0));
+    let output = f();
+    (output, LARGEST_ALLOCATION.with(|a| a.get()))
+}
+
+#[test]
+fn spawn_in_static_storage() {
+    let pool = create_pool(1, true);
+    let storage = Box::leak(Box::new(TaskStorage::<256>::new()));
+    let value = 21;
+
+    let before = allocations();
+    let handle = pool.spawn_in(storage, move || value * 2);
+    // the task registry allocates its entries
+    #[cfg(not(feature = "inspect"))]
+    assert_eq!(allocations(), before);
+    #[cfg(feature = "inspect")]
+    let _ = before;
+
+    assert_eq!(handle.join().unwrap(), 42);
+    pool.shutdown();
+}
+
+#[test]
+fn lower_timeout_reaps_faster() {
+    let pool = create_pool(1, false);
+    assert_eq!(pool.timeout(), Duration::from_secs(15));
+
+    pool.set_timeout(Duration::from_millis(200));
+    assert_eq!(pool.timeout(), Duration::from_millis(200));
+
+    pool.spawn(|| 1).join().unwrap();
+    assert_eq!(pool.inner.live_threads(), 1);
+
+    sleep(Duration::from_secs(1));
+    assert_eq!(pool.inner.live_threads(), 0);
+    pool.shutdown();
+}
+
+#[test]
+fn spawn_blocking_slot_waits_for_worker() {
+    let pool = create_pool(2, false);
+
+    let busy = (0..2)
+        .map(|_| pool.spawn(|| sleep(Duration::from_millis(500))))
+        .collect::<Vec<_>>();
+
+    sleep(Duration::from_millis(100));
+    let start = Instant::now();
+    let handle = pool.spawn_blocking_slot(|| 5);
+    assert!(start.elapsed() >= Duration::from_millis(300));
+
+    for busy in busy {
+        busy.join().unwrap();
+    }
+
+    assert_eq!(handle.join().unwrap(), 5);
+    pool.shutdown();
+}
+
+#[test]
+fn join_error_panic() {
+    let pool = create_pool(1, false);
+
+    let err = pool.spawn(|| panic!("Task panicked")).join().unwrap_err();
+    assert!(err.is_panic());
+    assert_eq!(*err.into_panic().unwrap().downcast::<&str>().unwrap(), "Task panicked");
+
+    pool.shutdown();
+}
+
+#[test]
+fn join_error_aborted() {
+    let pool = create_pool(1, false);
+
+    let busy = pool.spawn(|| sleep(Duration::from_millis(300)));
+    let handle = pool.spawn(|| 1);
+    handle.abort();
+
+    assert!(handle.join().unwrap_err().is_aborted());
+    busy.join().unwrap();
+    pool.shutdown();
+}
+
+#[test]
+fn join_error_cancelled() {
+    let pool = create_pool(1, false);
+
+    let busy = pool.spawn(|| sleep(Duration::from_millis(300)));
+    let handle = pool.spawn(|| 1);
+    pool.clone().shutdown();
+
+    assert!(handle.join().unwrap_err().is_cancelled());
+    busy.join().unwrap();
+    assert!(pool.spawn(|| 2).join().unwrap_err().is_cancelled());
+}
+
+#[test]
+fn max_concurrent_tasks_limit() {
+    let pool = Planetary::builder()
+        .max_threads(4)
+        .max_concurrent_tasks(2)
+        .build()
+        .unwrap();
+
+    let running = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+
+    let handles = (0..12).map(|_| {
+        let running = Arc::clone(&running);
+        let peak = Arc::clone(&peak);
+
+        pool.spawn(move || {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            sleep(Duration::from_millis(50));
+            running.fetch_sub(1, Ordering::SeqCst);
+        })
+    }).collect::<Vec<_>>();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert!(peak.load(Ordering::SeqCst) <= 2);
+    pool.shutdown();
+}
+
+#[test]
+fn max_concurrent_tasks_lets_tasks_join_each_other() {
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .max_concurrent_tasks(1)
+        .launch_on_build(true)
+        .build()
+        .unwrap();
+
+    // the outer tasks hold the only permit until they block joining, so the inner
+    // ones would never start if the permit wasn't given back meanwhile
+    let joining = {
+        let pool = pool.clone();
+        pool.clone().spawn(move || pool.spawn(|| 1).join().unwrap() + 1)
+    };
+    let helping = {
+        let pool = pool.clone();
+        pool.clone().spawn(move || pool.spawn(|| 2).join_helping().unwrap() + 1)
+    };
+
+    assert_eq!(joining.join().unwrap(), 2);
+    assert_eq!(helping.join().unwrap(), 3);
+    pool.shutdown();
+}
+
+#[test]
+fn reschedule_interleaves_futures() {
+    let pool = create_pool(1, false);
+    let order = Arc::new(Mutex::new(Vec::new()));
+
+    // keep the only worker busy so both futures start from the injector
+    let busy = pool.spawn(|| sleep(Duration::from_millis(100)));
+
+    let handles = ["a", "b"].map(|name| {
+        let order = Arc::clone(&order);
+
+        pool.spawn_future(async move {
+            for _ in 0..3 {
+                order.lock().unwrap().push(name);
+                crate::reschedule().await;
+            }
+        })
+    });
+
+    busy.join().unwrap();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(*order.lock().unwrap(), ["a", "b", "a", "b", "a", "b"]);
+    pool.shutdown();
+}
+
+#[test]
+fn config_reflects_builder() {
+    let pool = Planetary::builder()
+        .max_threads(3)
+        .stack_size(4 * 1024 * 1024)
+        .timeout(Duration::from_secs(2))
+        .max_concurrent_tasks(2)
+        .build()
+        .unwrap();
+
+    assert_eq!(pool.config(), PoolConfig {
+        max_threads: 3,
+        stack_size: Some(4 * 1024 * 1024),
+        timeout: Duration::from_secs(2),
+        launch_on_build: false,
+        max_concurrent_tasks: Some(2),
+        spawn_policy: SpawnPolicy::LocalFirst,
+        saturation_policy: SaturationPolicy::Queue,
+    });
+
+    pool.shutdown();
+}
+
+#[test]
+fn spawn_from_on_stop_runs_inline() {
+    let ran = Arc::new(AtomicUsize::new(0));
+
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .launch_on_build(true)
+        .timeout(Duration::from_millis(200))
+        .with_hooks({
+            let ran = Arc::clone(&ran);
+
+            move |hooks| {
+                hooks.set_on_stop_fn(move || {
+                    let ran = Arc::clone(&ran);
+                    let worker = std::thread::current().id();
+
+                    crate::spawn(move || {
+                        assert_eq!(std::thread::current().id(), worker);
+                        ran.fetch_add(1, Ordering::SeqCst);
+                    }).detach();
+                });
+            }
+        })
+        .build()
+        .unwrap();
+
+    pool.shutdown();
+    assert_eq!(ran.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn spawn_into_another_pool_from_on_stop_is_not_inline() {
+    let other = create_pool(1, true);
+    let inline = Arc::new(AtomicBool::new(false));
+
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .launch_on_build(true)
+        .with_hooks({
+            let (other, inline) = (other.clone(), Arc::clone(&inline));
+
+            move |hooks| {
+                hooks.set_on_stop_fn(move || {
+                    let worker = std::thread::current().id();
+                    let ran_on = other.spawn(|| std::thread::current().id()).join().unwrap();
+                    inline.store(ran_on == worker, Ordering::SeqCst);
+                });
+            }
+        })
+        .build()
+        .unwrap();
+
+    pool.shutdown();
+    assert!(!inline.load(Ordering::SeqCst));
+    other.shutdown();
+}
+
+#[test]
+fn autoscale_tracks_load() {
+    let pool = Planetary::builder()
+        .max_threads(4)
+        .autoscale(AutoscaleConfig {
+            min_threads: 1,
+            max_threads: 3,
+            interval: Duration::from_millis(10),
+            target_utilization: 0.75,
+            hysteresis: 2,
+        })
+        .build()
+        .unwrap();
+
+    sleep(Duration::from_millis(100));
+    assert_eq!(pool.inner.live_threads(), 1);
+
+    let handles = (0..30)
+        .map(|_| pool.spawn(|| sleep(Duration::from_millis(50))))
+        .collect::<Vec<_>>();
+
+    let mut peak = 0;
+    while handles.iter().any(|h| !h.is_finished()) {
+        peak = peak.max(pool.inner.live_threads());
+        sleep(Duration::from_millis(5));
+    }
+
+    assert!(peak > 1 && peak <= 3, "peak was {peak}");
+
+    sleep(Duration::from_millis(500));
+    assert_eq!(pool.inner.live_threads(), 1);
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    pool.shutdown();
+}
+
+#[test]
+fn barrier_waits_for_completions() {
+    let pool = create_pool(2, false);
+    let done = Arc::new(AtomicUsize::new(0));
+
+    let barrier = pool.barrier(4);
+    assert!(!barrier.wait_timeout(Duration::from_millis(50)));
+
+    for i in 0..4 {
+        let done = Arc::clone(&done);
+
+        pool.spawn(move || {
+            sleep(Duration::from_millis(50 * i));
+            done.fetch_add(1, Ordering::SeqCst);
+        }).detach();
+    }
+
+    barrier.wait();
+    assert_eq!(done.load(Ordering::SeqCst), 4);
+    assert_eq!(barrier.completed(), 4);
+    pool.shutdown();
+}
+
+#[test]
+fn barrier_only_counts_tasks_that_ran() {
+    let pool = create_pool(1, true);
+    let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+    let blocker = pool.spawn(move || { let _ = release_rx.recv(); });
+    while !blocker.has_started() {
+        sleep(Duration::from_millis(1));
+    }
+
+    let barrier = pool.barrier(3);
+
+    // aborted before running, so they don't complete
+    let aborted = (0..2).map(|_| pool.spawn(|| ())).collect::<Vec<_>>();
+    aborted.iter().for_each(JoinHandle::abort);
+
+    // and a future completes once, however many times it's polled
+    let future = pool.spawn_future(async {
+        for _ in 0..3 {
+            crate::reschedule().await;
+        }
+    });
+
+    drop(release_tx);
+    blocker.join().unwrap();
+    future.join().unwrap();
+    aborted.into_iter().for_each(|handle| assert!(handle.join().is_err()));
+
+    assert!(!barrier.wait_timeout(Duration::from_millis(50)));
+    assert_eq!(barrier.completed(), 2);
+    pool.shutdown();
+}
+
+#[test]
+fn consume_channel_spawns_closures() {
+    let pool = create_pool(2, false);
+    let ran = Arc::new(AtomicUsize::new(0));
+    let (tx, rx) = crossbeam_channel::unbounded::<Box<dyn FnOnce() + Send>>();
+
+    let barrier = pool.barrier(10);
+    let adapter = pool.consume_channel(rx);
+
+    for _ in 0..10 {
+        let ran = Arc::clone(&ran);
+        tx.send(Box::new(move || {
+            assert!(thread_name().starts_with("Worker-"));
+            ran.fetch_add(1, Ordering::SeqCst);
+        })).unwrap();
+    }
+
+    drop(tx);
+    adapter.join().unwrap();
+
+    assert!(barrier.wait_timeout(Duration::from_secs(5)));
+    assert_eq!(ran.load(Ordering::SeqCst), 10);
+    pool.shutdown();
+}
+
+#[test]
+fn respawned_worker_keeps_name() {
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .timeout(Duration::from_millis(100))
+        .name_prefix("pool")
+        .build()
+        .unwrap();
+
+    let first = pool.spawn(thread_name).join().unwrap();
+
+    while pool.inner.live_threads() > 0 {
+        sleep(Duration::from_millis(10));
+    }
+
+    let second = pool.spawn(thread_name).join().unwrap();
+
+    assert_eq!(first, "pool-0");
+    assert_eq!(first, second);
+    pool.shutdown();
+}
+
+#[test]
+fn with_workers_paused_excludes_tasks() {
+    let pool = create_pool(2, false);
+    let running = Arc::new(AtomicUsize::new(0));
+
+    let handles = (0..20).map(|_| {
+        let running = Arc::clone(&running);
+
+        pool.spawn(move || {
+            running.fetch_add(1, Ordering::SeqCst);
+            sleep(Duration::from_millis(20));
+            running.fetch_sub(1, Ordering::SeqCst);
+        })
+    }).collect::<Vec<_>>();
+
+    sleep(Duration::from_millis(30));
+
+    pool.with_workers_paused(|| {
+        for _ in 0..5 {
+            assert_eq!(running.load(Ordering::SeqCst), 0);
+            sleep(Duration::from_millis(10));
+        }
+    });
+
+    assert!(!pool.is_paused());
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    pool.shutdown();
+}
+
+struct ThreadWaker(std::thread::Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Minimal executor that polls the future from the current thread
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+
+        std::thread::park();
+    }
+}
+
+#[test]
+fn abort_wakes_awaiting_handle() {
+    let pool = create_pool(1, false);
+
+    let busy = pool.spawn(|| sleep(Duration::from_secs(2)));
+    let handle = pool.spawn(|| 1);
+    let abort = handle.abort_handle();
+
+    let aborter = std::thread::spawn(move || {
+        sleep(Duration::from_millis(100));
+        abort.abort();
+    });
+
+    let start = Instant::now();
+    assert!(block_on(handle).unwrap_err().is_aborted());
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    aborter.join().unwrap();
+    busy.join().unwrap();
+    pool.shutdown();
+}
+
+#[test]
+fn repolled_handles_only_wake_their_last_waker() {
+    struct CountWaker(AtomicUsize);
+
+    impl Wake for CountWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let pool = create_pool(1, true);
+    let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+    let mut handle = pool.spawn(move || { let _ = release_rx.recv(); });
+
+    let wakers = [(); 3].map(|_| Arc::new(CountWaker(AtomicUsize::new(0))));
+    for waker in &wakers {
+        let waker = Waker::from(Arc::clone(waker));
+        assert!(std::pin::Pin::new(&mut handle).poll(&mut Context::from_waker(&waker)).is_pending());
+    }
+
+    drop(release_tx);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while wakers[2].0.load(Ordering::SeqCst) == 0 {
+        assert!(Instant::now() < deadline, "the last waker was never woken");
+        sleep(Duration::from_millis(1));
+    }
+
+    assert_eq!(wakers.map(|w| w.0.load(Ordering::SeqCst)), [0, 0, 1]);
+    handle.join().unwrap();
+    pool.shutdown();
+}
+
+#[test]
+fn join_indexed_completion_order() {
+    let pool = create_pool(3, true);
+
+    let handles = [300, 50, 150]
+        .into_iter()
+        .map(|ms| pool.spawn(move || {
+            sleep(Duration::from_millis(ms));
+            ms
+        }))
+        .collect::<Vec<_>>();
+
+    let results = pool.join_indexed(handles)
+        .into_iter()
+        .map(|(index, result)| (index, result.unwrap()))
+        .collect::<Vec<_>>();
+
+    assert_eq!(results, [(1, 50), (2, 150), (0, 300)]);
+    pool.shutdown();
+}
+
+#[test]
+fn spawn_with_policy_overrides_placement() {
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .launch_on_build(true)
+        .spawn_policy(SpawnPolicy::LocalFirst)
+        .name_prefix("Worker")
+        .build()
+        .unwrap();
+
+    let (parent, child) = pool.spawn(|| {
+        let parent = thread_name();
+        let child = Planetary::current()
+            .spawn_with_policy(SpawnPolicy::GlobalFanout, thread_name);
+
+        // keep this worker busy, so only the other one can pick the child
+        // up from the injector
+        sleep(Duration::from_millis(300));
+        (parent, child)
+    }).join().unwrap();
+
+    assert_ne!(parent, child.join().unwrap());
+    pool.shutdown();
+}
+
+#[test]
+fn metrics_sink_receives_snapshots() {
+    let samples = Arc::new(Mutex::new(Vec::<Metrics>::new()));
+    let sink = {
+        let samples = samples.clone();
+        move |metrics: &Metrics| samples.lock().unwrap().push(metrics.clone())
+    };
+
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .launch_on_build(true)
+        .metrics_sink(sink, Duration::from_millis(20))
+        .build()
+        .unwrap();
+
+    pool.spawn(|| 1).join().unwrap();
+    sleep(Duration::from_millis(200));
+
+    let len = samples.lock().unwrap().len();
+    assert!(len > 1);
+    assert!(samples.lock().unwrap().iter().any(|m| m.live_threads == 2));
+    assert_eq!(pool.metrics().completed_tasks, 1);
+
+    pool.shutdown();
+    let after_shutdown = samples.lock().unwrap().len();
+    sleep(Duration::from_millis(100));
+    assert_eq!(samples.lock().unwrap().len(), after_shutdown);
+}
+
+#[test]
+fn shutdown_joins_the_metrics_sampler() {
+    let samples = Arc::new(AtomicUsize::new(0));
+    let sink = {
+        let samples = Arc::clone(&samples);
+        move |_: &Metrics| { samples.fetch_add(1, Ordering::SeqCst); }
+    };
+
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .metrics_sink(sink, Duration::from_secs(60))
+        .build()
+        .unwrap();
+
+    let started = Instant::now();
+    pool.shutdown();
+
+    // the sampler is woken up instead of sleeping through the interval, and it dropped the sink
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(Arc::strong_count(&samples), 1);
+}
+
+#[test]
+fn try_spawn_on_dead_worker_fails() {
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .launch_on_build(true)
+        .name_prefix("Worker")
+        .build()
+        .unwrap();
+
+    let Err(missing) = pool.try_spawn_on(5, || 10) else {
+        panic!("Worker 5 should not exist");
+    };
+    assert_eq!(missing(), 10);
+
+    for id in pool.worker_ids() {
+        let handle = pool.try_spawn_on(id, thread_name).ok().unwrap();
+        assert_eq!(handle.join().unwrap(), format!("Worker-{id}"));
+    }
+
+    pool.shutdown();
+}
+
+#[test]
+fn weak_pool_upgrade_after_shutdown() {
+    let pool = create_pool(2, false);
+    let weak = pool.downgrade();
+
+    let other = weak.upgrade().expect("Pool is alive");
+    assert_eq!(other.spawn(|| 3).join().unwrap(), 3);
+    drop(other);
+
+    pool.shutdown();
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn spawn_traced_reports_placement() {
+    let pool = create_pool(2, false);
+
+    let (handle, placement) = pool.spawn_traced(|| 1);
+    assert_eq!(placement.recv().unwrap(), Placement::NewThread);
+    handle.join().unwrap();
+
+    // let the worker park, so it's picked instead of spawning a new one
+    sleep(Duration::from_millis(100));
+
+    let (handle, placement) = pool.spawn_traced(|| 2);
+    assert_eq!(placement.recv().unwrap(), Placement::Injector);
+    handle.join().unwrap();
+
+    pool.shutdown();
+}
+
+#[test]
+fn dropping_last_handle_shuts_down() {
+    let stopped = Arc::new(AtomicUsize::new(0));
+
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .launch_on_build(true)
+        .with_hooks(|hooks| {
+            let stopped = stopped.clone();
+            hooks.set_on_stop_fn(move || {
+                stopped.fetch_add(1, Ordering::SeqCst);
+            });
+        })
+        .build()
+        .unwrap();
+
+    let clone = pool.clone();
+    drop(pool);
+    assert_eq!(clone.spawn(|| 4).join().unwrap(), 4);
+    drop(clone);
+
+    let start = Instant::now();
+    while stopped.load(Ordering::SeqCst) < 2 {
+        assert!(start.elapsed() < Duration::from_secs(5), "Workers did not exit");
+        sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn worker_local_is_per_worker() {
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .launch_on_build(true)
+        .name_prefix("Worker")
+        .build()
+        .unwrap();
+
+    let counter = pool.worker_local(|| Cell::new(0u32));
+    assert!(counter.try_get().is_none());
+
+    let handles = pool.worker_ids()
+        .into_iter()
+        .flat_map(|id| (0..=id).map(move |_| id))
+        .map(|id| {
+            let counter = counter.clone();
+            pool.spawn_on(id, move || {
+                let count = counter.get();
+                count.set(count.get() + 1);
+                (thread_name(), count.get())
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut last = std::collections::HashMap::new();
+    for handle in handles {
+        let (name, count) = handle.join().unwrap();
+        last.insert(name, count);
+    }
+
+    // the worker with id `n` ran `n + 1` tasks
+    assert_eq!(last.get("Worker-0"), Some(&1));
+    assert_eq!(last.get("Worker-1"), Some(&2));
+
+    pool.shutdown();
+}
+
+#[test]
+fn latency_percentiles_are_ordered() {
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .track_latency(true)
+        .build()
+        .unwrap();
+
+    let handles = (0..20)
+        .map(|_| pool.spawn(|| sleep(Duration::from_millis(5))))
+        .collect::<Vec<_>>();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let stats = pool.latency_percentiles();
+    assert!(stats.p99 > Duration::ZERO);
+    assert!(stats.p50 <= stats.p90 && stats.p90 <= stats.p99 && stats.p99 <= stats.max);
+
+    pool.shutdown();
+}
+
+#[test]
+fn result_sink_receives_detached_outputs() {
+    let results = Arc::new(Mutex::new(Vec::new()));
+
+    let pool = {
+        let results = results.clone();
+        Planetary::builder()
+            .max_threads(2)
+            .result_sink(move |result| {
+                let outcome = match result {
+                    Ok(value) => value.downcast::<i32>().map(|v| *v).unwrap_or(-1),
+                    Err(e) if e.is_panic() => -2,
+                    Err(_) => -3
+                };
+                results.lock().unwrap().push(outcome);
+            })
+            .build()
+            .unwrap()
+    };
+
+    let barrier = pool.barrier(3);
+    pool.spawn(|| 1).detach();
+    pool.spawn(|| 2).detach();
+    pool.spawn(|| -> i32 { panic!("Detached task panicked") }).detach();
+    // joined outputs are not sent to the sink
+    assert_eq!(pool.spawn(|| 3).join().unwrap(), 3);
+    assert!(barrier.wait_timeout(Duration::from_secs(5)));
+
+    let start = Instant::now();
+    while results.lock().unwrap().len() < 3 && start.elapsed() < Duration::from_secs(5) {
+        sleep(Duration::from_millis(10));
+    }
+
+    let mut results = results.lock().unwrap().clone();
+    results.sort();
+    assert_eq!(results, [-2, 1, 2]);
+
+    pool.shutdown();
+}
+
+#[test]
+fn thread_counters_do_not_underflow() {
+    let pool = Planetary::builder()
+        .max_threads(4)
+        .timeout(Duration::from_millis(20))
+        .build()
+        .unwrap();
+
+    let check = |pool: &Planetary| {
+        let metrics = pool.metrics();
+        assert!(metrics.idle_threads <= 4, "idle counter underflowed: {metrics:?}");
+        assert!(metrics.working_threads <= 4, "working counter underflowed: {metrics:?}");
+    };
+
+    for round in 0..10 {
+        let handles = (0..round * 4)
+            .map(|_| pool.spawn(|| sleep(Duration::from_millis(1))))
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            check(&pool);
+            handle.join().unwrap();
+        }
+
+        // let the workers time out, so the next round spawns them again
+        sleep(Duration::from_millis(40));
+        check(&pool);
+    }
+
+    pool.shutdown();
+}
+
+/// Local queues filled by hand with [`load_local_queues`], whose workers stay busy
+/// until they are released.
+struct LoadedQueues {
+    /// Tasks queued on each worker, returning the name of the thread that ran them
+    children: Vec<Vec<JoinHandle<String>>>,
+    release: Arc<std::sync::Barrier>,
+    loaders: Vec<JoinHandle<()>>,
+}
+
+impl LoadedQueues {
+    /// Lets the loaded workers run their queues, waiting for every queued task.
+    fn release(self) -> Vec<Vec<String>> {
+        self.release.wait();
+        self.loaders.into_iter().for_each(|loader| loader.join().unwrap());
+
+        self.children.into_iter()
+            .map(|handles| handles.into_iter().map(|handle| handle.join().unwrap()).collect())
+            .collect()
+    }
+}
+
+/// Queues `len` tasks on the local queue of each `(worker_id, len)` pair while the
+/// threadpool is paused, keeping those workers busy, and then wakes one of the rest.
+/// The tasks can only be stolen by the rest of the workers, which see the queues full.
+fn load_local_queues(pool: &Planetary, loads: &[(usize, usize)]) -> LoadedQueues {
+    let started = Arc::new(std::sync::Barrier::new(loads.len() + 1));
+    let filling = Arc::new(std::sync::Barrier::new(loads.len() + 1));
+    let release = Arc::new(std::sync::Barrier::new(loads.len() + 1));
+    let (tx, rx) = std::sync::mpsc::channel();
+
+    let loaders = loads.iter()
+        .enumerate()
+        .map(|(index, &(worker_id, len))| {
+            let (started, filling, release, tx) = (Arc::clone(&started), Arc::clone(&filling), Arc::clone(&release), tx.clone());
+
+            pool.spawn_on(worker_id, move || {
+                started.wait();
+                filling.wait();
+                let children = (0..len).map(|_| crate::spawn(thread_name)).collect::<Vec<_>>();
+                tx.send((index, children)).unwrap();
+                release.wait();
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // the loaders are running, so pausing only keeps the rest of the workers away
+    started.wait();
+    pool.pause();
+    filling.wait();
+
+    let mut children = (0..loads.len()).map(|_| Vec::new()).collect::<Vec<_>>();
+    for (index, handles) in rx.iter().take(loads.len()) {
+        children[index] = handles;
+    }
+
+    pool.resume();
+    // pushing to a local queue doesn't wake parked workers, the injector does
+    pool.spawn(|| ()).join().unwrap();
+
+    LoadedQueues { children, release, loaders }
+}
+
+#[test]
+fn most_loaded_steals_from_deepest_queue() {
+    let peers = [
+        Peer::new(0, 3, None),
+        Peer::new(1, 9, None),
+        Peer::new(2, 0, None),
+    ];
+
+    assert_eq!(MostLoaded.pick(2, &peers), Some(1));
+    assert_eq!(MostLoaded.pick(1, &peers), Some(0));
+    assert_eq!(MostLoaded.pick(0, &peers[..1]), None);
+
+    let steals = Arc::new(Mutex::new(Vec::new()));
+    let pool = Planetary::builder()
+        .max_threads(3)
+        .launch_on_build(true)
+        .steal_strategy(MostLoaded)
+        .with_hooks(|hooks| {
+            let steals = Arc::clone(&steals);
+            hooks.set_on_steal_fn(move |_, victim| steals.lock().unwrap().push(victim));
+        })
+        .build()
+        .unwrap();
+
+    let ids = pool.worker_ids();
+    let (shallow, deep) = (ids[0], ids[1]);
+    let loaded = load_local_queues(&pool, &[(shallow, 3), (deep, 9)]);
+
+    // the only free worker steals everything, the deep queue first until both are even
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while steals.lock().unwrap().len() < 12 {
+        assert!(Instant::now() < deadline, "the free worker stopped stealing");
+        sleep(Duration::from_millis(1));
+    }
+
+    let steals = steals.lock().unwrap().clone();
+    assert!(steals[..6].iter().all(|&victim| victim == deep), "steals went to {steals:?}");
+
+    loaded.release();
+    pool.shutdown();
+}
+
+#[test]
+fn run_until_executes_on_caller() {
+    let pool = create_pool(1, true);
+    let caller = std::thread::current().id();
+
+    let ran_on_caller = pool.run_until(move || {
+        let handles = (0..10)
+            .map(|_| crate::spawn(|| {
+                sleep(Duration::from_millis(5));
+                std::thread::current().id()
+            }))
+            .collect::<Vec<_>>();
+
+        // the only worker blocks here, so the children can only run on the caller
+        handles.into_iter()
+            .map(|handle| handle.join().unwrap())
+            .filter(|id| *id == caller)
+            .count()
+    });
+
+    assert_eq!(ran_on_caller, 10);
+    pool.shutdown();
+}
+
+#[cfg(all(feature = "stack-guard", any(target_arch = "x86", target_arch = "x86_64", target_arch = "arm", target_arch = "aarch64")))]
+#[test]
+fn stack_guard_refuses_nested_tasks() {
+    const STACK_SIZE: usize = 512 * 1024;
+
+    /// Recurses until less than `left` bytes of stack remain.
+    fn recurse(left: usize) -> crate::JoinResult<()> {
+        let frame = std::hint::black_box([0u8; 1024]);
+
+        if crate::stack::remaining(Some(STACK_SIZE)).unwrap() < left {
+            // run the child nested on top of this deep stack
+            let child = crate::spawn(|| ());
+            crate::worker::yield_now();
+            return child.join();
+        }
+
+        let result = recurse(left);
+        std::hint::black_box(frame);
+        result
+    }
+
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .stack_size(STACK_SIZE)
+        .task_stack_guard(256 * 1024)
+        .launch_on_build(true)
+        .build()
+        .unwrap();
+
+    assert!(pool.spawn(|| recurse(STACK_SIZE)).join().unwrap().is_ok());
+
+    let nested = pool.spawn(|| recurse(192 * 1024)).join().unwrap();
+    assert!(nested.is_err_and(|e| e.is_stack_exhausted()));
+
+    pool.shutdown();
+}
+
+#[test]
+fn steal_hooks_report_stealer_and_victim() {
+    let steals = Arc::new(Mutex::new(Vec::new()));
+    let failed_scans = Arc::new(AtomicUsize::new(0));
+
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .launch_on_build(true)
+        .name_prefix("Worker")
+        // random picks may miss the loaded worker and leave the other one parked,
+        // cycling covers both workers on every scan
+        .steal_strategy(RoundRobin::default())
+        .with_hooks(|hooks| {
+            let steals = Arc::clone(&steals);
+            let failed_scans = Arc::clone(&failed_scans);
+
+            hooks.set_on_steal_fn(move |stealer, victim| {
+                steals.lock().unwrap().push((stealer, victim));
+            }).set_on_steal_fail_fn(move |_| {
+                failed_scans.fetch_add(1, Ordering::SeqCst);
+            });
+        })
+        .build()
+        .unwrap();
+
+    // load the local queue of one worker while it stays busy
+    let (queued_tx, queued_rx) = crossbeam_channel::bounded(1);
+    let loader = pool.spawn(move || {
+        let handles = (0..10)
+            .map(|_| crate::spawn(|| sleep(Duration::from_millis(5))))
+            .collect::<Vec<_>>();
+
+        queued_tx.send(()).unwrap();
+        sleep(Duration::from_millis(200));
+        (thread_name(), handles)
+    });
+
+    // pushing to a local queue doesn't wake parked workers, so wake the other
+    // one through the injector, it will steal once it runs out of tasks
+    queued_rx.recv().unwrap();
+    pool.spawn(|| ()).join().unwrap();
+
+    let (loaded, handles) = loader.join().unwrap();
+
+    handles.into_iter().for_each(|handle| handle.join().unwrap());
+
+    let loaded = loaded.strip_prefix("Worker-").unwrap().parse::<usize>().unwrap();
+    let steals = steals.lock().unwrap();
+
+    assert!(!steals.is_empty());
+    assert!(steals.iter().all(|&(stealer, victim)| victim == loaded && stealer != loaded));
+    assert!(failed_scans.load(Ordering::SeqCst) > 0);
+    pool.shutdown();
+}
+
+#[test]
+fn shutdown_waits_for_every_on_stop() {
+    const WORKERS: usize = 4;
+    let stopped = Arc::new(AtomicUsize::new(0));
+
+    let pool = Planetary::builder()
+        .max_threads(WORKERS)
+        .launch_on_build(true)
+        .with_hooks(|hooks| {
+            let stopped = Arc::clone(&stopped);
+
+            hooks.set_on_stop_fn(move || {
+                // make slow hooks likely to still be running if shutdown didn't wait
+                sleep(Duration::from_millis(50));
+                stopped.fetch_add(1, Ordering::SeqCst);
+            });
+        })
+        .build()
+        .unwrap();
+
+    pool.shutdown();
+    assert_eq!(stopped.load(Ordering::SeqCst), WORKERS);
+}
+
+#[test]
+fn spawn_with_arc_shares_data() {
+    let pool = create_pool(4, false);
+    let data = Arc::new(vec![1, 2, 3, 4]);
+
+    let handles = (0..data.len())
+        .map(|index| pool.spawn_with_arc(&data, move |data| data[index] * 10))
+        .collect::<Vec<_>>();
+
+    let outputs = handles.into_iter()
+        .map(|handle| handle.join().unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(outputs, [10, 20, 30, 40]);
+    // every clone handed to the tasks was dropped along with them
+    assert_eq!(Arc::strong_count(&data), 1);
+    pool.shutdown();
+}
+
+#[test]
+fn quiesce_hooks_waits_for_after_work() {
+    let before = Arc::new(AtomicUsize::new(0));
+    let after = Arc::new(AtomicUsize::new(0));
+
+    let pool = Planetary::builder()
+        .max_threads(4)
+        .with_hooks(|hooks| {
+            let before = Arc::clone(&before);
+            let after = Arc::clone(&after);
+
+            hooks.set_before_work_fn(move || {
+                before.fetch_add(1, Ordering::SeqCst);
+            }).set_after_work_fn(move || {
+                // outputs are ready before this runs, so joining doesn't wait for it
+                sleep(Duration::from_millis(50));
+                after.fetch_add(1, Ordering::SeqCst);
+            });
+        })
+        .build()
+        .unwrap();
+
+    let handles = (0..8)
+        .map(|_| pool.spawn(|| sleep(Duration::from_millis(10))))
+        .collect::<Vec<_>>();
+
+    handles.into_iter().for_each(|handle| handle.join().unwrap());
+    pool.quiesce_hooks();
+
+    assert_eq!(before.load(Ordering::SeqCst), 8);
+    assert_eq!(after.load(Ordering::SeqCst), 8);
+    pool.shutdown();
+}
+
+#[test]
+fn spawn_catch_maps_panics() {
+    let pool = create_pool(2, false);
+
+    let fallback = pool.spawn_catch(
+        || -> i32 { panic!("boom") },
+        |payload| if payload.downcast_ref::<&str>() == Some(&"boom") { -1 } else { -2 }
+    );
+    let regular = pool.spawn_catch(|| 7, |_| -1);
+
+    assert_eq!(fallback.join().unwrap(), -1);
+    assert_eq!(regular.join().unwrap(), 7);
+    pool.shutdown();
+}
+
+#[test]
+fn shutdown_async_resolves_after_workers_exit() {
+    const WORKERS: usize = 3;
+    let stopped = Arc::new(AtomicUsize::new(0));
+
+    let pool = Planetary::builder()
+        .max_threads(WORKERS)
+        .launch_on_build(true)
+        .with_hooks(|hooks| {
+            let stopped = Arc::clone(&stopped);
+
+            hooks.set_on_stop_fn(move || {
+                sleep(Duration::from_millis(50));
+                stopped.fetch_add(1, Ordering::SeqCst);
+            });
+        })
+        .build()
+        .unwrap();
+
+    let waiter = pool.shutdown_async();
+    assert!(!waiter.is_finished());
+
+    block_on(waiter);
+    assert_eq!(stopped.load(Ordering::SeqCst), WORKERS);
+}
+
+/// Builds a single worker pool kept busy until the returned sender is used.
+fn saturated_pool(policy: SaturationPolicy) -> (Planetary, crossbeam_channel::Sender<()>) {
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .saturation_policy(policy)
+        .build()
+        .unwrap();
+
+    let (started_tx, started_rx) = crossbeam_channel::bounded(1);
+    let (release_tx, release_rx) = crossbeam_channel::bounded::<()>(1);
+    pool.spawn(move || {
+        started_tx.send(()).unwrap();
+        let _ = release_rx.recv();
+    }).detach();
+
+    started_rx.recv().unwrap();
+    (pool, release_tx)
+}
+
+#[test]
+fn saturation_policy_queue() {
+    let (pool, release) = saturated_pool(SaturationPolicy::Queue);
+
+    let (handle, placement) = pool.spawn_traced(|| 1);
+    assert_eq!(placement.recv().unwrap(), Placement::Injector);
+
+    release.send(()).unwrap();
+    assert_eq!(handle.join().unwrap(), 1);
+    pool.shutdown();
+}
+
+#[test]
+fn saturation_policy_caller_runs() {
+    let (pool, release) = saturated_pool(SaturationPolicy::CallerRuns);
+    let caller = std::thread::current().id();
+
+    let (handle, placement) = pool.spawn_traced(|| std::thread::current().id());
+    assert_eq!(placement.recv().unwrap(), Placement::Inline);
+    assert_eq!(handle.join().unwrap(), caller);
+    // the caller counts the task like a worker would
+    assert_eq!(pool.metrics().completed_tasks, 1);
+    assert_eq!(pool.stats().tasks_completed, 1);
+
+    release.send(()).unwrap();
+    pool.shutdown();
+}
+
+#[test]
+fn saturation_policy_reject() {
+    let (pool, release) = saturated_pool(SaturationPolicy::Reject);
+
+    let Err(runnable) = pool.try_spawn(|| 1) else {
+        panic!("Task was accepted by a saturated pool");
+    };
+    assert_eq!(runnable(), 1);
+
+    let (handle, placement) = pool.spawn_traced(|| 1);
+    assert_eq!(placement.recv().unwrap(), Placement::Rejected);
+    assert!(handle.join().is_err_and(|e| e.is_cancelled()));
+
+    release.send(()).unwrap();
+    pool.shutdown();
+}
+
+/// Callable type with its own `Runnable` implementation, which would overlap with
+/// a blanket implementation for closures.
+type BoxedJob = Box<dyn FnOnce() -> u32 + Send>;
+
+impl Runnable for BoxedJob {
+    type Output = u32;
+
+    fn run(self) -> Self::Output {
+        self() * 2
+    }
+}
+
+#[test]
+fn callable_types_can_implement_runnable() {
+    let pool = create_pool(1, false);
+
+    let custom = pool.spawn::<RunnableMarker, _>(Box::new(|| 21u32) as BoxedJob);
+    let as_closure = pool.spawn::<FnMarker, _>(Box::new(|| 21u32) as BoxedJob);
+    let wrapped = pool.spawn(crate::task::from_fn(|| 21));
+
+    assert_eq!(custom.join().unwrap(), 42);
+    assert_eq!(as_closure.join().unwrap(), 21);
+    assert_eq!(wrapped.join().unwrap(), 21);
+    pool.shutdown();
+}
+
+#[test]
+fn injector_len_tracks_backlog() {
+    let pool = create_pool(1, false);
+    assert_eq!(pool.inner.live_threads(), 0);
+
+    // the first spawn starts the only worker, which stays busy while the rest queue up
+    let (release_tx, release_rx) = crossbeam_channel::bounded::<()>(1);
+    let blocker = pool.spawn(move || { let _ = release_rx.recv(); });
+
+    let handles = (0..10)
+        .map(|_| pool.spawn(|| ()))
+        .collect::<Vec<_>>();
+
+    assert_eq!(pool.injector_len(), 10);
+
+    release_tx.send(()).unwrap();
+    blocker.join().unwrap();
+    handles.into_iter().for_each(|handle| handle.join().unwrap());
+    assert_eq!(pool.injector_len(), 0);
+    pool.shutdown();
+}
+
+#[test]
+fn run_returns_output() {
+    let pool = create_pool(2, false);
+
+    assert_eq!(pool.run(|| 6 * 7).unwrap(), 42);
+    assert!(pool.run(|| panic!("boom")).is_err_and(|e| e.is_panic()));
+    pool.shutdown();
+}
+
+#[test]
+fn run_from_worker_does_not_deadlock() {
+    let pool = create_pool(1, true);
+
+    let output = pool.spawn(|| {
+        let pool = Planetary::current();
+        pool.run(|| 1).unwrap() + pool.run(|| 1).unwrap()
+    });
+
+    assert_eq!(output.join().unwrap(), 2);
+    pool.shutdown();
+}
+
+#[test]
+fn spawn_erased_task_from_builder() {
+    let pool = create_pool(2, false);
+
+    let (task, handle) = TaskBuilder::new(|| String::from("erased")).build();
+    pool.spawn_erased(task);
+    assert_eq!(handle.join().unwrap(), "erased");
+
+    // never spawned, so the handle gets cancelled instead of waiting forever
+    let (task, handle) = TaskBuilder::new(|| 1).build();
+    drop(task);
+    assert!(handle.join().is_err_and(|e| e.is_cancelled()));
+
+    pool.shutdown();
+}
+
+#[test]
+fn external_tasks_are_not_starved_by_local_fanout() {
+    /// Keeps respawning itself into the local queue of the worker until stopped.
+    fn churn(stop: Arc<AtomicBool>) {
+        sleep(Duration::from_millis(1));
+
+        if !stop.load(Ordering::SeqCst) {
+            crate::spawn(move || churn(stop)).detach();
+        }
+    }
+
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .injector_interval(8)
+        .build()
+        .unwrap();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    pool.spawn({
+        let stop = Arc::clone(&stop);
+        move || (0..4).for_each(|_| {
+            let stop = Arc::clone(&stop);
+            crate::spawn(move || churn(stop)).detach();
+        })
+    }).join().unwrap();
+
+    for _ in 0..5 {
+        let submitted = Instant::now();
+        let waited = pool.spawn(move || submitted.elapsed()).join().unwrap();
+        assert!(waited < Duration::from_millis(500), "External task waited {waited:?}");
+    }
+
+    stop.store(true, Ordering::SeqCst);
+    pool.shutdown();
+}
+
+#[test]
+fn join_unwrap_resumes_panics() {
+    let pool = create_pool(2, false);
+
+    assert_eq!(pool.spawn(|| 5).join_unwrap(), 5);
+
+    let handle = pool.spawn(|| -> i32 { std::panic::panic_any(17u8) });
+    let payload = std::panic::catch_unwind(AssertUnwindSafe(|| handle.join_unwrap())).unwrap_err();
+    assert_eq!(payload.downcast_ref::<u8>(), Some(&17));
+
+    pool.shutdown();
+}
+
+#[test]
+fn spinning_workers_still_park_and_time_out() {
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .launch_on_build(true)
+        .timeout(Duration::from_millis(200))
+        .spin_before_park(Duration::from_millis(50))
+        .build()
+        .unwrap();
+
+    // a task spawned while the worker spins is picked up without parking
+    assert_eq!(pool.spawn(|| 1).join().unwrap(), 1);
+
+    let started = Instant::now();
+    while pool.inner.live_threads() > 0 {
+        assert!(started.elapsed() < Duration::from_secs(5), "Spinning worker never timed out");
+        sleep(Duration::from_millis(10));
+    }
+
+    pool.shutdown();
+}
+
+#[test]
+fn spinning_workers_report_one_steal_failure_per_spin() {
+    let failed = Arc::new(AtomicUsize::new(0));
+
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .launch_on_build(true)
+        .spin_before_park(Duration::from_millis(100))
+        .with_hooks(|hooks| {
+            let failed = Arc::clone(&failed);
+            hooks.set_on_steal_fail_fn(move |_| {
+                failed.fetch_add(1, Ordering::SeqCst);
+            });
+        })
+        .build()
+        .unwrap();
+
+    pool.spawn(|| ()).join().unwrap();
+    // both workers spin for a while and park, trying to steal thousands of times
+    sleep(Duration::from_millis(300));
+
+    let failed = failed.load(Ordering::SeqCst);
+    assert!(failed <= 4, "Reported {failed} steal failures for two spins");
+    assert_eq!(pool.stats().steals_failed, failed as u64);
+
+    pool.shutdown();
+}
+
+#[test]
+fn shutdown_from_own_worker_does_not_deadlock() {
+    let pool = create_pool(2, false);
+    let (done_tx, done_rx) = crossbeam_channel::bounded(1);
+
+    pool.spawn(move || {
+        Planetary::current().shutdown();
+        done_tx.send(()).unwrap();
+    }).detach();
+
+    done_rx.recv_timeout(Duration::from_secs(5)).expect("shutdown deadlocked the worker");
+    pool.shutdown();
+}
+
+#[test]
+fn partitions_respect_their_own_limits() {
+    /// Tracks the number of tasks running at the same time and the highest seen.
+    #[derive(Default)]
+    struct Concurrency {
+        running: AtomicUsize,
+        max: AtomicUsize,
+    }
+
+    impl Concurrency {
+        fn run(&self) {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max.fetch_max(running, Ordering::SeqCst);
+            sleep(Duration::from_millis(20));
+            self.running.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    let pool = create_pool(4, true);
+    let io = pool.partition("io", PartitionConfig::default().max_concurrent(1));
+    let cpu = pool.partition("cpu", PartitionConfig::default().max_concurrent(2));
+
+    let io_concurrency = Arc::new(Concurrency::default());
+    let cpu_concurrency = Arc::new(Concurrency::default());
+
+    let handles = (0..6)
+        .flat_map(|_| {
+            let io_concurrency = Arc::clone(&io_concurrency);
+            let cpu_concurrency = Arc::clone(&cpu_concurrency);
+
+            [
+                io.spawn(move || io_concurrency.run()),
+                cpu.spawn(move || cpu_concurrency.run())
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    handles.into_iter().for_each(|handle| handle.join().unwrap());
+
+    assert_eq!(io_concurrency.max.load(Ordering::SeqCst), 1);
+    assert!(cpu_concurrency.max.load(Ordering::SeqCst) <= 2);
+    assert_eq!(pool.partition("io", PartitionConfig::default()).name(), io.name());
+    pool.shutdown();
+}
+
+#[test]
+fn partition_queues_count_in_metrics() {
+    let pool = create_pool(1, true);
+    let io = pool.partition("io", PartitionConfig::default());
+
+    pool.pause();
+    let handles = (0..3).map(|_| io.spawn(|| ())).collect::<Vec<_>>();
+    assert_eq!(io.queued_tasks(), 3);
+    assert_eq!(pool.metrics().queued_tasks, 3);
+
+    pool.resume();
+    handles.into_iter().for_each(|handle| handle.join().unwrap());
+    assert_eq!(pool.metrics().queued_tasks, 0);
+    pool.shutdown();
+}
+
+#[test]
+fn disabled_work_stealing_keeps_local_tasks() {
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .launch_on_build(true)
+        .name_prefix("Worker")
+        .work_stealing(false)
+        .build()
+        .unwrap();
+
+    let (queued_tx, queued_rx) = crossbeam_channel::bounded(1);
+    let loader = pool.spawn(move || {
+        let handles = (0..10)
+            .map(|_| crate::spawn(|| {
+                sleep(Duration::from_millis(2));
+                thread_name()
+            }))
+            .collect::<Vec<_>>();
+
+        queued_tx.send(()).unwrap();
+        sleep(Duration::from_millis(100));
+        (thread_name(), handles)
+    });
+
+    // wake the other worker, which would steal the queued tasks if it could
+    queued_rx.recv().unwrap();
+    pool.spawn(|| ()).join().unwrap();
+
+    let (loaded, handles) = loader.join().unwrap();
+    assert!(handles.into_iter().all(|handle| handle.join().unwrap() == loaded));
+    pool.shutdown();
+}
+
+#[test]
+fn take_output_through_raw_header() {
+    // runs the task on this thread, without a pool, so it can be checked with miri
+    let (task, handle) = TaskBuilder::new(|| vec![1, 2, 3]).build();
+    let header = handle.into_raw();
+
+    assert!(unsafe { Planetary::take_output_raw::<Vec<i32>>(header) }.is_none());
+    task.run();
+
+    let output = unsafe { Planetary::take_output_raw::<Vec<i32>>(header) };
+    assert_eq!(output.unwrap().unwrap(), [1, 2, 3]);
+    assert!(unsafe { Planetary::take_output_raw::<Vec<i32>>(header) }.is_none());
+
+    drop(unsafe { JoinHandle::<Vec<i32>>::from_raw(header) });
+}
+
+#[test]
+fn aborted_group_produces_no_output() {
+    let pool = create_pool(2, true);
+    let ran = Arc::new(AtomicUsize::new(0));
+
+    pool.pause();
+    let mut group = pool.group();
+    for _ in 0..4 {
+        let ran = Arc::clone(&ran);
+        group.spawn(move || ran.fetch_add(1, Ordering::SeqCst));
+    }
+
+    group.abort_all();
+    // spawned after the abort, so aborted as well
+    group.spawn(|| 0);
+    pool.resume();
+
+    assert!(group.join_all().into_iter().all(|res| res.is_err_and(|e| e.is_aborted())));
+    assert_eq!(ran.load(Ordering::SeqCst), 0);
+    pool.shutdown();
+}
+
+#[test]
+fn group_join_all_returns_outputs_in_order() {
+    let pool = create_pool(2, true);
+
+    let mut group = pool.group().abort_on_drop(true);
+    for i in 0..8u64 {
+        group.spawn(move || {
+            sleep(Duration::from_millis(8 - i));
+            i * 2
+        });
+    }
+
+    assert_eq!(group.len(), 8);
+    let outputs = group.join_all().into_iter().map(Result::unwrap).collect::<Vec<_>>();
+    assert_eq!(outputs, (0..8).map(|i| i * 2).collect::<Vec<_>>());
+    pool.shutdown();
+}
+
+#[test]
+fn fallible_task_errors_reach_the_hook() {
+    let (errors_tx, errors_rx) = crossbeam_channel::unbounded();
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .with_hooks(move |hooks| {
+            hooks.set_on_task_error_fn(move |e| errors_tx.send(format!("{e:?}")).unwrap());
+        })
+        .build()
+        .unwrap();
+
+    let failed = pool.spawn_fallible(|| Err::<u32, _>("bad input"));
+    let succeeded = pool.spawn_fallible(|| Ok::<_, String>(7));
+
+    assert_eq!(failed.join().unwrap(), Err("bad input"));
+    assert_eq!(succeeded.join().unwrap(), Ok(7));
+    assert_eq!(errors_rx.try_iter().collect::<Vec<_>>(), ["\"bad input\""]);
+    pool.shutdown();
+}
+
+#[test]
+fn name_context_includes_worker_and_process() {
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .with_hooks(|hooks| {
+            hooks.set_name_ctx_fn(|ctx| format!("pool{}-pid{}-w{}", ctx.pool_epoch, ctx.pid, ctx.worker_id));
+        })
+        .build()
+        .unwrap();
+
+    let name = pool.spawn(thread_name).join().unwrap();
+    // a single worker always gets id 0, the epoch depends on the other tests
+    assert!(name.starts_with("pool"));
+    assert!(name.ends_with(&format!("-pid{}-w0", std::process::id())));
+    pool.shutdown();
+}
+
+#[test]
+fn test_pool_ticks_one_task_at_a_time() {
+    let pool = Planetary::builder().test_mode();
+    let order = Arc::new(Mutex::new(Vec::new()));
+
+    let handles = (0..3).map(|i| {
+        let order = Arc::clone(&order);
+        pool.spawn(move || order.lock().unwrap().push(i))
+    }).collect::<Vec<_>>();
+
+    assert_eq!(pool.queued_tasks(), 3);
+    assert!(pool.tick());
+    assert_eq!(*order.lock().unwrap(), [0]);
+    assert!(!handles[1].is_finished());
+
+    assert!(pool.tick());
+    assert_eq!(*order.lock().unwrap(), [0, 1]);
+    assert_eq!(pool.queued_tasks(), 1);
+
+    assert_eq!(pool.run_all(), 1);
+    assert!(!pool.tick());
+    assert!(handles.into_iter().all(|h| h.join().is_ok()));
+    assert_eq!(pool.metrics().completed_tasks, 3);
+}
+
+#[test]
+fn test_pool_runs_nested_spawns() {
+    let pool = Planetary::builder().test_mode();
+    let order = Arc::new(Mutex::new(Vec::new()));
+
+    let outer = {
+        let order = Arc::clone(&order);
+        pool.spawn(move || {
+            order.lock().unwrap().push("outer");
+
+            let order = Arc::clone(&order);
+            crate::spawn(move || order.lock().unwrap().push("inner"))
+        })
+    };
+
+    assert!(pool.tick());
+    // the nested task is queued, not run
+    assert_eq!(*order.lock().unwrap(), ["outer"]);
+    assert_eq!(pool.queued_tasks(), 1);
+
+    assert_eq!(pool.run_all(), 1);
+    assert_eq!(*order.lock().unwrap(), ["outer", "inner"]);
+    outer.join().unwrap().join().unwrap();
+
+    // leftover tasks are cancelled with the pool
+    let leftover = pool.spawn(|| ());
+    drop(pool);
+    assert!(leftover.join().is_err_and(|e| e.is_cancelled()));
+}
+
+#[test]
+fn at_capacity_follows_live_workers() {
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .timeout(Duration::from_millis(100))
+        .build()
+        .unwrap();
+
+    assert!(pool.can_grow());
+
+    let (release_tx, release_rx) = crossbeam_channel::bounded::<()>(0);
+    let handles = (0..2).map(|_| {
+        let release_rx = release_rx.clone();
+        pool.spawn(move || { let _ = release_rx.recv(); })
+    }).collect::<Vec<_>>();
+
+    assert!(pool.at_capacity());
+    assert!(!pool.can_grow());
+
+    drop(release_tx);
+    handles.into_iter().for_each(|h| h.join().unwrap());
+
+    // the workers time out once idle, leaving room for new ones
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while pool.at_capacity() && Instant::now() < deadline {
+        sleep(Duration::from_millis(10));
+    }
+
+    assert!(pool.can_grow());
+    pool.shutdown();
+}
+
+#[test]
+fn at_capacity_respects_the_autoscaler_limit() {
+    let pool = Planetary::builder()
+        .max_threads(4)
+        .autoscale(AutoscaleConfig {
+            min_threads: 1,
+            max_threads: 1,
+            interval: Duration::from_millis(10),
+            target_utilization: 0.75,
+            hysteresis: 2,
+        })
+        .build()
+        .unwrap();
+
+    // the autoscaler starts its minimum, which is all it will ever run
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while pool.inner.live_threads() == 0 && Instant::now() < deadline {
+        sleep(Duration::from_millis(1));
+    }
+
+    assert!(pool.at_capacity());
+    pool.shutdown();
+}
+
+#[test]
+fn low_priority_tasks_are_not_starved() {
+    /// Keeps respawning itself into the local queue of the worker until stopped.
+    fn churn(stop: Arc<AtomicBool>) {
+        sleep(Duration::from_millis(1));
+
+        if !stop.load(Ordering::SeqCst) {
+            crate::spawn(move || churn(stop)).detach();
+        }
+    }
+
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .starvation_threshold(Duration::from_millis(50))
+        .build()
+        .unwrap();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    for _ in 0..4 {
+        let stop = Arc::clone(&stop);
+        pool.spawn(move || churn(stop)).detach();
+    }
+
+    let started = Instant::now();
+    let low = pool.spawn_with_priority(Priority::Low, Instant::now);
+    let ran_at = low.join().unwrap();
+    stop.store(true, Ordering::SeqCst);
+
+    // high priority tasks never ran out, so it only ran once it was considered starved
+    assert!(ran_at - started >= Duration::from_millis(50));
+    assert!(ran_at - started < Duration::from_secs(2));
+    pool.shutdown();
+}
+
+#[test]
+fn crashed_workers_are_replaced() {
+    let panicked = Arc::new(AtomicBool::new(false));
+    let hook_panicked = Arc::clone(&panicked);
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .launch_on_build(true)
+        .with_hooks(move |hooks| {
+            hooks.set_before_work_fn(move || {
+                if !hook_panicked.swap(true, Ordering::SeqCst) {
+                    panic!("before_work panicked");
+                }
+            });
+        })
+        .build()
+        .unwrap();
+
+    // the task is dropped along with the crashing worker
+    assert!(pool.spawn(|| ()).join().is_err_and(|e| e.is_cancelled()));
+    assert!(panicked.load(Ordering::SeqCst));
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while pool.inner.live_threads() < 2 && Instant::now() < deadline {
+        sleep(Duration::from_millis(10));
+    }
+
+    assert_eq!(pool.inner.live_threads(), 2);
+    assert_eq!(pool.spawn(|| 5).join().unwrap(), 5);
+    pool.shutdown();
+}
+
+#[test]
+fn spawn_chunks_matches_sequential_map() {
+    let pool = create_pool(4, true);
+    let data = (0..10_007u64).collect::<Vec<_>>();
+    let expected = data.iter().map(|x| x * x + 1).collect::<Vec<_>>();
+
+    let handles = pool.spawn_chunks(data, 1000, |chunk| chunk.iter().map(|x| x * x + 1).collect());
+    assert_eq!(handles.len(), 11);
+
+    let results = handles.into_iter()
+        .flat_map(|h| h.join().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(results, expected);
+
+    assert!(pool.spawn_chunks(Vec::<u64>::new(), 16, |chunk| chunk.to_vec()).is_empty());
+    pool.shutdown();
+}
+
+#[test]
+fn slow_task_hook_fires_without_aborting() {
+    let (slow_tx, slow_rx) = crossbeam_channel::unbounded();
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .with_hooks(move |hooks| {
+            hooks.set_on_slow_task_fn(move |_, elapsed| slow_tx.send(elapsed).unwrap());
+        })
+        .build()
+        .unwrap();
+
+    let slow = pool.spawn_timed(Duration::from_millis(50), || {
+        sleep(Duration::from_millis(200));
+        "done"
+    });
+    let fast = pool.spawn_timed(Duration::from_millis(50), || "quick");
+
+    assert_eq!(slow.join().unwrap(), "done");
+    assert_eq!(fast.join().unwrap(), "quick");
+
+    // only the slow task was reported, once its limit elapsed
+    let elapsed = slow_rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert!(elapsed >= Duration::from_millis(50));
+    assert!(slow_rx.recv_timeout(Duration::from_millis(100)).is_err());
+    pool.shutdown();
+}
+
+#[test]
+fn local_queue_order_changes_child_order() {
+    fn children_order(order: QueueOrder) -> Vec<usize> {
+        let pool = Planetary::builder()
+            .max_threads(1)
+            .local_queue_order(order)
+            .build()
+            .unwrap();
+
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        let children = {
+            let ran = Arc::clone(&ran);
+            pool.spawn(move || {
+                (0..4).map(|i| {
+                    let ran = Arc::clone(&ran);
+                    crate::spawn(move || ran.lock().unwrap().push(i))
+                }).collect::<Vec<_>>()
+            }).join().unwrap()
+        };
+
+        children.into_iter().for_each(|h| h.join().unwrap());
+        pool.shutdown();
+
+        Arc::try_unwrap(ran).unwrap().into_inner().unwrap()
+    }
+
+    assert_eq!(children_order(QueueOrder::Fifo), [0, 1, 2, 3]);
+    assert_eq!(children_order(QueueOrder::Lifo), [3, 2, 1, 0]);
+}
+
+#[test]
+fn cumulative_stats_count_outcomes_and_reset() {
+    let pool = create_pool(2, true);
+
+    pool.pause();
+    let aborted = (0..2).map(|_| pool.spawn(|| ())).collect::<Vec<_>>();
+    aborted.iter().for_each(JoinHandle::abort);
+    pool.resume();
+
+    let ok = (0..5).map(|_| pool.spawn(|| ())).collect::<Vec<_>>();
+    let panicked = (0..3).map(|_| pool.spawn(|| panic!("expected panic"))).collect::<Vec<_>>();
+
+    ok.into_iter().chain(aborted).for_each(|h| { let _ = h.join(); });
+    panicked.into_iter().for_each(|h| assert!(h.join().is_err()));
+
+    // outcomes are counted right after the handles are woken
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while pool.stats().tasks_completed + pool.stats().tasks_panicked + pool.stats().tasks_aborted < 10
+        && Instant::now() < deadline
+    {
+        sleep(Duration::from_millis(5));
+    }
+
+    let stats = pool.stats();
+    assert_eq!(stats.tasks_completed, 5);
+    assert_eq!(stats.tasks_panicked, 3);
+    assert_eq!(stats.tasks_aborted, 2);
+    assert_eq!(stats.threads_spawned, 2);
+
+    pool.reset_stats();
+    assert_eq!(pool.stats(), CumulativeStats::default());
+    pool.shutdown();
+}
+
+#[test]
+fn initial_tasks_run_after_build() {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let tasks = (0..10).map(|_| {
+        let counter = Arc::clone(&counter);
+        move || { counter.fetch_add(1, Ordering::SeqCst); }
+    });
+
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .launch_on_build(true)
+        .with_initial_tasks(tasks)
+        .build()
+        .unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while counter.load(Ordering::SeqCst) < 10 && Instant::now() < deadline {
+        sleep(Duration::from_millis(5));
+    }
+
+    assert_eq!(counter.load(Ordering::SeqCst), 10);
+    pool.shutdown();
+}
+
+#[test]
+fn progress_reported_by_tasks_climbs() {
+    let pool = create_pool(2, true);
+    let (step_tx, step_rx) = crossbeam_channel::bounded::<()>(0);
+
+    let handle = pool.spawn(move || {
+        for step in 1..=4 {
+            step_rx.recv().unwrap();
+            crate::report_progress(step as f32 / 4.0);
+        }
+    });
+
+    assert_eq!(handle.progress(), None);
+
+    let mut observed = Vec::new();
+    for _ in 0..4 {
+        step_tx.send(()).unwrap();
+
+        let last = observed.last().copied().unwrap_or(0.0);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while handle.progress().is_none_or(|p| p <= last) && Instant::now() < deadline {
+            sleep(Duration::from_millis(1));
+        }
+
+        observed.push(handle.progress().unwrap());
+    }
+
+    assert_eq!(observed, [0.25, 0.5, 0.75, 1.0]);
+    handle.join().unwrap();
+
+    // a no-op outside of tasks
+    crate::report_progress(0.5);
+    pool.shutdown();
+}
+
+#[test]
+fn downsizing_keeps_local_tasks() {
+    let pool = Planetary::builder()
+        .max_threads(4)
+        .launch_on_build(true)
+        .work_stealing(false)
+        .build()
+        .unwrap();
+
+    let counter = Arc::new(AtomicUsize::new(0));
+    let (loaded_tx, loaded_rx) = crossbeam_channel::unbounded();
+
+    for id in pool.worker_ids() {
+        let counter = Arc::clone(&counter);
+        let loaded_tx = loaded_tx.clone();
+        pool.spawn_on(id, move || {
+            for _ in 0..10 {
+                let counter = Arc::clone(&counter);
+                crate::spawn(move || {
+                    sleep(Duration::from_millis(10));
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }).detach();
+            }
+
+            loaded_tx.send(()).unwrap();
+        }).detach();
+    }
+
+    for _ in 0..4 {
+        loaded_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    pool.set_max_threads(1);
+    assert_eq!(pool.config().max_threads, 1);
+
+    // the reaped workers handed their queued tasks to the survivor
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while (counter.load(Ordering::SeqCst) < 40 || pool.inner.live_threads() > 1) && Instant::now() < deadline {
+        sleep(Duration::from_millis(10));
+    }
+
+    assert_eq!(counter.load(Ordering::SeqCst), 40);
+    assert_eq!(pool.inner.live_threads(), 1);
+    pool.shutdown();
+}
+
+#[test]
+fn reap_requests_stop_at_the_floor() {
+    let pool = create_pool(3, true);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while pool.inner.live_threads() < 3 {
+        assert!(Instant::now() < deadline, "the workers never started");
+        sleep(Duration::from_millis(1));
+    }
+
+    // whether or not the first request was taken yet, it's the only one needed
+    assert!(pool.inner.request_reap(2));
+    assert!(!pool.inner.request_reap(2));
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while pool.inner.live_threads() > 2 {
+        assert!(Instant::now() < deadline, "the worker never exited");
+        sleep(Duration::from_millis(1));
+    }
+
+    sleep(Duration::from_millis(50));
+    assert_eq!(pool.inner.live_threads(), 2);
+    pool.shutdown();
+}
+
+#[test]
+fn eager_caller_run_skips_thread_startup() {
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .eager_caller_run(true)
+        .build()
+        .unwrap();
+
+    let caller = std::thread::current().id();
+    let spawned_at = Instant::now();
+    let (handle, placement) = pool.spawn_traced(move || (std::thread::current().id(), spawned_at.elapsed()));
+    let (ran_on, latency) = handle.join().unwrap();
+
+    assert_eq!(placement.recv().unwrap(), Placement::Inline);
+    assert_eq!(ran_on, caller);
+    // no thread had to start before the task did
+    assert!(latency < Duration::from_millis(5), "{latency:?}");
+    assert_eq!(pool.stats().tasks_completed, 1);
+
+    // the thread spawned meanwhile takes the next tasks once ready
+    assert_eq!(pool.inner.live_threads(), 1);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while pool.metrics().idle_threads == 0 && Instant::now() < deadline {
+        sleep(Duration::from_millis(1));
+    }
+
+    assert_ne!(pool.spawn(|| std::thread::current().id()).join().unwrap(), caller);
+    pool.shutdown();
+}
+
+#[test]
+fn select_returns_the_first_task_to_finish() {
+    let pool = create_pool(3, true);
+
+    let handles = [300, 50, 200].map(|ms| pool.spawn(move || {
+        sleep(Duration::from_millis(ms));
+        ms
+    }));
+
+    let (index, output, rest) = crate::select(handles.into());
+    assert_eq!(index, 1);
+    assert_eq!(output.unwrap(), 50);
+    assert_eq!(rest.len(), 2);
+
+    let (index, output, rest) = crate::select(rest);
+    assert_eq!((index, output.unwrap()), (1, 200));
+
+    // both done before selecting, the lowest index wins
+    let done = vec![pool.spawn(|| 'a'), pool.spawn(|| 'b')];
+    while !done.iter().all(JoinHandle::is_finished) {
+        sleep(Duration::from_millis(1));
+    }
+
+    let (index, output, _) = crate::select(done);
+    assert_eq!((index, output.unwrap()), (0, 'a'));
+    rest.into_iter().for_each(|h| { h.join().unwrap(); });
+    pool.shutdown();
+}
+
+#[test]
+fn thread_spawn_rate_spaces_out_new_threads() {
+    let created = Arc::new(Mutex::new(Vec::new()));
+    let hook_created = Arc::clone(&created);
+    let pool = Planetary::builder()
+        .max_threads(4)
+        .thread_spawn_rate(Some(Duration::from_millis(50)))
+        .with_hooks(move |hooks| {
+            // the name is built right when the thread is created
+            hooks.set_name_with_id_fn(move |id| {
+                hook_created.lock().unwrap().push(Instant::now());
+                format!("Worker-{id}")
+            });
+        })
+        .build()
+        .unwrap();
+
+    let counter = Arc::new(AtomicUsize::new(0));
+    let handles = (0..40).map(|_| {
+        let counter = Arc::clone(&counter);
+        let handle = pool.spawn(move || {
+            sleep(Duration::from_millis(10));
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        sleep(Duration::from_millis(2));
+        handle
+    }).collect::<Vec<_>>();
+
+    handles.into_iter().for_each(|h| h.join().unwrap());
+    assert_eq!(counter.load(Ordering::SeqCst), 40);
+
+    let created = created.lock().unwrap();
+    assert!(created.len() > 1);
+    assert!(created.windows(2).all(|w| w[1] - w[0] >= Duration::from_millis(45)));
+    pool.shutdown();
+}
+
+#[test]
+fn reset_returns_the_pool_to_a_clean_state() {
+    let pool = create_pool(2, true);
+    let (tx, rx) = crossbeam_channel::bounded::<()>(0);
+
+    let blocked = pool.spawn(move || { let _ = rx.recv(); });
+    assert!(!pool.reset());
+    tx.send(()).unwrap();
+    blocked.join().unwrap();
+
+    let run_batch = || {
+        let handles = (0..20).map(|i| pool.spawn(move || i * 2)).collect::<Vec<_>>();
+        assert_eq!(handles.into_iter().map(|h| h.join().unwrap()).sum::<i32>(), 380);
+    };
+
+    run_batch();
+
+    // the workers leave their work section right after waking the handles
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !pool.reset() {
+        assert!(Instant::now() < deadline);
+        sleep(Duration::from_millis(5));
+    }
+
+    assert_eq!(pool.stats(), CumulativeStats::default());
+    assert_eq!(pool.metrics().completed_tasks, 0);
+
+    run_batch();
+    assert!(pool.metrics().completed_tasks <= 20);
+    pool.shutdown();
+}
+
+/// Id of the worker stealing along with the peers offered to the strategy.
+type StealPick = (usize, Vec<Peer>);
+
+/// Random steal strategy that records the peers offered to it.
+#[derive(Default)]
+struct RecordingSteal {
+    picks: Arc<Mutex<Vec<StealPick>>>,
+}
+
+impl StealStrategy for RecordingSteal {
+    fn pick(&self, self_id: usize, peers: &[Peer]) -> Option<usize> {
+        self.picks.lock().unwrap().push((self_id, peers.to_vec()));
+        crate::steal::Random.pick(self_id, peers)
+    }
+}
+
+fn record_steals() -> Vec<StealPick> {
+    let strategy = RecordingSteal::default();
+    let picks = Arc::clone(&strategy.picks);
+    let pool = Planetary::builder()
+        .max_threads(3)
+        .launch_on_build(true)
+        .steal_strategy(strategy)
+        .build()
+        .unwrap();
+
+    // let every worker start before queueing anything
+    sleep(Duration::from_millis(50));
+
+    let children = pool.spawn(|| {
+        let handles = (0..30)
+            .map(|_| crate::spawn(|| sleep(Duration::from_millis(2))))
+            .collect::<Vec<_>>();
+
+        sleep(Duration::from_millis(100));
+        handles
+    }).join().unwrap();
+
+    children.into_iter().for_each(|h| h.join().unwrap());
+
+    // workers exiting during the shutdown leave the rest with fewer peers
+    let picks = picks.lock().unwrap().clone();
+    pool.shutdown();
+    picks
+}
+
+#[cfg(not(feature = "numa"))]
+#[test]
+fn steals_without_topology_see_every_peer() {
+    let picks = record_steals();
+
+    assert!(!picks.is_empty());
+    assert!(picks.iter().all(|(_, peers)| peers.len() == 3));
+    assert!(picks.iter().flat_map(|(_, peers)| peers).all(|peer| peer.node.is_none()));
+}
+
+#[cfg(feature = "numa")]
+#[test]
+fn steals_prefer_workers_on_the_same_node() {
+    let picks = record_steals();
+    assert!(!picks.is_empty());
+
+    for (self_id, peers) in picks {
+        let Some(node) = peers.iter().find(|peer| peer.id == self_id).and_then(|peer| peer.node) else {
+            // no topology, nothing to prefer
+            continue;
+        };
+
+        // either only same node victims are offered, or every worker as the fallback
+        assert!(peers.iter().all(|peer| peer.node == Some(node)) || peers.len() == 3);
+    }
+}
+
+#[test]
+fn finalizers_run_once_for_every_outcome() {
+    let pool = create_pool(1, true);
+    let outcomes = Arc::new(Mutex::new(Vec::new()));
+
+    let record = |name: &'static str| {
+        let outcomes = Arc::clone(&outcomes);
+        move |result: &JoinResult<i32>| {
+            let outcome = match result {
+                Ok(value) => format!("{name}: ok {value}"),
+                Err(e) if e.is_panic() => format!("{name}: panic"),
+                Err(JoinError::Aborted) => format!("{name}: aborted"),
+                Err(e) => format!("{name}: {e:?}"),
+            };
+            outcomes.lock().unwrap().push(outcome);
+        }
+    };
+
+    pool.pause();
+    let aborted = pool.spawn_with_finalizer(|| 1, record("aborted"));
+    aborted.abort();
+    pool.resume();
+
+    let ok = pool.spawn_with_finalizer(|| 2, record("ok"));
+    let panicked = pool.spawn_with_finalizer(|| -> i32 { panic!("expected panic") }, record("panicked"));
+
+    assert_eq!(ok.join().unwrap(), 2);
+    assert!(panicked.join().unwrap_err().is_panic());
+    assert!(matches!(aborted.join(), Err(JoinError::Aborted)));
+    pool.shutdown();
+
+    let mut outcomes = outcomes.lock().unwrap().clone();
+    outcomes.sort();
+    assert_eq!(outcomes, ["aborted: aborted", "ok: ok 2", "panicked: panic"]);
+}
+
+#[test]
+fn spawn_blocking_timeout_gives_up_or_waits_for_capacity() {
+    let (pool, release) = saturated_pool(SaturationPolicy::Reject);
+
+    let start = Instant::now();
+    let Err(rejected) = pool.spawn_blocking_timeout(|| 1, Duration::from_millis(50)) else {
+        panic!("the pool stayed saturated");
+    };
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert_eq!(rejected(), 1);
+
+    let releaser = std::thread::spawn(move || {
+        sleep(Duration::from_millis(30));
+        release.send(()).unwrap();
+    });
+
+    let handle = pool.spawn_blocking_timeout(|| 2, Duration::from_secs(5))
+        .unwrap_or_else(|_| panic!("capacity freed within the wait"));
+    assert_eq!(handle.join().unwrap(), 2);
+
+    releaser.join().unwrap();
+    pool.shutdown();
+}
+
+#[test]
+fn worker_cpu_times_reflect_imbalance() {
+    let pool = create_pool(4, true);
+    let loaded = pool.worker_ids()[0];
+
+    let handles = (0..10)
+        .map(|_| pool.spawn_on(loaded, || sleep(Duration::from_millis(5))))
+        .collect::<Vec<_>>();
+    handles.into_iter().for_each(|h| h.join().unwrap());
+
+    // times are added right after the handles are woken
+    let loaded_time = || pool.worker_cpu_times().into_iter().find(|(id, _)| *id == loaded).unwrap().1;
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while loaded_time() < Duration::from_millis(50) && Instant::now() < deadline {
+        sleep(Duration::from_millis(5));
+    }
+
+    let times = pool.worker_cpu_times();
+    assert_eq!(times.len(), 4);
+
+    for (id, time) in times {
+        if id == loaded {
+            assert!(time >= Duration::from_millis(50), "{time:?}");
+        } else {
+            assert!(time < Duration::from_millis(10), "{time:?}");
+        }
+    }
+
+    pool.shutdown();
+}
+
+#[test]
+fn dedicated_stealers_drain_loaded_workers() {
+    let steals = Arc::new(AtomicUsize::new(0));
+    let pool = Planetary::builder()
+        .max_threads(3)
+        .launch_on_build(true)
+        .dedicated_stealers(2)
+        .name_prefix("Worker")
+        .with_hooks(|hooks| {
+            let steals = Arc::clone(&steals);
+            hooks.set_on_steal_fn(move |_, _| { steals.fetch_add(1, Ordering::SeqCst); });
+        })
+        .build()
+        .unwrap();
+
+    // stealers can't be targeted, so only the regular worker takes the task
+    let mut regular = None;
+    let mut rejected = 0;
+
+    for id in pool.worker_ids() {
+        match pool.try_spawn_on(id, || ()) {
+            Ok(handle) => {
+                handle.join().unwrap();
+                regular = Some(id);
+            }
+            Err(_) => rejected += 1,
+        }
+    }
+
+    assert_eq!(rejected, 2);
+
+    // the regular worker stays busy, so only the stealers can run its queue
+    let regular = regular.unwrap();
+    let loaded = load_local_queues(&pool, &[(regular, 30)]);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while steals.load(Ordering::SeqCst) < 30 {
+        assert!(Instant::now() < deadline, "the stealers stopped draining the queue");
+        sleep(Duration::from_millis(1));
+    }
+
+    let names = loaded.release().concat();
+    assert!(names.iter().all(|name| *name != format!("Worker-{regular}")));
+    pool.shutdown();
+}
+
+#[test]
+fn map_join_maps_outputs_and_panics() {
+    #[derive(Debug, PartialEq)]
+    enum Outcome {
+        Doubled(i32),
+        Crashed,
+    }
+
+    let to_outcome = |result: JoinResult<i32>| result.map(Outcome::Doubled).unwrap_or(Outcome::Crashed);
+    let pool = create_pool(1, true);
+
+    assert_eq!(pool.spawn(|| 21 * 2).map_join(to_outcome), Outcome::Doubled(42));
+    assert_eq!(pool.spawn(|| -> i32 { panic!("expected panic") }).map_join(to_outcome), Outcome::Crashed);
+
+    // the only worker runs the child while waiting for it
+    let nested = pool.spawn(move || crate::spawn(|| 4).map_join(to_outcome)).join().unwrap();
+    assert_eq!(nested, Outcome::Doubled(4));
+    pool.shutdown();
+}
+
+#[test]
+fn map_join_keeps_running_tasks_spawned_while_parked() {
+    let pool = create_pool(2, true);
+
+    // one worker sleeps in the task while the other one waits on it with nothing to run
+    let slow = pool.spawn(|| sleep(Duration::from_millis(300)));
+    let waiter = pool.spawn(move || slow.map_join(|result| result.is_ok()));
+    sleep(Duration::from_millis(50));
+
+    // no worker is parked in the threadpool, so only the waiting one can pick it up
+    let started = Instant::now();
+    pool.spawn(|| ()).join().unwrap();
+    assert!(started.elapsed() < Duration::from_millis(150), "Waiting worker didn't run the task");
+
+    assert!(waiter.join().unwrap());
+    pool.shutdown();
+}
+
+#[test]
+fn drained_injector_tasks_run_in_the_new_order() {
+    let pool = create_pool(1, true);
+    let order = Arc::new(Mutex::new(Vec::new()));
+
+    pool.pause();
+    let handles = (0..5).map(|i| {
+        let order = Arc::clone(&order);
+        pool.spawn(move || order.lock().unwrap().push(i))
+    }).collect::<Vec<_>>();
+
+    let tasks = pool.drain_injector();
+    assert_eq!(tasks.len(), 5);
+    assert_eq!(pool.injector_len(), 0);
+
+    tasks.into_iter().rev().for_each(|task| pool.spawn_erased(task));
+    pool.resume();
+
+    handles.into_iter().for_each(|h| h.join().unwrap());
+    assert_eq!(*order.lock().unwrap(), [4, 3, 2, 1, 0]);
+    pool.shutdown();
+}
+
+#[test]
+fn join_n_returns_the_fastest_tasks() {
+    let pool = create_pool(5, true);
+
+    let handles = [40u64, 200, 10, 300, 70]
+        .into_iter()
+        .map(|ms| pool.spawn(move || {
+            sleep(Duration::from_millis(ms));
+            ms
+        }))
+        .collect::<Vec<_>>();
+
+    let (done, pending) = pool.join_n(handles, 3);
+    let mut indexes = done.iter().map(|(index, _)| *index).collect::<Vec<_>>();
+    indexes.sort();
+
+    assert_eq!(indexes, [0, 2, 4]);
+    assert!(done.into_iter().all(|(_, result)| result.unwrap() < 100));
+    assert_eq!(pending.into_iter().map(|h| h.join().unwrap()).collect::<Vec<_>>(), [200, 300]);
+
+    // more than there are handles waits for all of them
+    let (done, pending) = pool.join_n(vec![pool.spawn(|| 1u64)], 5);
+    assert_eq!(done.len(), 1);
+    assert!(pending.is_empty());
+    pool.shutdown();
+}
+
+#[test]
+fn health_reflects_stranded_tasks_and_shutdown() {
+    let pool = create_pool(2, true);
+    assert!(pool.is_healthy());
+
+    // a test pool has no workers, so its tasks wait until it's driven
+    let stranded = Planetary::builder().test_mode();
+    assert!(stranded.is_healthy());
+    let handle = stranded.spawn(|| 1);
+    assert!(!stranded.is_healthy());
+    stranded.run_all();
+    assert_eq!(handle.join().unwrap(), 1);
+    assert!(stranded.is_healthy());
+
+    let observer = pool.clone();
+    pool.shutdown();
+    assert!(!observer.is_healthy());
+}
+
+#[test]
+fn work_hooks_receive_matching_task_ids() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let (before, after) = (Arc::clone(&events), Arc::clone(&events));
+
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .launch_on_build(true)
+        .with_hooks(move |hooks| {
+            hooks.set_before_work_with_id_fn(move |id| before.lock().unwrap().push(("before", id)))
+                .set_after_work_with_id_fn(move |id| after.lock().unwrap().push(("after", id)));
+        })
+        .build()
+        .unwrap();
+
+    for i in 0..5 {
+        assert_eq!(pool.spawn(move || i).join().unwrap(), i);
+    }
+
+    pool.shutdown();
+
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 10);
+
+    let ids = events.chunks(2)
+        .map(|pair| {
+            assert_eq!((pair[0].0, pair[1].0), ("before", "after"));
+            assert_eq!(pair[0].1, pair[1].1);
+            pair[0].1
+        })
+        .collect::<std::collections::HashSet<_>>();
+    assert_eq!(ids.len(), 5);
+}
+
+#[test]
+fn spawned_context_is_visible_to_the_task() {
+    #[derive(Debug, Clone, PartialEq)]
+    struct RequestId(u32);
+
+    let pool = create_pool(2, true);
+
+    let handles = (0..4)
+        .map(|i| pool.spawn_with_context(RequestId(i), || {
+            // children don't inherit it
+            let child = crate::spawn(crate::current_context::<RequestId>).map_join(Result::unwrap);
+            (crate::current_context::<RequestId>(), crate::current_context::<String>(), child)
+        }))
+        .collect::<Vec<_>>();
+
+    for (i, handle) in handles.into_iter().enumerate() {
+        assert_eq!(handle.join().unwrap(), (Some(RequestId(i as u32)), None, None));
+    }
+
+    assert_eq!(crate::current_context::<RequestId>(), None);
+    pool.shutdown();
+}
+
+#[test]
+fn prewarm_starts_every_worker() {
+    let started = Arc::new(AtomicUsize::new(0));
+    let hook_started = Arc::clone(&started);
+
+    let pool = Planetary::builder()
+        .max_threads(4)
+        .with_hooks(move |hooks| {
+            hooks.set_on_start_fn(move || {
+                // slow lazy initialization
+                sleep(Duration::from_millis(20));
+                hook_started.fetch_add(1, Ordering::SeqCst);
+            });
+        })
+        .build()
+        .unwrap();
+
+    assert_eq!(pool.inner.live_threads(), 0);
+    assert_eq!(pool.prewarm(), 4);
+
+    assert_eq!(pool.inner.live_threads(), 4);
+    assert_eq!(started.load(Ordering::SeqCst), 4);
+    // the workers are still alive, so there's nothing left to start
+    assert_eq!(pool.prewarm(), 0);
+    pool.shutdown();
+}
+
+#[test]
+fn async_mutex_excludes_without_blocking_the_worker() {
+    // a single worker, which would deadlock if waiting for the lock blocked it
+    let pool = create_pool(1, true);
+    let mutex = Arc::new(crate::sync::Mutex::new(0));
+    let inside = Arc::new(AtomicBool::new(false));
+
+    let handles = (0..8).map(|_| {
+        let (mutex, inside) = (Arc::clone(&mutex), Arc::clone(&inside));
+
+        pool.spawn_future(async move {
+            for _ in 0..10 {
+                let mut guard = mutex.lock().await;
+                assert!(!inside.swap(true, Ordering::SeqCst));
+
+                // let the rest of futures contend for the lock while holding it
+                crate::reschedule().await;
+                *guard += 1;
+
+                inside.store(false, Ordering::SeqCst);
+            }
+        })
+    }).collect::<Vec<_>>();
+
+    handles.into_iter().for_each(|h| h.join().unwrap());
+    assert_eq!(*mutex.try_lock().unwrap(), 80);
+    pool.shutdown();
+}
+
+#[test]
+fn async_notify_wakes_a_waiting_future() {
+    let pool = create_pool(1, true);
+    let notify = Arc::new(crate::sync::Notify::new());
+    let steps = Arc::new(Mutex::new(Vec::new()));
+
+    let waiter = {
+        let (notify, steps) = (Arc::clone(&notify), Arc::clone(&steps));
+        pool.spawn_future(async move {
+            steps.lock().unwrap().push("waiting");
+            notify.notified().await;
+            steps.lock().unwrap().push("notified");
+        })
+    };
+
+    // runs on the same worker once the waiter parked
+    let notifier = {
+        let (notify, steps) = (Arc::clone(&notify), Arc::clone(&steps));
+        pool.spawn_future(async move {
+            crate::reschedule().await;
+            steps.lock().unwrap().push("notifying");
+            notify.notify_one();
+        })
+    };
+
+    notifier.join().unwrap();
+    waiter.join().unwrap();
+    assert_eq!(*steps.lock().unwrap(), ["waiting", "notifying", "notified"]);
+
+    // a notification sent with nobody waiting is kept for the next one
+    notify.notify_one();
+    pool.spawn_future(async move { notify.notified().await }).join().unwrap();
+    pool.shutdown();
+}
+
+#[test]
+fn weak_handles_upgrade_until_the_output_is_reclaimed() {
+    let pool = create_pool(1, true);
+    let (tx, rx) = std::sync::mpsc::channel::<()>();
+    let output = Arc::new(());
+
+    let task_output = Arc::clone(&output);
+    let weak = pool.spawn_weak(move || {
+        rx.recv().unwrap();
+        task_output
+    });
+
+    let handle = weak.upgrade().expect("the task is still pending");
+    // only one handle can wait for the output
+    assert!(weak.upgrade().is_none());
+    drop(handle);
+
+    tx.send(()).unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while let Some(handle) = weak.upgrade() {
+        assert!(Instant::now() < deadline, "output never reclaimed");
+        drop(handle);
+        sleep(Duration::from_millis(1));
+    }
+
+    // the weak handle doesn't keep the output alive
+    assert!(weak.is_finished());
+    assert_eq!(Arc::strong_count(&output), 1);
+    pool.shutdown();
+}
+
+#[test]
+fn panic_observer_sees_panics_of_every_pool() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let observed = Arc::clone(&seen);
+
+    crate::set_panic_observer(move |panic| {
+        // other tests may panic tasks concurrently
+        if let Some(message) = panic.message().filter(|message| message.starts_with("observed")) {
+            observed.lock().unwrap().push(message.to_string());
+        }
+    });
+
+    let first = create_pool(1, true);
+    let second = create_pool(1, true);
+
+    assert!(first.spawn(|| panic!("observed first")).join().is_err());
+    assert!(second.spawn(|| std::panic::panic_any(String::from("observed second"))).join().is_err());
+    crate::panic::clear_panic_observer();
+
+    let mut seen = seen.lock().unwrap().clone();
+    seen.sort();
+    assert_eq!(seen, ["observed first", "observed second"]);
+
+    // the observer runs without the lock held, so it can replace itself
+    crate::set_panic_observer(|_| crate::panic::clear_panic_observer());
+    assert!(first.spawn(|| panic!("observed once")).join().is_err());
+
+    first.shutdown();
+    second.shutdown();
+}
+
+#[test]
+#[cfg(feature = "inspect")]
+fn abort_where_aborts_only_matching_tasks() {
+    let pool = create_pool(1, true);
+    let (tx, rx) = std::sync::mpsc::channel::<()>();
+
+    // keep the only worker busy so the rest of the tasks don't start
+    let blocker = pool.spawn_named("blocker", move || rx.recv().unwrap());
+
+    let handles = ["report-daily", "index", "report-weekly", "cleanup"]
+        .map(|name| (name, pool.spawn_named(name, || ())));
+
+    let aborted = pool.abort_where(|task| {
+        !task.running && task.name.is_some_and(|name| name.starts_with("report-"))
+    });
+    assert_eq!(aborted, 2);
+
+    tx.send(()).unwrap();
+    blocker.join().unwrap();
+
+    for (name, handle) in handles {
+        match handle.join() {
+            Err(JoinError::Aborted) => assert!(name.starts_with("report-")),
+            Ok(()) => assert!(!name.starts_with("report-")),
+            Err(error) => panic!("unexpected error {error}"),
+        }
+    }
+
+    // already aborted tasks are not counted again
+    assert_eq!(pool.abort_where(|_| true), 0);
+    pool.shutdown();
+}
+
+#[test]
+#[cfg(feature = "inspect")]
+fn finished_tasks_leave_the_registry_whatever_their_submit_path() {
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .saturation_policy(SaturationPolicy::CallerRuns)
+        .launch_on_build(true)
+        .build()
+        .unwrap();
+
+    let id = pool.worker_ids()[0];
+    pool.try_spawn_on(id, || ()).ok().unwrap().join().unwrap();
+    pool.spawn_blocking(|| ()).join().unwrap();
+    pool.spawn_with_priority(Priority::Low, || ()).join().unwrap();
+    pool.partition("io", PartitionConfig::default()).spawn(|| ()).join().unwrap();
+
+    // with the worker idle, the keyed task is queued for it instead of running inline
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while pool.metrics().idle_threads == 0 && Instant::now() < deadline {
+        sleep(Duration::from_millis(1));
+    }
+
+    // once the worker runs it, the duplicate follows the keyed task and the pool is
+    // saturated, so the last task runs inline without blocking the test thread
+    let (started_tx, started_rx) = crossbeam_channel::bounded::<()>(1);
+    let (release_tx, release_rx) = crossbeam_channel::bounded::<()>(1);
+    let keyed = pool.spawn_keyed("key", move || {
+        started_tx.send(()).unwrap();
+        release_rx.recv().unwrap();
+    });
+    started_rx.recv().unwrap();
+
+    let follower = pool.spawn_keyed("key", || ());
+    let (inline, placement) = pool.spawn_traced(|| ());
+    assert_eq!(placement.recv().unwrap(), Placement::Inline);
+    release_tx.send(()).unwrap();
+    keyed.join().unwrap();
+    follower.join().unwrap();
+    inline.join().unwrap();
+
+    let visited = AtomicUsize::new(0);
+    pool.abort_where(|_| {
+        visited.fetch_add(1, Ordering::SeqCst);
+        false
+    });
+    assert_eq!(visited.load(Ordering::SeqCst), 0);
+    pool.shutdown();
+}
+
+#[test]
+fn shutdown_is_observed_while_tasks_keep_coming() {
+    fn respawn(count: Arc<AtomicUsize>) {
+        count.fetch_add(1, Ordering::Relaxed);
+        crate::spawn(move || respawn(count)).detach();
+    }
+
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .timeout(Duration::from_secs(5))
+        .launch_on_build(true)
+        .build()
+        .unwrap();
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let task_count = Arc::clone(&count);
+    pool.spawn(move || respawn(task_count)).detach();
+
+    // the worker never runs out of tasks, so it only sees the stop flag between them
+    while count.load(Ordering::Relaxed) < 1000 {
+        sleep(Duration::from_millis(1));
+    }
+
+    let started = Instant::now();
+    pool.shutdown();
+    assert!(started.elapsed() < Duration::from_secs(1), "shutdown took {:?}", started.elapsed());
+
+    let stopped_at = count.load(Ordering::Relaxed);
+    sleep(Duration::from_millis(20));
+    assert_eq!(count.load(Ordering::Relaxed), stopped_at);
+}
+
+#[test]
+fn parallelize_matches_a_sequential_map() {
+    fn collatz_steps(mut n: u64) -> u32 {
+        let mut steps = 0;
+
+        while n != 1 {
+            n = if n.is_multiple_of(2) { n / 2 } else { 3 * n + 1 };
+            steps += 1;
+        }
+
+        steps
+    }
+
+    let pool = create_pool(4, true);
+    let offset = 1;
+    let items = (0..20_000u64).collect::<Vec<_>>();
+
+    let expected = items.iter().map(|n| collatz_steps(n + offset)).collect::<Vec<_>>();
+    // the closure borrows from the caller
+    let parallel = pool.parallelize(items, |n| collatz_steps(n + offset));
+    assert_eq!(parallel, expected);
+
+    assert!(pool.parallelize(Vec::<u64>::new(), |n| n).is_empty());
+    // a worker helps with the chunks instead of blocking
+    let nested = pool.spawn({
+        let pool = pool.clone();
+        move || pool.parallelize(vec![1, 2, 3], |n| n * 2)
+    }).map_join(Result::unwrap);
+    assert_eq!(nested, [2, 4, 6]);
+    pool.shutdown();
+}
+
+#[test]
+fn join_helping_runs_other_tasks_while_waiting() {
+    let pool = create_pool(1, true);
+    let (tx, rx) = std::sync::mpsc::channel::<()>();
+    let caller = std::thread::current().id();
+
+    // saturate the only worker, the caller would block on it otherwise
+    let blocker = pool.spawn(move || rx.recv().unwrap());
+    while !blocker.has_started() {
+        sleep(Duration::from_millis(1));
+    }
+
+    let ran_on_caller = Arc::new(AtomicUsize::new(0));
+    let handles = (0..10)
+        .map(|_| {
+            let ran_on_caller = Arc::clone(&ran_on_caller);
+            pool.spawn(move || {
+                if std::thread::current().id() == caller {
+                    ran_on_caller.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let awaited = pool.spawn(move || std::thread::current().id());
+    assert_eq!(awaited.join_helping().unwrap(), caller);
+    assert_eq!(ran_on_caller.load(Ordering::SeqCst), 10);
+
+    // the caller is left as it was
+    assert!(crate::worker::try_get_worker().is_none());
+    assert!(Planetary::try_current().is_some());
+
+    tx.send(()).unwrap();
+    blocker.join().unwrap();
+    handles.into_iter().for_each(|h| h.join().unwrap());
+    pool.shutdown();
+}
+
+#[test]
+fn join_helping_helps_the_threadpool_of_the_task() {
+    let pool = create_pool(1, true);
+    let (tx, rx) = std::sync::mpsc::channel::<()>();
+    let blocker = pool.spawn(move || rx.recv().unwrap());
+    while !blocker.has_started() {
+        sleep(Duration::from_millis(1));
+    }
+
+    // building another threadpool makes it the one in scope of this thread
+    let other = create_pool(1, true);
+    let caller = std::thread::current().id();
+    let awaited = pool.spawn(move || std::thread::current().id());
+    assert_eq!(awaited.join_helping().unwrap(), caller);
+
+    tx.send(()).unwrap();
+    blocker.join().unwrap();
+    other.shutdown();
+    pool.shutdown();
+}
+
+#[test]
+fn blocking_pool_is_capped_and_drains_its_queue() {
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .max_blocking_threads(2)
+        .blocking_queue_policy(BlockingQueuePolicy::Queue(4))
+        .blocking_timeout(Duration::from_millis(50))
+        .build()
+        .unwrap();
+
+    let running = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+
+    // spawning waits for room in the queue once it's full
+    let handles = (0..20)
+        .map(|_| {
+            let (running, peak) = (Arc::clone(&running), Arc::clone(&peak));
+            pool.spawn_blocking(move || {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                sleep(Duration::from_millis(5));
+                running.fetch_sub(1, Ordering::SeqCst);
+            })
+        })
+        .collect::<Vec<_>>();
+
+    assert!(pool.blocking_threads() <= 2);
+    handles.into_iter().for_each(|h| h.join().unwrap());
+    assert_eq!(peak.load(Ordering::SeqCst), 2);
+    // blocking tasks don't take the workers
+    assert_eq!(pool.inner.live_threads(), 0);
+
+    // idle blocking threads exit after their timeout
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while pool.blocking_threads() > 0 {
+        assert!(Instant::now() < deadline, "blocking threads never exited");
+        sleep(Duration::from_millis(10));
+    }
+
+    pool.shutdown();
+}
+
+#[test]
+fn blocking_tasks_run_hooks_and_count_in_stats() {
+    let before = Arc::new(AtomicUsize::new(0));
+
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .with_hooks(|hooks| {
+            let before = Arc::clone(&before);
+            hooks.set_before_work_fn(move || { before.fetch_add(1, Ordering::SeqCst); });
+        })
+        .build()
+        .unwrap();
+
+    pool.spawn_blocking(|| ()).join().unwrap();
+    pool.quiesce_hooks();
+
+    assert_eq!(before.load(Ordering::SeqCst), 1);
+    assert_eq!(pool.stats().tasks_completed, 1);
+    pool.shutdown();
+}
+
+#[test]
+fn blocking_threads_spawning_into_a_full_queue_run_inline() {
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .max_blocking_threads(1)
+        .blocking_queue_policy(BlockingQueuePolicy::Queue(0))
+        .build()
+        .unwrap();
+
+    // the only blocking thread would wait for itself to make room otherwise
+    let (outer, inner) = pool.spawn_blocking(|| {
+        let inner = Planetary::current().spawn_blocking(|| std::thread::current().id());
+        (std::thread::current().id(), inner.join().unwrap())
+    }).join().unwrap();
+
+    assert_eq!(outer, inner);
+    pool.shutdown();
+}
+
+#[test]
+fn shutdown_waits_for_blocking_threads() {
+    let pool = create_pool(1, true);
+    let finished = Arc::new(AtomicBool::new(false));
+    let (started_tx, started_rx) = std::sync::mpsc::channel();
+
+    pool.spawn_blocking({
+        let finished = Arc::clone(&finished);
+        move || {
+            started_tx.send(()).unwrap();
+            sleep(Duration::from_millis(50));
+            finished.store(true, Ordering::SeqCst);
+        }
+    }).detach();
+
+    started_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    pool.shutdown();
+    assert!(finished.load(Ordering::SeqCst));
+}
+
+#[test]
+#[should_panic(expected = "max_blocking_threads must be at least 1")]
+fn zero_blocking_threads_are_rejected() {
+    Planetary::builder().max_blocking_threads(0);
+}
+
+#[test]
+fn join_with_tick_fires_every_interval() {
+    let pool = create_pool(1, true);
+    let handle = pool.spawn(|| {
+        sleep(Duration::from_millis(250));
+        7
+    });
+
+    let mut ticks = 0;
+    assert_eq!(handle.join_with_tick(Duration::from_millis(50), || ticks += 1).unwrap(), 7);
+    assert!((2..=5).contains(&ticks), "ticked {ticks} times");
+
+    // tasks that are already done never tick
+    let mut ticks = 0;
+    let done = pool.spawn(|| ());
+    while !done.is_finished() {
+        sleep(Duration::from_millis(1));
+    }
+    done.join_with_tick(Duration::ZERO, || ticks += 1).unwrap();
+    assert_eq!(ticks, 0);
+    pool.shutdown();
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn planetary_spawns_futures_as_an_executor() {
+    use futures_task::{FutureObj, Spawn};
+
+    fn spawn_with(spawner: &impl Spawn, tx: std::sync::mpsc::Sender<u32>) -> Result<(), futures_task::SpawnError> {
+        spawner.spawn_obj(FutureObj::new(Box::new(async move {
+            crate::reschedule().await;
+            tx.send(42).unwrap();
+        })))
+    }
+
+    let pool = create_pool(2, true);
+    let (tx, rx) = std::sync::mpsc::channel();
+
+    assert!(pool.status().is_ok());
+    spawn_with(&pool, tx.clone()).unwrap();
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(42));
+
+    let observer = pool.clone();
+    pool.shutdown();
+    assert!(spawn_with(&observer, tx).unwrap_err().is_shutdown());
+}
+
+#[test]
+fn self_join_deadlock_is_reported() {
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .launch_on_build(true)
+        .detect_deadlocks(true)
+        .build()
+        .unwrap();
+
+    // the inner task lands on the local queue of the only worker, which blocks joining it
+    let outer = pool.spawn(|| crate::spawn(|| 1).join());
+    let Err(JoinError::Panic(payload)) = outer.join() else {
+        panic!("the self join didn't report a deadlock");
+    };
+    let message = payload.downcast_ref::<String>().unwrap();
+    assert!(message.starts_with("deadlock detected"), "{message}");
+
+    // joins that can complete are left alone
+    let nested = pool.spawn(|| crate::spawn(|| 2).map_join(Result::unwrap)).join().unwrap();
+    assert_eq!(nested, 2);
+    pool.shutdown();
+}
+
+#[test]
+fn large_outputs_are_boxed_out_of_the_task() {
+    let pool = create_pool(1, true);
+    let seed = 3u8;
+
+    let ((task, handle), largest) = largest_allocation(|| {
+        TaskBuilder::new(move || std::array::from_fn::<u8, 16384, _>(|i| (i as u8).wrapping_mul(seed))).build()
+    });
+    // the allocation only holds the header and the closure
+    assert!(largest < 1024, "task allocation took {largest} bytes");
+
+    pool.spawn_erased(task);
+    let output = handle.join().unwrap();
+    assert!(output.iter().enumerate().all(|(i, byte)| *byte == (i as u8).wrapping_mul(seed)));
+
+    // detached outputs reach the result sink unboxed
+    let (tx, rx) = std::sync::mpsc::channel();
+    let sink_pool = Planetary::builder()
+        .max_threads(1)
+        .result_sink(move |result| tx.send(result).unwrap())
+        .build()
+        .unwrap();
+
+    sink_pool.spawn(|| [7u64; 1024]).detach();
+    let output = rx.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+    assert_eq!(*output.downcast::<[u64; 1024]>().unwrap(), [7u64; 1024]);
+
+    sink_pool.shutdown();
+    pool.shutdown();
+}
+
+#[test]
+fn keyed_spawns_coalesce_duplicates() {
+    let pool = create_pool(2, true);
+    let runs = Arc::new(AtomicUsize::new(0));
+    let barrier = std::sync::Barrier::new(8);
+
+    let outputs = std::thread::scope(|scope| {
+        let spawners = (0..8)
+            .map(|_| scope.spawn(|| {
+                let runs = Arc::clone(&runs);
+                barrier.wait();
+
+                pool.spawn_keyed("refresh", move || {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    sleep(Duration::from_millis(100));
+                    String::from("fresh")
+                }).join().unwrap()
+            }))
+            .collect::<Vec<_>>();
+
+        spawners.into_iter().map(|s| s.join().unwrap()).collect::<Vec<_>>()
+    });
+
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+    assert!(outputs.iter().all(|output| output == "fresh"));
+
+    // once finished, the key can be spawned again
+    assert_eq!(pool.spawn_keyed("refresh", || String::from("again")).join().unwrap(), "again");
+    let observer = pool.clone();
+    pool.shutdown();
+
+    // the coalesced spawns still count as tasks run by the threadpool
+    assert_eq!(observer.stats().tasks_completed, 9);
+}
+
+#[test]
+fn keyed_followers_complete_under_a_concurrency_limit() {
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .max_concurrent_tasks(1)
+        .launch_on_build(true)
+        .build()
+        .unwrap();
+
+    let (started_tx, started_rx) = crossbeam_channel::bounded::<()>(1);
+    let (release_tx, release_rx) = crossbeam_channel::bounded::<()>(1);
+    let leader = pool.spawn_keyed("limited", move || {
+        started_tx.send(()).unwrap();
+        release_rx.recv().unwrap();
+        7
+    });
+
+    // the leader holds the only permit while its follower completes
+    started_rx.recv().unwrap();
+    let follower = pool.spawn_keyed("limited", || 0);
+    release_tx.send(()).unwrap();
+
+    assert_eq!(leader.join().unwrap(), 7);
+    assert_eq!(follower.join().unwrap(), 7);
+    pool.shutdown();
+}
+
+#[test]
+fn final_metrics_summarize_the_whole_run() {
+    let (tx, rx) = std::sync::mpsc::channel();
+
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .launch_on_build(true)
+        .final_metrics(move |stats| tx.send(stats).unwrap())
+        .build()
+        .unwrap();
+
+    let handles = (0..10)
+        .map(|i| pool.spawn(move || assert!(i % 5 != 0, "task {i} failed")))
+        .collect::<Vec<_>>();
+
+    let panicked = handles.into_iter().map(JoinHandle::join).filter(Result::is_err).count();
+    assert_eq!(panicked, 2);
+    assert!(rx.try_recv().is_err());
+
+    pool.shutdown();
+
+    let stats = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(stats.tasks_completed, 8);
+    assert_eq!(stats.tasks_panicked, 2);
+    assert_eq!(stats.threads_spawned, 2);
+}
+
+#[test]
+fn bursts_grow_the_pool_past_its_maximum_temporarily() {
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .timeout(Duration::from_millis(50))
+        .build()
+        .unwrap();
+
+    let peak = pool.with_burst(2, || {
+        let barrier = Arc::new(std::sync::Barrier::new(3));
+        let handles = (0..3)
+            .map(|_| {
+                let barrier = Arc::clone(&barrier);
+                pool.spawn(move || { barrier.wait(); })
+            })
+            .collect::<Vec<_>>();
+
+        // the tasks only finish if they run on three threads at once
+        handles.into_iter().for_each(|handle| handle.join().unwrap());
+        pool.inner.live_threads()
+    });
+
+    assert_eq!(peak, 3);
+    assert!(!pool.can_grow());
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while pool.inner.live_threads() > 1 {
+        assert!(Instant::now() < deadline, "the burst threads never exited");
+        sleep(Duration::from_millis(10));
+    }
+
+    pool.shutdown();
+}
+
+#[test]
+fn worker_locals_have_slots_for_burst_workers() {
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .build()
+        .unwrap();
+
+    let local = pool.worker_local(|| AtomicUsize::new(0));
+
+    let values = pool.with_burst(3, || {
+        let barrier = Arc::new(std::sync::Barrier::new(4));
+        let handles = (0..4)
+            .map(|_| {
+                let barrier = Arc::clone(&barrier);
+                let local = local.clone();
+                pool.spawn(move || {
+                    // every task runs on its own worker, some with ids past the maximum
+                    barrier.wait();
+                    local.get().fetch_add(1, Ordering::SeqCst)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles.into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    assert_eq!(values, [0; 4]);
+    pool.shutdown();
+}
+
+#[test]
+fn shutdown_waits_for_tasks_detached_to_be_awaited() {
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .launch_on_build(true)
+        .build()
+        .unwrap();
+
+    let finished = Arc::new(AtomicBool::new(false));
+    let blocker = pool.spawn(|| sleep(Duration::from_millis(50)));
+
+    // queued behind the blocker, so a plain shutdown would never run it
+    let task = {
+        let finished = Arc::clone(&finished);
+        pool.spawn(move || {
+            sleep(Duration::from_millis(100));
+            finished.store(true, Ordering::SeqCst);
+        })
+    };
+
+    task.detach_and_wait_on_shutdown();
+    blocker.detach();
+    pool.shutdown();
+
+    assert!(finished.load(Ordering::SeqCst));
+}
+
+#[test]
+fn async_shutdowns_wait_for_awaited_tasks_in_the_waiter() {
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .launch_on_build(true)
+        .build()
+        .unwrap();
+
+    let (tx, rx) = std::sync::mpsc::channel::<()>();
+    pool.spawn(move || rx.recv().unwrap()).detach_and_wait_on_shutdown();
+
+    // the shutdown only starts, the awaited task is still waiting on the channel
+    let waiter = pool.shutdown_async();
+    assert!(!waiter.wait_timeout(Duration::from_millis(50)));
+
+    tx.send(()).unwrap();
+    assert!(waiter.wait_timeout(Duration::from_secs(5)));
+}
+
+#[test]
+fn paused_pools_shut_down_without_waiting_for_tasks_that_cannot_start() {
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .launch_on_build(true)
+        .build()
+        .unwrap();
+
+    pool.pause();
+    let task = pool.spawn(|| ());
+    let queued = task.abort_handle();
+    task.detach_and_wait_on_shutdown();
+
+    pool.shutdown();
+    assert!(queued.is_finished());
+}
+
+#[test]
+fn hard_timeouts_abort_tasks_running_past_their_limit() {
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .launch_on_build(true)
+        .build()
+        .unwrap();
+
+    let iterations = Arc::new(AtomicUsize::new(0));
+    let started = Instant::now();
+
+    let handle = {
+        let iterations = Arc::clone(&iterations);
+        pool.spawn_hard_timeout(Duration::from_millis(50), move || {
+            while !crate::is_aborted() && iterations.load(Ordering::SeqCst) < 500 {
+                iterations.fetch_add(1, Ordering::SeqCst);
+                sleep(Duration::from_millis(10));
+            }
+        })
+    };
+
+    assert!(handle.join().is_err_and(|e| e.is_timed_out()));
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    // the worker is free again once the task bails
+    pool.spawn(|| ()).join().unwrap();
+    assert!(iterations.load(Ordering::SeqCst) < 500);
+
+    assert_eq!(pool.spawn_hard_timeout(Duration::from_secs(5), || 3).join().unwrap(), 3);
+    assert!(!crate::is_aborted());
+
+    pool.shutdown();
+}
+
+#[test]
+fn timed_out_tasks_are_finished_for_their_handles() {
+    let pool = create_pool(1, true);
+    let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+
+    // the task ignores the abort, but its handle is done with it anyway
+    let handle = pool.spawn_hard_timeout(Duration::from_millis(20), move || { let _ = release_rx.recv(); });
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !handle.is_finished() {
+        assert!(Instant::now() < deadline, "the task never timed out");
+        sleep(Duration::from_millis(5));
+    }
+
+    assert!(handle.join().is_err_and(|e| e.is_timed_out()));
+    drop(release_tx);
+    pool.shutdown();
+}
+
+#[test]
+fn result_channels_throttle_producers_to_the_consumer() {
+    let pool = Planetary::builder()
+        .max_threads(4)
+        .launch_on_build(true)
+        .build()
+        .unwrap();
+
+    let channel = ResultChannel::new(2);
+    let produced = Arc::new(AtomicUsize::new(0));
+
+    let handles = (0..40)
+        .map(|i| {
+            let produced = Arc::clone(&produced);
+            pool.spawn_to_result_channel(&channel, move || {
+                produced.fetch_add(1, Ordering::SeqCst);
+                i
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // every worker ends up blocked on the full channel, holding a single output
+    sleep(Duration::from_millis(100));
+    assert!(produced.load(Ordering::SeqCst) <= 6);
+
+    let mut received = Vec::new();
+
+    while received.len() < 40 {
+        received.push(channel.recv_timeout(Duration::from_secs(5)).unwrap());
+        assert!(produced.load(Ordering::SeqCst) <= received.len() + 6);
+        sleep(Duration::from_millis(1));
+    }
+
+    received.sort_unstable();
+    assert_eq!(received, (0..40).collect::<Vec<_>>());
+    handles.into_iter().for_each(|handle| handle.join().unwrap());
+
+    // once the consumer is gone the outputs are dropped instead of blocking the workers
+    let handles = (0..3).map(|i| pool.spawn_to_result_channel(&channel, move || i)).collect::<Vec<_>>();
+    sleep(Duration::from_millis(50));
+    assert_eq!(channel.len(), 2);
+    assert_eq!(handles.iter().filter(|handle| handle.is_finished()).count(), 2);
+
+    drop(channel);
+    handles.into_iter().for_each(|handle| handle.join().unwrap());
+
+    pool.shutdown();
+}
+
+#[test]
+fn on_worker_reads_back_per_worker_state() {
+    let pool = Planetary::builder()
+        .max_threads(3)
+        .launch_on_build(true)
+        .build()
+        .unwrap();
+
+    let value = pool.worker_local(|| Cell::new(0usize));
+    let ids = pool.worker_ids();
+
+    for &id in &ids {
+        let set = pool.on_worker(id, || value.get().set(id * 10));
+        assert_eq!(set, Some(()));
+    }
+
+    // borrows from the caller are fine, as it blocks until the worker is done
+    let seen = Mutex::new(Vec::new());
+    for &id in &ids {
+        let read = pool.on_worker(id, || {
+            seen.lock().unwrap().push(id);
+            value.get().get()
+        });
+        assert_eq!(read, Some(id * 10));
+    }
+    assert_eq!(seen.into_inner().unwrap(), ids);
+
+    assert_eq!(pool.on_worker(usize::MAX, || ()), None);
+
+    // from the target worker itself it runs inline instead of waiting for itself
+    let first = ids[0];
+    let nested = pool.spawn_on(first, {
+        let pool = pool.clone();
+        let value = value.clone();
+        move || pool.on_worker(first, || value.get().get())
+    });
+    assert_eq!(nested.join().unwrap(), Some(first * 10));
+
+    pool.shutdown();
+}
+
+fn peak_local_queue(order: ChildSpawnOrder) -> usize {
+    const DEPTH: u32 = 10;
+
+    fn fan_out(depth: u32, peak: Arc<AtomicUsize>, leaves: Arc<AtomicUsize>) {
+        let queued = crate::worker::try_get_worker().unwrap().queue.len();
+        peak.fetch_max(queued, Ordering::SeqCst);
+
+        if depth == 0 {
+            leaves.fetch_add(1, Ordering::SeqCst);
+            return;
+        }
+
+        for _ in 0..2 {
+            let (peak, leaves) = (Arc::clone(&peak), Arc::clone(&leaves));
+            crate::spawn(move || fan_out(depth - 1, peak, leaves)).detach();
+        }
+    }
+
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .launch_on_build(true)
+        .child_spawn_order(order)
+        .build()
+        .unwrap();
+
+    let peak = Arc::new(AtomicUsize::new(0));
+    let leaves = Arc::new(AtomicUsize::new(0));
+
+    {
+        let (peak, leaves) = (Arc::clone(&peak), Arc::clone(&leaves));
+        pool.spawn(move || fan_out(DEPTH, peak, leaves)).join().unwrap();
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while leaves.load(Ordering::SeqCst) < 1 << DEPTH {
+        assert!(Instant::now() < deadline, "the fan-out never completed");
+        sleep(Duration::from_millis(5));
+    }
+
+    pool.shutdown();
+    peak.load(Ordering::SeqCst)
+}
+
+#[test]
+fn depth_first_child_spawns_keep_the_local_queue_short() {
+    let depth = peak_local_queue(ChildSpawnOrder::Depth);
+    let breadth = peak_local_queue(ChildSpawnOrder::Breadth);
+
+    // breadth-first holds a whole level of the tree at once, the last one has 1024 tasks
+    assert!(breadth >= 512, "breadth-first peak was only {breadth}");
+    assert!(depth <= 64, "depth-first peak was {depth}");
+}
+
+#[test]
+fn aborting_a_chain_aborts_every_task_upstream() {
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .launch_on_build(true)
+        .build()
+        .unwrap();
+
+    let continued = Arc::new(AtomicUsize::new(0));
+    let (started_tx, started_rx) = std::sync::mpsc::channel();
+
+    let first = pool.spawn(move || {
+        started_tx.send(()).unwrap();
+        let started = Instant::now();
+
+        while !crate::is_aborted() {
+            assert!(started.elapsed() < Duration::from_secs(5), "the abort never reached the first task");
+            sleep(Duration::from_millis(1));
+        }
+
+        1
+    });
+    let first_abort = first.abort_handle();
+
+    let second = first.then({
+        let continued = Arc::clone(&continued);
+        move |x| { continued.fetch_add(1, Ordering::SeqCst); x + 1 }
+    });
+    let second_abort = second.abort_handle();
+
+    let third = second.then({
+        let continued = Arc::clone(&continued);
+        move |x| { continued.fetch_add(1, Ordering::SeqCst); x * 2 }
+    });
+
+    started_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    third.abort();
+
+    assert!(third.join().is_err_and(|e| e.is_aborted()));
+    assert!(second_abort.is_aborted());
+    assert!(first_abort.is_aborted());
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !first_abort.is_finished() {
+        assert!(Instant::now() < deadline);
+        sleep(Duration::from_millis(1));
+    }
+
+    // a panic upstream short-circuits the rest of the chain
+    let failed = pool.spawn(|| -> i32 { panic!("upstream failed") })
+        .then(|x| x + 1)
+        .then(|x| x * 2);
+    assert!(failed.join().is_err_and(|e| e.is_aborted()));
+
+    // and a chain left alone runs to the end
+    assert_eq!(pool.spawn(|| 1).then(|x| x + 1).then(|x| x * 2).join().unwrap(), 4);
+
+    assert_eq!(continued.load(Ordering::SeqCst), 0);
+    pool.shutdown();
+}
+
+#[test]
+fn aborting_long_chains_does_not_overflow_the_stack() {
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .build()
+        .unwrap();
+
+    let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+    let first = pool.spawn(move || { let _ = release_rx.recv(); 0 });
+    let first_abort = first.abort_handle();
+
+    let last = (0..100_000).fold(first, |handle, _| handle.then(|x| x + 1));
+    last.abort();
+
+    assert!(first_abort.is_aborted());
+    drop(release_tx);
+    assert!(last.join().is_err_and(|e| e.is_aborted()));
+    pool.shutdown();
+}
+
+#[test]
+fn continuations_run_on_the_threadpool_of_the_task() {
+    let pool = Planetary::builder()
+        .max_threads(1)
+        .build()
+        .unwrap();
+
+    let handle = pool.spawn(|| 1);
+    // no threadpool context on this thread, so the one of the task is used
+    let chained = std::thread::spawn(move || handle.then(|x| x + 1).join().unwrap());
+
+    assert_eq!(chained.join().unwrap(), 2);
+    pool.shutdown();
+}
+
+#[test]
+fn idle_exits_are_counted_as_reaped_threads() {
+    let pool = Planetary::builder()
+        .max_threads(2)
+        .timeout(Duration::from_millis(20))
+        .build()
+        .unwrap();
+
+    for _ in 0..5 {
+        let handles = (0..4)
+            .map(|_| pool.spawn(|| sleep(Duration::from_millis(2))))
+            .collect::<Vec<_>>();
+        handles.into_iter().for_each(|handle| handle.join().unwrap());
+
+        // the gap between bursts outlasts the timeout, so every worker exits
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.inner.live_threads() > 0 {
+            assert!(Instant::now() < deadline, "the workers never timed out");
+            sleep(Duration::from_millis(5));
+        }
+    }
+
+    let stats = pool.stats();
+    assert!(stats.threads_spawned >= 5, "{stats:?}");
+    assert_eq!(stats.threads_reaped, stats.threads_spawned);
+
+    pool.shutdown();
+}
+
+#[test]
+fn worker_local_initializers_cannot_reenter() {
+    let pool = create_pool(1, true);
+    let local = Arc::new(std::sync::OnceLock::<crate::local::WorkerLocal<u32>>::new());
+
+    let value = {
+        let local = Arc::clone(&local);
+        pool.worker_local(move || *local.get().unwrap().get() + 1)
+    };
+    let _ = local.set(value.clone());
+
+    let reentrant = pool.spawn(move || *value.get());
+    assert!(reentrant.join().is_err_and(|e| e.is_panic()));
+
+    pool.shutdown();
+}
@@ -0,0 +1,135 @@
+use std::{cmp::Ordering, collections::BinaryHeap, sync::{Arc, Condvar, Mutex}, time::Instant};
+
+/// Action to be run by the timer thread, returning when to run it again, if ever.
+type Action = Box<dyn FnMut() -> Option<Instant> + Send + 'static>;
+
+/// Runs actions at given instants on a dedicated thread, which exits once the timer
+/// is dropped, discarding the actions that didn't run yet.
+pub struct Timer {
+    shared: Arc<Shared>
+}
+
+struct Shared {
+    state: Mutex<State>,
+    condvar: Condvar,
+}
+
+struct State {
+    entries: BinaryHeap<Entry>,
+    /// Increasing counter, so actions scheduled for the same instant run in order
+    next_seq: u64,
+    closed: bool,
+}
+
+struct Entry {
+    at: Instant,
+    seq: u64,
+    action: Action,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                entries: BinaryHeap::new(),
+                next_seq: 0,
+                closed: false,
+            }),
+            condvar: Condvar::new(),
+        });
+
+        let thread_shared = Arc::clone(&shared);
+        std::thread::Builder::new()
+            .name("planetary-timer".to_string())
+            .spawn(move || run_timer(thread_shared))
+            .unwrap_or_else(|_| panic!("Failed to spawn timer thread"));
+
+        Self { shared }
+    }
+
+    /// Schedules `action` to run at `at`, and again at the instant it returns, if any.
+    pub fn schedule(&self, at: Instant, action: impl FnMut() -> Option<Instant> + Send + 'static) {
+        let mut state = self.shared.lock();
+        state.push(at, Box::new(action));
+        drop(state);
+
+        self.shared.condvar.notify_one();
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.closed = true;
+        state.entries.clear();
+        drop(state);
+
+        self.shared.condvar.notify_one();
+    }
+}
+
+impl State {
+    fn push(&mut self, at: Instant, action: Action) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.entries.push(Entry { at, seq, action });
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|s| s.into_inner())
+    }
+}
+
+fn run_timer(shared: Arc<Shared>) {
+    let mut state = shared.lock();
+
+    while !state.closed {
+        let now = Instant::now();
+
+        match state.entries.peek() {
+            Some(entry) if entry.at <= now => {
+                let mut entry = state.entries.pop().unwrap();
+                drop(state);
+                let next = (entry.action)();
+                state = shared.lock();
+
+                if let Some(at) = next {
+                    state.push(at, entry.action);
+                }
+            },
+            Some(entry) => {
+                let timeout = entry.at - now;
+                state = shared.condvar.wait_timeout(state, timeout)
+                    .unwrap_or_else(|s| s.into_inner())
+                    .0;
+            },
+            None => {
+                state = shared.condvar.wait(state)
+                    .unwrap_or_else(|s| s.into_inner());
+            }
+        }
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // reversed, so the binary heap pops the earliest entry first
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
@@ -0,0 +1,60 @@
+//! NUMA topology queries used to prefer stealing from workers on the same node,
+//! read from the kernel's sysfs, so only Linux is supported.
+
+use std::{collections::HashMap, fs, sync::OnceLock};
+
+/// Maps each cpu to its NUMA node, `None` if the topology couldn't be read.
+fn cpu_nodes() -> Option<&'static HashMap<usize, usize>> {
+    static NODES: OnceLock<Option<HashMap<usize, usize>>> = OnceLock::new();
+
+    NODES.get_or_init(|| {
+        let mut nodes = HashMap::new();
+
+        for entry in fs::read_dir("/sys/devices/system/node").ok()?.flatten() {
+            let name = entry.file_name();
+            let Some(node) = name.to_str()
+                .and_then(|name| name.strip_prefix("node"))
+                .and_then(|id| id.parse().ok())
+            else {
+                continue;
+            };
+
+            let cpus = fs::read_to_string(entry.path().join("cpulist")).ok()?;
+            nodes.extend(parse_cpu_list(&cpus)?.into_iter().map(|cpu| (cpu, node)));
+        }
+
+        (!nodes.is_empty()).then_some(nodes)
+    }).as_ref()
+}
+
+/// Parses a cpu list in the kernel format, like `0-3,8,10-11`.
+fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((start, end)) => cpus.extend(start.parse::<usize>().ok()?..=end.parse().ok()?),
+            None => cpus.push(range.parse().ok()?),
+        }
+    }
+
+    Some(cpus)
+}
+
+/// Cpu the calling thread last ran on.
+fn current_cpu() -> Option<usize> {
+    let stat = fs::read_to_string("/proc/thread-self/stat").ok()?;
+    // the command name may contain spaces, the fields after it don't, and
+    // the processor is the 39th field, the 37th after the command name
+    stat.rsplit_once(')')?.1
+        .split_whitespace()
+        .nth(36)?
+        .parse()
+        .ok()
+}
+
+/// NUMA node of the cpu the calling thread is running on, `None` if the topology
+/// is unavailable. Workers aren't pinned, so this is where the worker started running.
+pub fn current_node() -> Option<usize> {
+    cpu_nodes()?.get(&current_cpu()?).copied()
+}
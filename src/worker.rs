@@ -1,39 +1,106 @@
-use std::cell::UnsafeCell;
+use std::cell::{Cell, RefCell, UnsafeCell};
 
-use crossbeam_deque::Worker;
+use std::{sync::{atomic::{AtomicU64, Ordering}, Arc, OnceLock}, thread::Thread, time::{Duration, Instant}};
 
-use crate::{core::Core, defer, hooks::Hooks, macros::tracing_feat, task::TypeErasedTask};
+use crossbeam_deque::{Injector, Worker};
+
+use crate::{builder::QueueOrder, core::{self, Core, CoreInner, StealScratch}, defer, macros::tracing_feat, task::{Header, TypeErasedTask}};
 
 thread_local! {
-    static WORKER: UnsafeCell<Option<*const WorkerCore>> = UnsafeCell::new(None);
+    static WORKER: UnsafeCell<Option<*const WorkerCore>> = const { UnsafeCell::new(None) };
+    /// Threadpool of the worker running its `on_stop` hook on the current thread, null if none
+    static STOPPING: Cell<*const CoreInner> = const { Cell::new(std::ptr::null()) };
 }
 
+/// Id of threads temporarily acting as workers, which never matches a real worker.
+const PARTICIPANT_ID: usize = usize::MAX;
+
+/// Value of [`WorkerCore::waiting_on`] while the worker isn't blocked joining a task.
+pub(crate) const NOT_WAITING: u64 = u64::MAX;
+
+/// Number of tasks a worker runs in a row before checking the stop flag again, it's
+/// always checked once the worker runs out of tasks.
+const STOP_CHECK_INTERVAL: u32 = 64;
+
+/// How long a worker helping out while it waits stays parked before looking for tasks again.
+const HELP_PARK_TIMEOUT: Duration = Duration::from_millis(1);
+
 pub struct WorkerCore {
     core: Core,
     pub queue: Worker<TypeErasedTask>,
-    id: usize
+    /// Tasks placed directly on this worker
+    pub inbox: Arc<Injector<TypeErasedTask>>,
+    /// NUMA node the worker runs on, set when it starts if the topology is known
+    pub node: Arc<OnceLock<usize>>,
+    /// Nanoseconds spent running tasks
+    pub busy_nanos: Arc<AtomicU64>,
+    /// Whether the worker is a dedicated stealer, which never gets tasks of its own
+    pub stealer: bool,
+    /// Id of the task the worker is blocked joining, [`NOT_WAITING`] if none
+    pub waiting_on: Arc<AtomicU64>,
+    id: usize,
+    /// Number of times the worker looked for a task, used to give turns to the global injector
+    ticks: Cell<u32>,
+    steal_scratch: RefCell<StealScratch>,
 }
 
 impl WorkerCore {
     pub fn new(core: Core, id: usize) -> Self {
-        let queue = Worker::new_fifo();
+        let queue = match core.local_queue_order() {
+            QueueOrder::Fifo => Worker::new_fifo(),
+            QueueOrder::Lifo => Worker::new_lifo(),
+        };
 
         Self {
             core,
             queue,
-            id
+            inbox: Arc::new(Injector::new()),
+            node: Arc::new(OnceLock::new()),
+            busy_nanos: Arc::new(AtomicU64::new(0)),
+            stealer: false,
+            waiting_on: Arc::new(AtomicU64::new(NOT_WAITING)),
+            id,
+            ticks: Cell::new(0),
+            steal_scratch: RefCell::default(),
         }
     }
-}
 
-impl Drop for WorkerCore {
-    fn drop(&mut self) {
-        // Remove the worker from the core
-        self.core.remove_worker(self.id);
+    pub fn core(&self) -> &Core {
+        &self.core
+    }
+
+    fn try_steal(&self) -> Option<TypeErasedTask> {
+        self.core.try_steal(self.id, &mut self.steal_scratch.borrow_mut())
+    }
+
+    /// Reports a failed steal once the worker runs out of tasks, so a worker spinning
+    /// before parking counts as a single failure instead of one per attempt.
+    fn report_steal_failure(&self) {
+        if std::mem::take(&mut self.steal_scratch.borrow_mut().failed) {
+            self.core.report_steal_failure(self.id);
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Whether this is a thread temporarily acting as a worker, whose local queue
+    /// can't be stolen from.
+    pub fn is_participant(&self) -> bool {
+        self.id == PARTICIPANT_ID
     }
 }
 
 pub fn run_worker(core: WorkerCore, initial_task: Option<TypeErasedTask>) {
+    #[cfg(feature = "stack-guard")]
+    crate::stack::mark_top();
+
+    #[cfg(feature = "numa")]
+    if let Some(node) = crate::topology::current_node() {
+        let _ = core.node.set(node);
+    }
+
     tracing_feat!(info!("Worker {} started", core.id));
 
     WORKER.with(|worker| {
@@ -41,6 +108,10 @@ pub fn run_worker(core: WorkerCore, initial_task: Option<TypeErasedTask>) {
             *worker.get() = Some(&core);
         }
     });
+
+    // set once the worker started, and cleared again only if it exits cleanly
+    let crashed = Cell::new(false);
+
     defer!(|| {
         WORKER.with(|worker| {
             unsafe {
@@ -48,60 +119,308 @@ pub fn run_worker(core: WorkerCore, initial_task: Option<TypeErasedTask>) {
             }
         });
 
+        if crashed.get() {
+            // a hook panicked, the worker left no other counters held when unwinding
+            core.core.leave_working();
+        }
+
+        STOPPING.with(|s| s.set(core.core.as_ptr()));
         core.core.hooks.call_on_stop_fn();
+        STOPPING.with(|s| s.set(std::ptr::null()));
         core.core.remove_worker(core.id);
 
         tracing_feat!(info!("Worker {} stopped", core.id));
-    });
 
-    crate::handle::sealed::set_handle(crate::handle::Planetary {
-        inner: core.core.clone()
+        if crashed.get() {
+            core.core.replace_worker();
+        }
     });
 
+    crate::handle::sealed::set_handle(core.core.clone());
+
     core.core.hooks.call_on_start_fn();
+    // a worker failing to start would fail again if replaced, so only replace it after this
+    crashed.set(true);
 
     if let Some(task) = initial_task {
-        execute_task_inner(&core.core.hooks, task);
+        while !core.core.enter_active() {
+            core.core.wait_resumed();
+        }
+
+        defer!(|| core.core.leave_active());
+        execute_task_inner(&core, task);
     }
 
+    work_loop(&core);
+    crashed.set(false);
+}
+
+/// Executes tasks until the worker should exit.
+fn work_loop(core: &WorkerCore) {
+    // iterations left until the stop flag is checked again
+    let mut until_check: u32 = 0;
+
     loop {
-        if core.core.should_stop() {
+        if until_check == 0 && core.core.should_stop() {
             while let Some(task) = core.queue.pop() {
-                task.abort();
+                task.cancel();
             }
 
+            while let Some(task) = core::steal_task(&core.inbox) {
+                task.cancel();
+            }
+
+            core.core.cancel_injected();
+            core.core.leave_working();
+
             return;
         }
 
-        // try execute a task, if we cant sleep for timeout at max and die
-        if !try_execute_task(&core) {
-            if core.core.park() {
+        until_check = until_check.checked_sub(1).unwrap_or(STOP_CHECK_INTERVAL - 1);
+
+        if !core.core.enter_active() {
+            core.core.wait_resumed();
+            continue;
+        }
+
+        let executed = {
+            defer!(|| core.core.leave_active());
+            try_execute_task(core)
+        };
+
+        // the threadpool got downsized, exit without waiting to run out of tasks
+        if executed && core.core.take_downscale_reap() {
+            drain_local_queue(core);
+            core.core.leave_working();
+            return;
+        }
+
+        // try execute a task, if we cant keep looking for a while if configured,
+        // then sleep for timeout at max and die
+        if !executed {
+            until_check = 0;
+        } else {
+            core.steal_scratch.borrow_mut().failed = false;
+        }
+
+        if !executed && !spin_for_task(core) {
+            core.report_steal_failure();
+
+            if core.core.park(&core.inbox, core.stealer) {
                 return; // die, defer macro will do its magic here
             }
+
+            // the autoscaler may have woken us up to reduce the number of workers
+            if core.core.take_reap_request() {
+                drain_local_queue(core);
+                core.core.leave_working();
+                return;
+            }
+        }
+    }
+}
+
+/// Hands the tasks left in the local queue of an exiting worker to the rest.
+fn drain_local_queue(core: &WorkerCore) {
+    while let Some(task) = core.queue.pop() {
+        core.core.requeue(task);
+    }
+}
+
+/// Keeps looking for tasks for up to the configured spin duration, returning whether
+/// the worker should go on looking for tasks instead of parking.
+fn spin_for_task(core: &WorkerCore) -> bool {
+    let spin = core.core.spin_before_park();
+
+    if spin.is_zero() {
+        return false;
+    }
+
+    let deadline = Instant::now() + spin;
+
+    while Instant::now() < deadline {
+        if core.core.should_stop() {
+            return true;
+        }
+
+        // let the main loop wait while paused
+        if !core.core.enter_active() {
+            return true;
+        }
+
+        let executed = {
+            defer!(|| core.core.leave_active());
+            try_execute_task(core)
+        };
+
+        if executed {
+            return true;
+        }
+
+        std::hint::spin_loop();
+    }
+
+    false
+}
+
+/// Makes the current thread execute tasks of the threadpool as if it was a worker,
+/// until `done` returns true.
+pub(crate) fn participate(core: &Core, done: impl Fn() -> bool) {
+    let worker = WorkerCore::new(core.clone(), PARTICIPANT_ID);
+    let previous = crate::handle::sealed::set_handle(core.clone());
+
+    WORKER.with(|w| {
+        unsafe {
+            *w.get() = Some(&worker);
+        }
+    });
+    defer!(|| {
+        WORKER.with(|w| {
+            unsafe {
+                *w.get() = None;
+            }
+        });
+
+        match previous {
+            Some(previous) => drop(crate::handle::sealed::set_handle(previous)),
+            None => crate::handle::sealed::remove_handle()
+        }
+    });
+
+    while !done() {
+        if !core.enter_active() {
+            core.wait_resumed();
+            continue;
+        }
+
+        let executed = try_execute_task(&worker);
+        core.leave_active();
+
+        if !executed {
+            std::thread::yield_now();
         }
     }
 }
 
 /// Tries to execute a task, and returns whether it was executed successfully or not
 fn try_execute_task(core: &WorkerCore) -> bool {
-    if let Some(task) = core.queue.pop() {
-        execute_task_inner(&core.core.hooks, task);
+    if let Some(task) = core::steal_task(&core.inbox) {
+        trace_dequeue(core, &task, "inbox");
+        execute_task_inner(core, task);
+        return true;
+    }
+
+    // dedicated stealers have no tasks of their own, so they look for backlog first
+    if core.stealer && let Some(task) = core.try_steal() {
+        trace_dequeue(core, &task, "steal");
+        execute_task_inner(core, task);
+        return true;
+    }
+
+    if let Some(task) = core.core.take_starved() {
+        trace_dequeue(core, &task, "starved low priority queue");
+        execute_task_inner(core, task);
+        return true;
+    }
+
+    let ticks = core.ticks.get().wrapping_add(1);
+    core.ticks.set(ticks);
+
+    // give the injector a turn every once in a while, so tasks spawned from outside
+    // the threadpool can't be starved by workers feeding their own queues
+    if let Some(task) = core.core.poll_injector_turn(ticks) {
+        trace_dequeue(core, &task, "global injector turn");
+        execute_task_inner(core, task);
+        return true;
+    }
+
+    if !core.stealer && let Some(task) = core.queue.pop() {
+        trace_dequeue(core, &task, "local queue");
+        execute_task_inner(core, task);
+        return true;
+    }
+
+    if let Some((task, slot)) = core.core.take_partitioned() {
+        trace_dequeue(core, &task, "partition");
+        execute_task_inner(core, task);
+        drop(slot);
         return true;
     }
 
     // try stealing a task from another worker
-    if let Some(task) = core.core.try_steal(core.id) {
-        execute_task_inner(&core.core.hooks, task);
+    if !core.stealer && let Some(task) = core.try_steal() {
+        trace_dequeue(core, &task, "steal");
+        execute_task_inner(core, task);
+        return true;
+    }
+
+    if let Some(task) = core.core.take_low_priority() {
+        trace_dequeue(core, &task, "low priority queue");
+        execute_task_inner(core, task);
         true
     } else {
         false
     }
 }
 
-fn execute_task_inner(hooks: &Hooks, task: TypeErasedTask) {
-    hooks.call_before_work_fn();
-    task.run();
-    hooks.call_after_work_fn();
+#[allow(unused)]
+fn trace_dequeue(core: &WorkerCore, task: &TypeErasedTask, source: &str) {
+    tracing_feat!(
+        let header = task.header();
+        let waited = header.enqueued_at.elapsed().unwrap_or_default();
+        trace!(queue_latency_us = waited.as_micros() as u64, "Worker {} took task {} from {source}", core.id, header.id);
+    );
+}
+
+fn execute_task_inner(worker: &WorkerCore, task: TypeErasedTask) {
+    let busy = worker.core.execute_task(task);
+    worker.busy_nanos.fetch_add(crate::core::duration_to_nanos(busy), Ordering::Relaxed);
+}
+
+/// Records that the current worker is about to block joining the task of `awaited`,
+/// until the returned guard is dropped. Panics if deadlock detection is enabled and
+/// every worker is blocked the same way while the task didn't start, since nothing
+/// could run it then.
+pub(crate) fn wait_on(awaited: &Header) -> Option<impl Drop + use<>> {
+    let worker = try_get_worker().filter(|w| !w.is_participant() && w.core.detects_deadlocks())?;
+    let waiting_on = Arc::clone(&worker.waiting_on);
+    waiting_on.store(awaited.id, Ordering::SeqCst);
+
+    if worker.core.is_join_deadlocked(awaited) {
+        waiting_on.store(NOT_WAITING, Ordering::SeqCst);
+        panic!(
+            "deadlock detected: worker {} joined task {}, which didn't start while every worker is blocked joining, \
+            join from workers with JoinHandle::join_helping or JoinHandle::map_join instead",
+            worker.id, awaited.id
+        );
+    }
+
+    Some(crate::defer::Defer::new(move || waiting_on.store(NOT_WAITING, Ordering::SeqCst)))
+}
+
+/// Makes the current worker execute other tasks until `done` returns true, so
+/// waiting on a task from a worker doesn't keep the worker from running it.
+///
+/// Once it runs out of tasks the worker parks, handing its thread to `register` to be
+/// unparked when `done` may have changed. It still wakes up every [`HELP_PARK_TIMEOUT`],
+/// as tasks spawned meanwhile only wake the workers parked in the threadpool.
+pub(crate) fn help_until(worker: &WorkerCore, done: impl Fn() -> bool, register: impl Fn(&Thread)) {
+    let current = std::thread::current();
+
+    while !done() {
+        if try_execute_task(worker) {
+            continue;
+        }
+
+        register(&current);
+
+        // the wait may have ended before we registered ourselves, in which
+        // case nobody would unpark us
+        if !done() {
+            let _suspended = core::suspend_task_permits();
+            std::thread::park_timeout(HELP_PARK_TIMEOUT);
+        }
+    }
 }
 
 /// Yields execution to the current worker for a single task,
@@ -119,6 +438,11 @@ pub fn yield_now() {
     try_execute_task(core);
 }
 
+/// Whether the current thread is a worker of the given threadpool running its `on_stop` hook.
+pub(crate) fn is_stopping(core: &Core) -> bool {
+    STOPPING.with(|s| s.get() == core.as_ptr())
+}
+
 pub(crate) fn try_get_worker() -> Option<&'static WorkerCore> {
     unsafe {
         let ptr = WORKER.with(|w| {